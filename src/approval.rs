@@ -0,0 +1,50 @@
+use serde_json::{json, Value};
+
+use crate::redact;
+
+// Asking the user to confirm an operation through MCP elicitation
+
+/// An operation that a session's policy holds for confirmation
+pub struct ApprovalRequest<'a> {
+    pub session_id: &'a str,
+    pub host: &'a str,
+    pub tool_name: &'a str,
+    /// The command, or the path being written
+    pub target: &'a str,
+    pub use_sudo: bool,
+    pub reason: &'a str,
+}
+
+impl ApprovalRequest<'_> {
+    /// Parameters of the `elicitation/create` request shown to the user
+    pub fn elicitation_params(&self) -> Value {
+        json!({
+            "message": format!(
+                "Approval required: {} on host {} (session '{}')\n\n{}\n\nsudo: {}\nReason: {}",
+                self.tool_name, self.host, self.session_id, redact::text(self.target),
+                if self.use_sudo { "yes" } else { "no" }, self.reason),
+            "requestedSchema": {
+                "type": "object",
+                "properties": {
+                    "approve": {
+                        "type": "boolean",
+                        "title": "Run this operation",
+                        "description": "Check to let the operation proceed"
+                    }
+                },
+                "required": ["approve"]
+            }
+        })
+    }
+
+    /// One line for the log
+    pub fn describe(&self) -> String {
+        format!("{} on session '{}' ({}, sudo: {}): {}",
+            self.tool_name, self.session_id, self.reason, self.use_sudo, redact::text(self.target))
+    }
+}
+
+/// Only an accepted elicitation with `approve: true` counts as approval
+pub fn is_approved(result: &Value) -> bool {
+    result["action"] == "accept" && result["content"]["approve"] == true
+}
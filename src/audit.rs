@@ -0,0 +1,282 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Map, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::files::sha256_hex;
+use crate::redact;
+use crate::time::{format_rfc3339_millis, now_millis};
+
+// Append-only JSONL audit log with hash chaining
+//
+// Each entry carries the hash of the previous one in `prev_hash`, and its own
+// `hash` is the sha256 of the entry serialized without that field (object keys
+// sorted). Editing, removing or reordering entries breaks the chain. The hash is
+// not keyed: whoever can write the file can also recompute the chain after the
+// edited entry, and entries cut off the end leave a valid chain. Comparing the
+// latest hash with a copy kept elsewhere catches both.
+
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Argument strings longer than this are recorded as a hash and length only
+const MAX_ARGUMENT_LEN: usize = 1024;
+
+pub struct AuditLog {
+    path: PathBuf,
+    state: Mutex<ChainState>,
+}
+
+struct ChainState {
+    file: File,
+    seq: u64,
+    last_hash: String,
+}
+
+impl AuditLog {
+    /// Open for appending, continuing the chain of an existing log
+    pub fn open(path: &Path) -> Result<Self> {
+        let (seq, last_hash) = match File::open(path) {
+            Ok(mut file) => {
+                let last = last_line(&mut file)
+                    .context(format!("Failed to read audit log {}", path.display()))?;
+                match last {
+                    Some(line) => {
+                        let entry: Value = serde_json::from_str(&line)
+                            .context(format!("Audit log {} ends with a corrupt entry; run verify-audit", path.display()))?;
+                        match (entry["seq"].as_u64(), entry["hash"].as_str()) {
+                            (Some(seq), Some(hash)) => (seq, hash.to_string()),
+                            _ => bail!("Audit log {} ends with an entry without seq/hash; run verify-audit", path.display()),
+                        }
+                    }
+                    None => (0, GENESIS_HASH.to_string()),
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (0, GENESIS_HASH.to_string()),
+            Err(e) => return Err(anyhow::Error::from(e).context(format!("Failed to open audit log {}", path.display()))),
+        };
+
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .context(format!("Failed to open audit log {} for appending", path.display()))?;
+
+        eprintln!("[AUDIT] Logging to {} ({} existing entries, last hash {})", path.display(), seq, last_hash);
+        Ok(AuditLog {
+            path: path.to_path_buf(),
+            state: Mutex::new(ChainState { file, seq, last_hash }),
+        })
+    }
+
+    /// Append an event; `fields` are merged into the entry
+    pub fn record(&self, event: &str, fields: Map<String, Value>) {
+        let mut state = self.state.lock().unwrap();
+
+        let mut entry = fields;
+        entry.values_mut().for_each(|value| {
+            redact::value(value);
+        });
+        entry.insert("seq".to_string(), json!(state.seq + 1));
+        entry.insert("timestamp".to_string(), json!(format_rfc3339_millis(now_millis())));
+        entry.insert("event".to_string(), json!(event));
+        entry.insert("prev_hash".to_string(), json!(state.last_hash));
+        let hash = entry_hash(&entry);
+        entry.insert("hash".to_string(), json!(hash));
+
+        let line = Value::Object(entry).to_string();
+        let written = writeln!(state.file, "{}", line).and_then(|()| state.file.sync_data());
+        match written {
+            Ok(()) => {
+                state.seq += 1;
+                state.last_hash = hash;
+            }
+            Err(e) => eprintln!("[AUDIT] Failed to write to {}: {} (entry: {})", self.path.display(), e, line),
+        }
+    }
+}
+
+/// The last non-blank line of `file`, read from the end so long logs are not read whole
+fn last_line(file: &mut File) -> std::io::Result<Option<String>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut chunk = 4096;
+    loop {
+        let start = len.saturating_sub(chunk);
+        file.seek(SeekFrom::Start(start))?;
+        let mut tail = Vec::new();
+        file.take(len - start).read_to_end(&mut tail)?;
+
+        let text = String::from_utf8_lossy(&tail);
+        let text = text.trim_end();
+        match text.rfind('\n') {
+            Some(newline) if !text[newline..].trim().is_empty() => return Ok(Some(text[newline + 1..].to_string())),
+            _ if start == 0 => return Ok(Some(text.trim_start().to_string()).filter(|line| !line.is_empty())),
+            _ => chunk *= 2,
+        }
+    }
+}
+
+/// The hash of an entry, computed over everything except its `hash` field
+fn entry_hash(entry: &Map<String, Value>) -> String {
+    let mut entry = entry.clone();
+    entry.remove("hash");
+    sha256_hex(Value::Object(entry).to_string().as_bytes())
+}
+
+/// Tool arguments as recorded: long strings (file contents, diffs) become their hash and length
+pub fn summarize_arguments(arguments: &Value) -> Value {
+    match arguments {
+        Value::String(s) if s.len() > MAX_ARGUMENT_LEN => json!({ "sha256": sha256_hex(s.as_bytes()), "bytes": s.len() }),
+        Value::Array(items) => Value::Array(items.iter().map(summarize_arguments).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), summarize_arguments(v))).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Check every entry's hash and link; returns the number of entries and the hash of the last one
+pub fn verify(path: &Path) -> Result<(u64, String)> {
+    let file = File::open(path).context(format!("Failed to open audit log {}", path.display()))?;
+
+    let mut expected_prev = GENESIS_HASH.to_string();
+    let mut count = 0;
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.context(format!("Failed to read line {}", line_number))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry: Map<String, Value> = serde_json::from_str(&line)
+            .context(format!("Line {}: not a JSON object", line_number))?;
+        let hash = entry.get("hash").and_then(Value::as_str)
+            .context(format!("Line {}: missing hash", line_number))?;
+        if entry.get("seq").and_then(Value::as_u64) != Some(count + 1) {
+            bail!("Line {}: expected seq {}, found {}", line_number, count + 1, entry.get("seq").unwrap_or(&Value::Null));
+        }
+        if entry.get("prev_hash").and_then(Value::as_str) != Some(expected_prev.as_str()) {
+            bail!("Line {}: chain broken, prev_hash does not match the previous entry", line_number);
+        }
+        if entry_hash(&entry) != hash {
+            bail!("Line {}: entry was modified, hash does not match its contents", line_number);
+        }
+
+        expected_prev = hash.to_string();
+        count += 1;
+    }
+    Ok((count, expected_prev))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_log(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mcp-ssh-audit-{}-{}.jsonl", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn write_log(name: &str, events: usize) -> PathBuf {
+        let path = temp_log(name);
+        let log = AuditLog::open(&path).unwrap();
+        for i in 0..events {
+            let mut fields = Map::new();
+            fields.insert("command".to_string(), json!(format!("echo {}", i)));
+            log.record("tool_call", fields);
+        }
+        path
+    }
+
+    fn edit_lines(path: &Path, edit: impl FnOnce(&mut Vec<String>)) {
+        let mut lines: Vec<String> = fs::read_to_string(path).unwrap().lines().map(str::to_string).collect();
+        edit(&mut lines);
+        fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[test]
+    fn verifies_an_intact_chain_across_reopens() {
+        let path = write_log("intact", 3);
+        let log = AuditLog::open(&path).unwrap();
+        log.record("tool_call", Map::new());
+        let (count, last_hash) = verify(&path).unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(last_hash, log.state.lock().unwrap().last_hash);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_only_the_last_line() {
+        let path = temp_log("last-line");
+        let long = format!("{{\"x\":\"{}\"}}", "y".repeat(10_000));
+        fs::write(&path, format!("first\n{}\n\n  \n", long)).unwrap();
+        assert_eq!(last_line(&mut File::open(&path).unwrap()).unwrap(), Some(long.clone()));
+        fs::write(&path, &long).unwrap();
+        assert_eq!(last_line(&mut File::open(&path).unwrap()).unwrap(), Some(long));
+        fs::write(&path, "\n \n").unwrap();
+        assert_eq!(last_line(&mut File::open(&path).unwrap()).unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncation_at_the_end_leaves_a_valid_chain() {
+        // Only a copy of the last hash kept elsewhere detects this
+        let path = write_log("truncated", 3);
+        let (_, last_hash) = verify(&path).unwrap();
+        edit_lines(&path, |lines| {
+            lines.pop();
+        });
+        let (count, truncated_hash) = verify(&path).unwrap();
+        assert_eq!(count, 2);
+        assert_ne!(truncated_hash, last_hash);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn detects_modified_entries() {
+        let path = write_log("modified", 3);
+        edit_lines(&path, |lines| lines[1] = lines[1].replace("echo 1", "echo 9"));
+        let error = verify(&path).unwrap_err().to_string();
+        assert!(error.starts_with("Line 2: entry was modified"), "{}", error);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn detects_removed_and_reordered_entries() {
+        let path = write_log("removed", 3);
+        edit_lines(&path, |lines| {
+            lines.remove(1);
+        });
+        assert!(verify(&path).unwrap_err().to_string().starts_with("Line 2: expected seq 2"));
+        fs::remove_file(&path).unwrap();
+
+        let path = write_log("reordered", 3);
+        edit_lines(&path, |lines| lines.swap(0, 1));
+        assert!(verify(&path).unwrap_err().to_string().starts_with("Line 1: expected seq 1"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn detects_a_rewritten_chain_link() {
+        // Renumbering alone does not help: the link to the previous entry breaks
+        let path = write_log("relinked", 3);
+        edit_lines(&path, |lines| {
+            lines.remove(1);
+            let mut entry: Map<String, Value> = serde_json::from_str(&lines[1]).unwrap();
+            entry.insert("seq".to_string(), json!(2));
+            let hash = entry_hash(&entry);
+            entry.insert("hash".to_string(), json!(hash));
+            lines[1] = Value::Object(entry).to_string();
+        });
+        assert!(verify(&path).unwrap_err().to_string().starts_with("Line 2: chain broken"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn summarizes_long_arguments() {
+        let long = "x".repeat(MAX_ARGUMENT_LEN + 1);
+        let summary = summarize_arguments(&json!({ "content": long, "path": "/etc/hosts", "n": 3 }));
+        assert_eq!(summary["content"]["bytes"], json!(MAX_ARGUMENT_LEN + 1));
+        assert_eq!(summary["content"]["sha256"], json!(sha256_hex(long.as_bytes())));
+        assert_eq!(summary["path"], json!("/etc/hosts"));
+        assert_eq!(summary["n"], json!(3));
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::glob;
+use crate::inventory::{self, Inventory};
+use crate::keepalive::{self, KeepaliveConfig};
+use crate::policy::Policy;
+use crate::redact::RedactionConfig;
+use crate::state;
+
+// Server configuration file (JSON)
+
+const CONFIG_ENV: &str = "MCP_SSH_CONFIG";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Named profiles; sessions pick one explicitly or by host pattern
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Append-only JSONL audit log of remote actions
+    #[serde(default)]
+    pub audit_log: Option<PathBuf>,
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    /// Named host groups; members are inventory host names, hosts or globs over inventory hosts
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<String>>,
+    /// Ansible INI/YAML inventories or ssh client configs to import hosts and groups from
+    #[serde(default)]
+    pub inventory: Vec<PathBuf>,
+    /// Where sessions are saved for `ssh_restore_sessions` (default under $XDG_STATE_HOME)
+    #[serde(default)]
+    pub state_file: Option<PathBuf>,
+    /// Save sessions to `state_file`. Off by default: the file holds connect
+    /// arguments and `env` values in plaintext
+    #[serde(default)]
+    pub save_sessions: bool,
+    /// Reconnect saved sessions when the server starts
+    #[serde(default)]
+    pub restore_sessions: bool,
+    /// ServerAlive options, heartbeat and idle timeout
+    #[serde(default)]
+    pub keepalive: KeepaliveConfig,
+    /// Hosts and groups from `groups` and `inventory`, built on load
+    #[serde(skip)]
+    pub hosts: Inventory,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Host globs that select this profile when `ssh_connect` names none
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[serde(default)]
+    pub policy: Policy,
+}
+
+impl Config {
+    /// Load from an explicit path, `$MCP_SSH_CONFIG`, or the default location if present
+    pub fn load(explicit: Option<&Path>) -> Result<Self> {
+        let path = match explicit {
+            Some(path) => Some(path.to_path_buf()),
+            None => match std::env::var_os(CONFIG_ENV) {
+                Some(path) => Some(PathBuf::from(path)),
+                None => default_path().filter(|path| path.exists()),
+            },
+        };
+
+        let Some(path) = path else {
+            eprintln!("[MAIN] No config file, running without profiles");
+            return Ok(Config::default());
+        };
+
+        let data = std::fs::read_to_string(&path)
+            .context(format!("Failed to read config file {}", path.display()))?;
+        let mut config: Config = serde_json::from_str(&data)
+            .context(format!("Invalid config file {}", path.display()))?;
+        keepalive::validate(&config.keepalive)
+            .context(format!("Invalid config file {}", path.display()))?;
+
+        // Relative inventory and state paths are relative to the config file, `~` is the home directory
+        let base = path.parent().unwrap_or(Path::new("."));
+        let inventory: Vec<PathBuf> = config.inventory.iter()
+            .map(|file| if file.starts_with("~") { file.clone() } else { base.join(file) })
+            .collect();
+        config.state_file = config.state_file.map(|file| if file.starts_with("~") { inventory::expand_home(&file) } else { base.join(file) });
+        config.hosts = Inventory::load(&config.groups, &inventory)?;
+
+        eprintln!("[MAIN] Loaded config from {} ({} profiles, {} groups)",
+            path.display(), config.profiles.len(), config.hosts.groups.len());
+        Ok(config)
+    }
+
+    /// Session state file, if saving sessions is turned on
+    pub fn state_path(&self) -> Option<PathBuf> {
+        if !self.save_sessions {
+            return None;
+        }
+        self.state_file.clone().or_else(state::default_path)
+    }
+
+    /// Pick the requested profile, else the first whose host globs match, else `default`
+    pub fn select_profile(&self, requested: Option<&str>, host: &str) -> Result<Option<(&str, &Profile)>> {
+        if let Some(name) = requested {
+            return match self.profiles.get_key_value(name) {
+                Some((name, profile)) => Ok(Some((name.as_str(), profile))),
+                None => bail!("Unknown profile '{}'. Configured profiles: {}", name,
+                    self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")),
+            };
+        }
+
+        Ok(self.profiles.iter()
+            .find(|(_, profile)| profile.hosts.iter().any(|pattern| glob::glob_match(pattern, host)))
+            .or_else(|| self.profiles.get_key_value("default"))
+            .map(|(name, profile)| (name.as_str(), profile)))
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("mcp-ssh-sessions").join("config.json"))
+}
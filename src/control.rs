@@ -0,0 +1,172 @@
+use anyhow::{bail, Context, Result};
+use std::fs::{self, DirBuilder, File};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::options::ConnectOptions;
+
+// OpenSSH connection multiplexing: one authenticated master connection per session
+
+static NEXT_CONTROL_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+pub struct ControlMaster {
+    host: String,
+    dir: PathBuf,
+    socket: PathBuf,
+    /// The session's connection options, so a client that cannot reach the
+    /// socket and connects on its own still uses the same user, port and key
+    args: Vec<String>,
+    child: Option<Child>,
+}
+
+/// Socket and options of a master connection, for starting `ssh` clients of it
+#[derive(Debug, Clone)]
+pub struct MuxClient {
+    socket: PathBuf,
+    args: Vec<String>,
+}
+
+impl MuxClient {
+    pub fn ssh_command(&self) -> Command {
+        let mut command = Command::new("ssh");
+        command
+            .args(["-o", "ControlMaster=no"])
+            .arg("-S").arg(&self.socket)
+            .args(&self.args);
+        command
+    }
+}
+
+impl ControlMaster {
+    /// Start a master connection with its socket in a private (0700) temp dir
+    pub fn start(host: &str, options: &ConnectOptions) -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!(
+            "mcp-ssh-{}-{}", std::process::id(), NEXT_CONTROL_ID.fetch_add(1, Ordering::SeqCst)));
+        DirBuilder::new()
+            .mode(0o700)
+            .create(&dir)
+            .context(format!("Failed to create control directory {}", dir.display()))?;
+
+        let socket = dir.join("master.sock");
+        let log_path = dir.join("master.log");
+        let log = File::create(&log_path)
+            .context("Failed to create control master log file")?;
+
+        eprintln!("[SSH] Starting control master for {}", host);
+        let child = Command::new("ssh")
+            .args(["-M", "-N", "-o", "ControlPersist=no"])
+            .arg("-S").arg(&socket)
+            .args(options.common_args())
+            .arg(host)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(log)
+            .spawn()
+            .context(format!("Failed to start SSH control master to {}", host))?;
+
+        let mut master = ControlMaster {
+            host: host.to_string(),
+            dir,
+            socket,
+            args: options.common_args(),
+            child: Some(child),
+        };
+
+        // Wait until the master accepts mux clients, or gives up
+        let deadline = Instant::now() + Duration::from_secs(options.connect_timeout() + 5);
+        loop {
+            if master.check() {
+                eprintln!("[SSH] Control master ready for {}", host);
+                return Ok(master);
+            }
+
+            let exited = match master.child.as_mut() {
+                Some(child) => child.try_wait().ok().flatten().is_some(),
+                None => true,
+            };
+            if exited || Instant::now() > deadline {
+                let log = fs::read_to_string(&log_path).unwrap_or_default();
+                master.close();
+                bail!("SSH control master to {} failed to start: {}", host, log.trim());
+            }
+
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Private directory of this connection, removed when it closes
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// `ssh` command preconfigured to reuse the master connection
+    pub fn ssh_command(&self) -> Command {
+        self.client().ssh_command()
+    }
+
+    /// What starting clients of the master needs, to use it without borrowing the session
+    pub fn client(&self) -> MuxClient {
+        MuxClient { socket: self.socket.clone(), args: self.args.clone() }
+    }
+
+    /// `scp` command preconfigured to reuse the master connection
+    pub fn scp_command(&self) -> Command {
+        let mut command = Command::new("scp");
+        command
+            .args(["-o", "ControlMaster=no"])
+            .arg("-o").arg(format!("ControlPath={}", self.socket.display()))
+            .args(&self.args);
+        command
+    }
+
+    /// Whether the master is running and accepts mux clients (`ssh -O check`);
+    /// runs locally, without opening a channel to the host
+    pub fn check(&self) -> bool {
+        self.socket.exists()
+            && self.ssh_command()
+                .args(["-O", "check", &self.host])
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .map(|status| status.success())
+                .unwrap_or(false)
+    }
+
+    /// Stop the master connection and remove the socket directory
+    pub fn close(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            eprintln!("[SSH] Closing control master for {}", self.host);
+            let _ = self.ssh_command()
+                .args(["-O", "exit", &self.host])
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+            if child.try_wait().ok().flatten().is_none() {
+                thread::sleep(Duration::from_millis(100));
+                if child.try_wait().ok().flatten().is_none() {
+                    let _ = child.kill();
+                }
+            }
+            let _ = child.wait();
+        }
+
+        if self.dir.exists() {
+            if let Err(e) = fs::remove_dir_all(&self.dir) {
+                eprintln!("[SSH] Failed to remove control directory {}: {}", self.dir.display(), e);
+            }
+        }
+    }
+}
+
+impl Drop for ControlMaster {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
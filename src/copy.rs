@@ -0,0 +1,348 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::process::{Child, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::shell::quote;
+use crate::sync;
+use crate::SshSession;
+
+// Host-to-host copies streamed through this process over the sessions' master connections
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Called with (bytes transferred, expected total, message)
+pub type ProgressFn<'a> = dyn FnMut(u64, Option<u64>, &str) + 'a;
+
+pub fn tool_definitions() -> Vec<Value> {
+    vec![
+        json!({
+            "name": "ssh_copy_between",
+            "description": "Copy a file or directory from one session's host to another's, streamed through the MCP server (not through the model), with checksum verification",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "source_session_id": { "type": "string", "description": "Session to copy from" },
+                    "source_path": { "type": "string", "description": "File or directory on the source host" },
+                    "dest_session_id": { "type": "string", "description": "Session to copy to" },
+                    "dest_path": { "type": "string", "description": "Destination path. An existing directory receives the source under its own name, like cp -r and scp -r (dest_path/<name>); otherwise the file or directory is created at dest_path" },
+                    "source_sudo": { "type": "boolean", "description": "Read with sudo on the source", "default": false },
+                    "dest_sudo": { "type": "boolean", "description": "Write with sudo on the destination", "default": false },
+                    "verify": { "type": "boolean", "description": "Compare sha256 checksums after copying", "default": true }
+                },
+                "required": ["source_session_id", "source_path", "dest_session_id", "dest_path"]
+            }
+        }),
+    ]
+}
+
+struct Endpoint<'a> {
+    session_id: &'a str,
+    session: &'a SshSession,
+    path: &'a str,
+    use_sudo: bool,
+}
+
+impl Endpoint<'_> {
+    /// Run a short command on the side channel and return its stdout
+    fn output(&self, command: &str) -> Result<Vec<u8>> {
+        let output = self.session.side_command(command, self.use_sudo)
+            .stdin(Stdio::null())
+            .output()
+            .context(format!("Failed to run command on session '{}'", self.session_id))?;
+        if !output.status.success() {
+            bail!("Command failed on session '{}': {}", self.session_id, String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// Handle `ssh_copy_between`
+pub fn copy_between(source: &SshSession, dest: &SshSession, arguments: &Value, progress: &mut ProgressFn) -> Result<Value> {
+    let source = Endpoint {
+        session_id: arguments["source_session_id"].as_str()
+            .context("Missing or invalid source_session_id parameter - must be a string")?,
+        session: source,
+        path: path_arg(arguments, "source_path")?,
+        use_sudo: arguments["source_sudo"].as_bool().unwrap_or(false),
+    };
+    let dest = Endpoint {
+        session_id: arguments["dest_session_id"].as_str()
+            .context("Missing or invalid dest_session_id parameter - must be a string")?,
+        session: dest,
+        path: path_arg(arguments, "dest_path")?,
+        use_sudo: arguments["dest_sudo"].as_bool().unwrap_or(false),
+    };
+    let verify = arguments["verify"].as_bool().unwrap_or(true);
+
+    let src = quote(source.path);
+    let kind = source.output(&format!("if [ -d {p} ]; then echo directory; elif [ -f {p} ]; then echo file; else echo missing; fi", p = src))?;
+    let is_dir = match String::from_utf8_lossy(&kind).trim() {
+        "directory" => true,
+        "file" => false,
+        _ => bail!("Source path does not exist or is not a regular file or directory: {}", source.path),
+    };
+
+    let total = String::from_utf8_lossy(&source.output(&if is_dir {
+        format!("du -sb -- {} | cut -f1", src)
+    } else {
+        format!("stat -L -c %s -- {}", src)
+    })?).trim().parse().ok();
+
+    eprintln!("[MCP] Copying {} {} from session '{}' to {} on session '{}'",
+        if is_dir { "directory" } else { "file" }, source.path, source.session_id, dest.path, dest.session_id);
+
+    let read_command = match is_dir {
+        true => format!("cd -- {} && tar -cf - .", src),
+        false => format!("cat -- {}", src),
+    };
+
+    let started = Instant::now();
+    let (transferred, stream_sha256, write_output) = stream(&source, &read_command, &dest, &write_command(is_dir, source.path, dest.path), total, progress)?;
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let mut verified = None;
+    if verify {
+        progress(transferred, total, "Verifying checksums");
+        if is_dir {
+            let listing = |endpoint: &Endpoint, dir: &str| -> Result<_> {
+                let output = endpoint.output(&format!(
+                    "cd -- {} && find . -type f -printf '%P\\0' | xargs -0 -r sha256sum -z --", quote(dir)))?;
+                Ok(sync::parse_checksums(&output))
+            };
+            // The write command prints the directory it extracted into
+            let target = String::from_utf8_lossy(&write_output);
+            let source_sums = listing(&source, source.path)?;
+            let dest_sums = listing(&dest, target.trim_end_matches('\n'))?;
+            let mismatched: Vec<&String> = source_sums.iter()
+                .filter(|(path, sum)| dest_sums.get(*path) != Some(sum))
+                .map(|(path, _)| path)
+                .collect();
+            if !mismatched.is_empty() {
+                bail!("Checksum verification failed for {} files, e.g. {}", mismatched.len(), mismatched[0]);
+            }
+            verified = Some(json!({ "files": source_sums.len() }));
+        } else {
+            let source_sha256 = String::from_utf8_lossy(&source.output(&format!("sha256sum -- {} | cut -d' ' -f1", src))?)
+                .trim().to_string();
+            let dest_sha256 = String::from_utf8_lossy(&write_output)
+                .split_whitespace().next().unwrap_or_default().to_string();
+            if source_sha256 != stream_sha256 || dest_sha256 != stream_sha256 {
+                bail!("Checksum verification failed: source {}, transferred {}, destination {}",
+                    source_sha256, stream_sha256, dest_sha256);
+            }
+            verified = Some(json!({ "sha256": stream_sha256 }));
+        }
+    }
+
+    progress(transferred, total, "Copy complete");
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": format!("Copied {} {}:{} to {}:{} ({} bytes in {:.1}s{})",
+                if is_dir { "directory" } else { "file" },
+                source.session_id, source.path, dest.session_id, dest.path, transferred, elapsed,
+                if verified.is_some() { ", checksums verified" } else { "" })
+        }],
+        "metadata": {
+            "source_session_id": source.session_id,
+            "source_path": source.path,
+            "dest_session_id": dest.session_id,
+            "dest_path": dest.path,
+            "type": if is_dir { "directory" } else { "file" },
+            "bytes": transferred,
+            "seconds": elapsed,
+            "verified": verified
+        }
+    }))
+}
+
+/// The command writing the copy at `dest_path`. Like cp and scp, an existing directory there
+/// receives the source under its own name. Files are replaced atomically and followed by their
+/// sha256sum; directories are followed by the path they were extracted into
+fn write_command(is_dir: bool, source_path: &str, dest_path: &str) -> String {
+    let name = source_path.trim_end_matches('/').rsplit('/').next().unwrap_or(source_path);
+    let target = format!("t={}; [ -d \"$t\" ] && t=\"$t\"/{}; ", quote(dest_path), quote(name));
+    if is_dir {
+        format!("{}mkdir -p -- \"$t\" && tar -xf - -C \"$t\" && printf '%s\\n' \"$t\"", target)
+    } else {
+        format!("{}tmp=\"$t.mcp-tmp-$$\"; \
+                 cat > \"$tmp\" && mv -f -- \"$tmp\" \"$t\" || {{ rm -f -- \"$tmp\"; exit 1; }}; \
+                 sha256sum -- \"$t\"", target)
+    }
+}
+
+/// Pipe the reader's stdout into the writer's stdin, returning bytes copied,
+/// the sha256 of the stream and the writer's stdout
+fn stream(source: &Endpoint, read_command: &str, dest: &Endpoint, write_command: &str,
+          total: Option<u64>, progress: &mut ProgressFn) -> Result<(u64, String, Vec<u8>)> {
+    let mut reader = source.session.side_command(read_command, source.use_sudo)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(format!("Failed to start reading on session '{}'", source.session_id))?;
+    let mut writer = match dest.session.side_command(write_command, dest.use_sudo)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn() {
+        Ok(writer) => writer,
+        Err(e) => {
+            let _ = reader.kill();
+            let _ = reader.wait();
+            return Err(anyhow::Error::from(e).context(format!("Failed to start writing on session '{}'", dest.session_id)));
+        }
+    };
+
+    let reader_stderr = collect(reader.stderr.take());
+    let writer_stderr = collect(writer.stderr.take());
+    let writer_stdout = collect(writer.stdout.take());
+
+    let mut input = reader.stdout.take().context("Failed to get stdout of reading process")?;
+    let mut output = writer.stdin.take().context("Failed to get stdin of writing process")?;
+
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut hasher = Sha256::new();
+    let mut transferred = 0u64;
+    let mut last_report = Instant::now();
+    progress(0, total, "Copy started");
+
+    let copied: Result<()> = loop {
+        let n = match input.read(&mut buffer) {
+            Ok(0) => break Ok(()),
+            Ok(n) => n,
+            Err(e) => break Err(anyhow::Error::from(e).context("Failed to read from source")),
+        };
+        if let Err(e) = output.write_all(&buffer[..n]) {
+            break Err(anyhow::Error::from(e).context("Failed to write to destination"));
+        }
+        hasher.update(&buffer[..n]);
+        transferred += n as u64;
+        source.session.stats.received(n);
+        dest.session.stats.sent(n);
+
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            progress(transferred, total, "Copying");
+            last_report = Instant::now();
+        }
+    };
+    drop(output);
+
+    if copied.is_err() {
+        let _ = reader.kill();
+    }
+    let read_status = finish(&mut reader, reader_stderr);
+    let write_status = finish(&mut writer, writer_stderr);
+    copied?;
+    read_status.context(format!("Reading {} on session '{}' failed", source.path, source.session_id))?;
+    write_status.context(format!("Writing {} on session '{}' failed", dest.path, dest.session_id))?;
+
+    let stdout = writer_stdout.join().unwrap_or_default();
+    let sha256 = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+    Ok((transferred, sha256, stdout))
+}
+
+pub fn collect<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut data = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut data);
+        }
+        data
+    })
+}
+
+pub fn finish(child: &mut Child, stderr: JoinHandle<Vec<u8>>) -> Result<()> {
+    let status = child.wait()?;
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        bail!("{} ({})", String::from_utf8_lossy(&stderr).trim(), status);
+    }
+    Ok(())
+}
+
+fn path_arg<'a>(arguments: &'a Value, key: &str) -> Result<&'a str> {
+    let path = arguments[key].as_str()
+        .context(format!("Missing or invalid {} parameter - must be a string", key))?;
+    if path.is_empty() || path.contains(['\n', '\0']) {
+        bail!("Invalid {} parameter", key);
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mcp-ssh-copy-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Copy `source` to `dest` on this machine with the read and write commands the tool runs remotely
+    fn copy_locally(is_dir: bool, source: &Path, dest: &Path) -> String {
+        let read = match is_dir {
+            true => format!("cd -- {} && tar -cf - .", quote(&source.to_string_lossy())),
+            false => format!("cat -- {}", quote(&source.to_string_lossy())),
+        };
+        let write = write_command(is_dir, &source.to_string_lossy(), &dest.to_string_lossy());
+        let output = Command::new("bash").arg("-c").arg(format!("({}) | ({})", read, write)).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn directories_go_under_their_name_in_an_existing_directory() {
+        let dir = temp_dir("dir-into");
+        fs::create_dir_all(dir.join("my app/sub")).unwrap();
+        fs::write(dir.join("my app/sub/a.txt"), "a").unwrap();
+        fs::create_dir(dir.join("dest")).unwrap();
+        fs::write(dir.join("dest/keep.txt"), "keep").unwrap();
+
+        let target = copy_locally(true, &dir.join("my app/"), &dir.join("dest"));
+        assert_eq!(target, format!("{}\n", dir.join("dest/my app").display()));
+        assert_eq!(fs::read_to_string(dir.join("dest/my app/sub/a.txt")).unwrap(), "a");
+        assert!(!dir.join("dest/sub").exists());
+        assert!(dir.join("dest/keep.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directories_are_created_at_a_new_path() {
+        let dir = temp_dir("dir-new");
+        fs::create_dir(dir.join("src")).unwrap();
+        fs::write(dir.join("src/a.txt"), "a").unwrap();
+
+        let target = copy_locally(true, &dir.join("src"), &dir.join("new/copy"));
+        assert_eq!(target, format!("{}\n", dir.join("new/copy").display()));
+        assert_eq!(fs::read_to_string(dir.join("new/copy/a.txt")).unwrap(), "a");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_go_into_an_existing_directory_or_replace_the_path() {
+        let dir = temp_dir("file");
+        fs::write(dir.join("a.txt"), "new").unwrap();
+        fs::create_dir(dir.join("dest")).unwrap();
+        fs::write(dir.join("old.txt"), "old").unwrap();
+
+        let output = copy_locally(false, &dir.join("a.txt"), &dir.join("dest"));
+        assert!(output.ends_with(&format!("{}\n", dir.join("dest/a.txt").display())), "{}", output);
+        assert_eq!(fs::read_to_string(dir.join("dest/a.txt")).unwrap(), "new");
+
+        copy_locally(false, &dir.join("a.txt"), &dir.join("old.txt"));
+        assert_eq!(fs::read_to_string(dir.join("old.txt")).unwrap(), "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3, "no temporary file left behind");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,292 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use similar::TextDiff;
+
+use crate::files::{self, WriteOptions};
+use crate::redact;
+use crate::SshSession;
+
+// Search-and-replace / patch edits of remote text files
+
+const MAX_EDIT_BYTES: u64 = 10 * 1024 * 1024;
+
+pub fn tool_definitions() -> Vec<Value> {
+    vec![
+        json!({
+            "name": "ssh_edit_file",
+            "description": "Edit a remote text file with exact old/new string replacements or a unified diff, returning the resulting diff",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "path": { "type": "string", "description": "Remote file path" },
+                    "edits": {
+                        "type": "array",
+                        "description": "Replacements applied in order; each old_string must match exactly once unless replace_all is set",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "old_string": { "type": "string" },
+                                "new_string": { "type": "string" },
+                                "replace_all": { "type": "boolean", "default": false }
+                            },
+                            "required": ["old_string", "new_string"]
+                        }
+                    },
+                    "diff": { "type": "string", "description": "Unified diff to apply instead of edits" },
+                    "backup": { "type": "boolean", "description": "Keep a copy of the original as <path>.bak", "default": false },
+                    "dry_run": { "type": "boolean", "description": "Only return the diff, do not write", "default": false },
+                    "sudo": { "type": "boolean", "description": "Read and write with sudo", "default": false }
+                },
+                "required": ["session_id", "path"]
+            }
+        }),
+    ]
+}
+
+/// Handle `ssh_edit_file`
+pub fn edit_file(session_id: &str, session: &mut SshSession, arguments: &Value) -> Result<Value> {
+    let path = files::path_arg(arguments)?;
+    let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
+    let backup = arguments["backup"].as_bool().unwrap_or(false);
+    let dry_run = arguments["dry_run"].as_bool().unwrap_or(false);
+
+    let remote = files::fetch_whole(session, path, MAX_EDIT_BYTES, use_sudo)?;
+    let original = String::from_utf8(remote.data)
+        .context(format!("{} is not a UTF-8 text file", path))?;
+
+    let updated = match (&arguments["edits"], &arguments["diff"]) {
+        (Value::Array(edits), Value::Null) => apply_edits(&original, edits)?,
+        (Value::Null, Value::String(diff)) => apply_unified_diff(&original, diff)?,
+        _ => bail!("Provide either an edits array or a diff string"),
+    };
+
+    redact::check_write(Some(original.as_bytes()), updated.as_bytes())?;
+
+    if updated == original {
+        return Ok(json!({
+            "content": [{
+                "type": "text",
+                "text": format!("No changes to {}", path)
+            }],
+            "metadata": {
+                "session_id": session_id,
+                "path": path,
+                "changed": false
+            }
+        }));
+    }
+
+    let diff = TextDiff::from_lines(&original, &updated)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{}", path.trim_start_matches('/')), &format!("b/{}", path.trim_start_matches('/')))
+        .to_string();
+
+    let backup_path = format!("{}.bak", path);
+    let mut sha256 = files::sha256_hex(updated.as_bytes());
+    if !dry_run {
+        eprintln!("[MCP] Editing {} on session '{}' (sudo: {}, backup: {})", path, session_id, use_sudo, backup);
+
+        // Refuse to write if someone changed the file since we read it; the
+        // backup is only taken once that check has passed
+        sha256 = files::write_bytes(session, path, updated.as_bytes(), &WriteOptions {
+            use_sudo,
+            atomic: true,
+            expected_sha256: Some(&remote.sha256),
+            backup: backup.then_some(backup_path.as_str()),
+            ..WriteOptions::default()
+        })?;
+    }
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": diff
+        }],
+        "metadata": {
+            "session_id": session_id,
+            "path": path,
+            "changed": true,
+            "dry_run": dry_run,
+            "backup": if backup && !dry_run { Some(backup_path) } else { None },
+            "previous_sha256": remote.sha256,
+            "sha256": sha256
+        }
+    }))
+}
+
+fn apply_edits(original: &str, edits: &[Value]) -> Result<String> {
+    if edits.is_empty() {
+        bail!("edits must not be empty");
+    }
+
+    let mut text = original.to_string();
+    for (index, edit) in edits.iter().enumerate() {
+        let old = edit["old_string"].as_str()
+            .context(format!("Edit {}: missing or invalid old_string", index + 1))?;
+        let new = edit["new_string"].as_str()
+            .context(format!("Edit {}: missing or invalid new_string", index + 1))?;
+        let replace_all = edit["replace_all"].as_bool().unwrap_or(false);
+
+        if old.is_empty() {
+            bail!("Edit {}: old_string must not be empty", index + 1);
+        }
+
+        match text.matches(old).count() {
+            0 => bail!("Edit {}: old_string not found", index + 1),
+            1 => text = text.replacen(old, new, 1),
+            _ if replace_all => text = text.replace(old, new),
+            n => bail!("Edit {}: old_string matches {} times - include more context to make it unique or set replace_all", index + 1, n),
+        }
+    }
+
+    Ok(text)
+}
+
+struct Hunk<'a> {
+    old_start: usize,
+    old: Vec<&'a str>,
+    new: Vec<&'a str>,
+}
+
+fn parse_unified_diff(diff: &str) -> Result<Vec<Hunk<'_>>> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    // Lines still expected in the current hunk, from its header; any line
+    // outside a hunk (file headers, `diff` lines) is skipped
+    let mut remaining = (0, 0);
+
+    for line in diff.lines() {
+        if remaining == (0, 0) {
+            if let Some(header) = line.strip_prefix("@@ ") {
+                let (old_start, old_count, new_count) = parse_hunk_header(header)
+                    .context(format!("Invalid hunk header: {}", line))?;
+                hunks.push(Hunk { old_start, old: Vec::new(), new: Vec::new() });
+                remaining = (old_count, new_count);
+            }
+            continue;
+        }
+
+        let hunk = hunks.last_mut().expect("remaining lines imply a hunk");
+        let (old_left, new_left) = &mut remaining;
+        let taken = match line.chars().next() {
+            // Some tools strip the single space of empty context lines
+            Some(' ') | None => {
+                let text = line.get(1..).unwrap_or_default();
+                hunk.old.push(text);
+                hunk.new.push(text);
+                (1, 1)
+            }
+            Some('-') => {
+                hunk.old.push(&line[1..]);
+                (1, 0)
+            }
+            Some('+') => {
+                hunk.new.push(&line[1..]);
+                (0, 1)
+            }
+            Some('\\') => (0, 0),
+            _ => bail!("Invalid line in unified diff: {}", line),
+        };
+        if taken.0 > *old_left || taken.1 > *new_left {
+            bail!("Hunk at line {} has more lines than its header says", hunk.old_start);
+        }
+        *old_left -= taken.0;
+        *new_left -= taken.1;
+    }
+
+    if remaining != (0, 0) {
+        bail!("Diff ends in the middle of a hunk ({} old and {} new lines missing)", remaining.0, remaining.1);
+    }
+    if hunks.is_empty() {
+        bail!("No hunks found in diff");
+    }
+    Ok(hunks)
+}
+
+/// `-a,b +c,d @@`: old start, old and new line counts (a missing count is 1)
+fn parse_hunk_header(header: &str) -> Option<(usize, usize, usize)> {
+    let mut ranges = header.split_whitespace();
+    let range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(ranges.next()?.strip_prefix('-')?)?;
+    let (_, new_count) = range(ranges.next()?.strip_prefix('+')?)?;
+    Some((old_start, old_count, new_count))
+}
+
+fn apply_unified_diff(original: &str, diff: &str) -> Result<String> {
+    let hunks = parse_unified_diff(diff)?;
+    let lines: Vec<&str> = original.split('\n').collect();
+    let mut result: Vec<&str> = Vec::with_capacity(lines.len());
+    let mut cursor = 0;
+
+    for (index, hunk) in hunks.iter().enumerate() {
+        // Prefer the match closest to the line number in the header
+        let expected = hunk.old_start.saturating_sub(if hunk.old.is_empty() { 0 } else { 1 });
+        let position = if hunk.old.is_empty() {
+            Some(expected.clamp(cursor, lines.len()))
+        } else {
+            (cursor..=lines.len().saturating_sub(hunk.old.len()))
+                .filter(|&pos| lines[pos..pos + hunk.old.len()] == hunk.old[..])
+                .min_by_key(|&pos| pos.abs_diff(expected))
+        };
+        let position = position
+            .context(format!("Hunk {} (line {}) does not apply: context not found", index + 1, hunk.old_start))?;
+
+        result.extend_from_slice(&lines[cursor..position]);
+        result.extend_from_slice(&hunk.new);
+        cursor = position + hunk.old.len();
+    }
+    result.extend_from_slice(&lines[cursor..]);
+
+    Ok(result.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_content_lines_that_look_like_file_headers() {
+        let original = "a\n-- comment\nb\n++ counter\nc\n";
+        let diff = "--- a/file\n+++ b/file\n@@ -1,5 +1,5 @@\n a\n--- comment\n+-- sql comment\n b\n-++ counter\n+++ total\n c\n";
+        assert_eq!(apply_unified_diff(original, diff).unwrap(), "a\n-- sql comment\nb\n++ total\nc\n");
+    }
+
+    #[test]
+    fn applies_several_hunks() {
+        let original = (1..=20).map(|n| format!("{}\n", n)).collect::<String>();
+        let diff = "@@ -2,2 +2,2 @@\n 2\n-3\n+three\n@@ -18 +18,2 @@\n 18\n+18.5\n";
+        let updated = apply_unified_diff(&original, diff).unwrap();
+        assert!(updated.starts_with("1\n2\nthree\n4\n"));
+        assert!(updated.contains("\n18\n18.5\n19\n"));
+    }
+
+    #[test]
+    fn skips_headers_between_files_and_no_newline_markers() {
+        let diff = "diff --git a/f b/f\n--- a/f\n+++ b/f\n@@ -1 +1 @@\n-x\n\\ No newline at end of file\n+y\n\\ No newline at end of file\n";
+        let hunks = parse_unified_diff(diff).unwrap();
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].old, ["x"]);
+        assert_eq!(hunks[0].new, ["y"]);
+    }
+
+    #[test]
+    fn rejects_truncated_and_overlong_hunks() {
+        assert!(parse_unified_diff("@@ -1,3 +1,3 @@\n a\n-b\n").is_err());
+        assert!(parse_unified_diff("@@ -1 +1,2 @@\n-a\n-b\n+c\n+d\n").is_err());
+        assert!(parse_unified_diff("@@ -x +1 @@\n").is_err());
+        assert!(parse_unified_diff("no hunks here\n").is_err());
+    }
+
+    #[test]
+    fn parses_hunk_headers() {
+        assert_eq!(parse_hunk_header("-3,4 +5,6 @@ fn main"), Some((3, 4, 6)));
+        assert_eq!(parse_hunk_header("-3 +5 @@"), Some((3, 1, 1)));
+        assert_eq!(parse_hunk_header("-0,0 +1,2 @@"), Some((0, 0, 2)));
+    }
+}
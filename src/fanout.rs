@@ -0,0 +1,386 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Read;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::files;
+use crate::glob;
+use crate::redact;
+use crate::shell::quote;
+use crate::{SideChannel, SshSession};
+
+// Running one command on many sessions at once, each over its own side channel
+
+const DEFAULT_MAX_PARALLEL: u64 = 10;
+const DEFAULT_TIMEOUT: u64 = 60;
+/// Per-host limit for each of stdout and stderr
+const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+/// Extra time the remote `timeout` gets to stop a command before the local channel is killed
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+pub fn tool_definitions() -> Vec<Value> {
+    vec![
+        json!({
+            "name": "ssh_execute_many",
+            "description": "Run the same command on several sessions concurrently and return per-session stdout, stderr and exit code, with identical results grouped",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "sessions": { "type": "array", "items": { "type": "string" }, "description": "Session IDs or group names to run on" },
+                    "pattern": { "type": "string", "description": "Glob selecting sessions by session ID or host, instead of sessions" },
+                    "command": { "type": "string", "description": "Command to execute" },
+                    "sudo": { "type": "boolean", "description": "Execute with sudo", "default": false },
+                    "max_parallel": { "type": "integer", "description": "Maximum number of sessions running at once", "default": DEFAULT_MAX_PARALLEL },
+                    "timeout": { "type": "integer", "description": "Seconds before a session's command is killed, on the host with timeout(1) where it is installed; elsewhere only the channel is closed and the command may keep running (0 = no timeout)", "default": DEFAULT_TIMEOUT }
+                },
+                "required": ["command"]
+            }
+        }),
+    ]
+}
+
+/// Resolve the `sessions` or `pattern` argument to sessions, sorted by ID
+pub fn select<'a>(sessions: &'a HashMap<String, SshSession>, arguments: &Value) -> Result<Vec<(&'a str, &'a SshSession)>> {
+    let mut selected: Vec<(&str, &SshSession)> = match (&arguments["sessions"], arguments["pattern"].as_str()) {
+        (Value::Array(ids), None) => ids.iter()
+            .map(|id| {
+                let id = id.as_str().context("Invalid sessions parameter - must be an array of strings")?;
+                let (id, session) = sessions.get_key_value(id)
+                    .context(format!("No active session found with ID '{}'. Use ssh_list_sessions to see available sessions.", id))?;
+                Ok((id.as_str(), session))
+            })
+            .collect::<Result<_>>()?,
+        (Value::Null, Some(pattern)) => sessions.iter()
+            .filter(|(id, session)| glob::glob_match(pattern, id) || glob::glob_match(pattern, &session.host))
+            .map(|(id, session)| (id.as_str(), session))
+            .collect(),
+        (Value::Null, None) => bail!("Pass sessions or pattern to select sessions"),
+        (Value::Array(_), Some(_)) => bail!("Pass either sessions or pattern, not both"),
+        _ => bail!("Invalid sessions parameter - must be an array of strings"),
+    };
+
+    selected.sort_by_key(|(id, _)| *id);
+    selected.dedup_by_key(|(id, _)| *id);
+    if selected.is_empty() {
+        bail!("No sessions selected");
+    }
+    Ok(selected)
+}
+
+#[derive(Debug, Default)]
+struct SessionResult {
+    session_id: String,
+    host: String,
+    stdout: String,
+    stderr: String,
+    exit_code: Option<i32>,
+    error: Option<String>,
+    duration_ms: u64,
+    /// Output bytes received, before truncation
+    received: usize,
+}
+
+impl SessionResult {
+    fn succeeded(&self) -> bool {
+        self.exit_code == Some(0) && self.error.is_none()
+    }
+
+    /// Results with the same key are reported together
+    fn group_key(&self) -> (Option<i32>, &str, &str, Option<&str>) {
+        (self.exit_code, &self.stdout, &self.stderr, self.error.as_deref())
+    }
+}
+
+/// Handle `ssh_execute_many`; `denied` lists sessions the policy kept from running, with the reason.
+/// Also returns the output bytes received from each session that ran, for its stats
+pub fn execute_many(targets: &[(String, SideChannel)], denied: Vec<(String, String, String)>, arguments: &Value)
+                    -> Result<(Value, Vec<(String, usize)>)> {
+    let command = arguments["command"].as_str()
+        .context("Missing or invalid command parameter - must be a string")?;
+    if command.is_empty() {
+        bail!("Command parameter cannot be empty");
+    }
+    let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
+    let max_parallel = files::optional_u64(arguments, "max_parallel")?.unwrap_or(DEFAULT_MAX_PARALLEL).max(1) as usize;
+    let timeout = match files::optional_u64(arguments, "timeout")?.unwrap_or(DEFAULT_TIMEOUT) {
+        0 => None,
+        seconds => Some(Duration::from_secs(seconds)),
+    };
+
+    eprintln!("[MCP] Executing on {} sessions (parallel: {}): {}", targets.len(), max_parallel, redact::text(command));
+    let started = Instant::now();
+
+    let queue = Mutex::new(targets.iter().enumerate().collect::<VecDeque<_>>());
+    let results = Mutex::new(Vec::with_capacity(targets.len()));
+    thread::scope(|scope| {
+        for _ in 0..max_parallel.min(targets.len()) {
+            scope.spawn(|| loop {
+                let Some((index, (session_id, channel))) = queue.lock().unwrap().pop_front() else { break };
+                let result = run_one(session_id, channel, command, use_sudo, timeout);
+                results.lock().unwrap().push((index, result));
+            });
+        }
+    });
+
+    let mut results: Vec<SessionResult> = {
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    };
+    let received = results.iter().map(|result| (result.session_id.clone(), result.received)).collect();
+    results.extend(denied.into_iter().map(|(session_id, host, reason)| SessionResult {
+        session_id,
+        host,
+        error: Some(reason),
+        ..SessionResult::default()
+    }));
+
+    let succeeded = results.iter().filter(|result| result.succeeded()).count();
+    let elapsed = started.elapsed().as_secs_f64();
+    eprintln!("[MCP] Executed on {} sessions in {:.1}s: {} succeeded", results.len(), elapsed, succeeded);
+
+    let groups = group(&results);
+    let text = summary(&results, &groups, elapsed);
+
+    let response = json!({
+        "content": [{
+            "type": "text",
+            "text": text
+        }],
+        "metadata": {
+            "command": command,
+            "sudo": use_sudo,
+            "total": results.len(),
+            "succeeded": succeeded,
+            "failed": results.len() - succeeded,
+            "seconds": elapsed,
+            "results": results.iter().map(|result| json!({
+                "session_id": result.session_id,
+                "host": result.host,
+                "exit_code": result.exit_code,
+                "stdout": result.stdout,
+                "stderr": result.stderr,
+                "error": result.error,
+                "duration_ms": result.duration_ms
+            })).collect::<Vec<_>>(),
+            "groups": groups.iter().map(|group| json!({
+                "sessions": group.iter().map(|result| &result.session_id).collect::<Vec<_>>(),
+                "exit_code": group[0].exit_code,
+                "error": group[0].error
+            })).collect::<Vec<_>>()
+        }
+    });
+    Ok((response, received))
+}
+
+/// Group identical outcomes, largest group first
+fn group(results: &[SessionResult]) -> Vec<Vec<&SessionResult>> {
+    let mut groups: BTreeMap<_, Vec<&SessionResult>> = BTreeMap::new();
+    for result in results {
+        groups.entry(result.group_key()).or_default().push(result);
+    }
+    let mut groups: Vec<Vec<&SessionResult>> = groups.into_values().collect();
+    groups.sort_by_key(|group| std::cmp::Reverse(group.len()));
+    groups
+}
+
+fn summary(results: &[SessionResult], groups: &[Vec<&SessionResult>], elapsed: f64) -> String {
+    let succeeded = results.iter().filter(|result| result.succeeded()).count();
+    let mut text = format!("{} sessions: {} succeeded, {} failed ({:.1}s)",
+        results.len(), succeeded, results.len() - succeeded, elapsed);
+    for group in groups {
+        let first = group[0];
+        let status = match (&first.error, first.exit_code) {
+            (Some(error), _) => format!("error: {}", error),
+            (None, Some(code)) => format!("exit {}", code),
+            (None, None) => "killed".to_string(),
+        };
+        text.push_str(&format!("\n\n=== {} ({} sessions: {}) ===", status, group.len(),
+            group.iter().map(|result| result.session_id.as_str()).collect::<Vec<_>>().join(", ")));
+        if !first.stdout.is_empty() {
+            text.push_str(&format!("\n{}", first.stdout.trim_end()));
+        }
+        if !first.stderr.is_empty() {
+            text.push_str(&format!("\n[stderr]\n{}", first.stderr.trim_end()));
+        }
+    }
+    text
+}
+
+/// Stop the command on the host when the timeout passes, so closing the channel is only the fallback
+fn remote_command(command: &str, timeout: Option<Duration>) -> String {
+    match timeout {
+        Some(timeout) => format!("if command -v timeout > /dev/null 2>&1; then exec timeout -k 2 {} bash -c {}; fi; {}",
+            timeout.as_secs(), quote(command), command),
+        None => command.to_string(),
+    }
+}
+
+fn run_one(session_id: &str, channel: &SideChannel, command: &str, use_sudo: bool, timeout: Option<Duration>) -> SessionResult {
+    let started = Instant::now();
+    let mut result = SessionResult {
+        session_id: session_id.to_string(),
+        host: channel.host.clone(),
+        ..SessionResult::default()
+    };
+
+    let mut child = match channel.command(&remote_command(command, timeout), use_sudo)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            result.error = Some(format!("Failed to start command: {}", e));
+            return result;
+        }
+    };
+    let stdout = collect_capped(child.stdout.take());
+    let stderr = collect_capped(child.stderr.take());
+
+    loop {
+        match child.try_wait() {
+            // timeout(1) exits 124 when it stopped the command
+            Ok(Some(status)) if status.code() == Some(124) && timeout.is_some_and(|timeout| started.elapsed() >= timeout) => {
+                result.error = Some(format!("Timed out after {}s", timeout.unwrap_or_default().as_secs()));
+                break;
+            }
+            Ok(Some(status)) => {
+                result.exit_code = status.code();
+                break;
+            }
+            Ok(None) if timeout.is_some_and(|timeout| started.elapsed() >= timeout + KILL_GRACE) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                result.error = Some(format!("Timed out after {}s", timeout.unwrap_or_default().as_secs()));
+                break;
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(20)),
+            Err(e) => {
+                result.error = Some(format!("Failed to wait for command: {}", e));
+                break;
+            }
+        }
+    }
+
+    let (stdout, stdout_total) = stdout.join().unwrap_or_default();
+    let (stderr, stderr_total) = stderr.join().unwrap_or_default();
+    result.received = stdout_total + stderr_total;
+    result.stdout = truncated(&stdout, stdout_total);
+    result.stderr = truncated(&stderr, stderr_total);
+    result.duration_ms = started.elapsed().as_millis() as u64;
+    result
+}
+
+/// Read a pipe to the end, keeping only the first `MAX_OUTPUT_BYTES`; also returns the total byte count
+fn collect_capped<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<(Vec<u8>, usize)> {
+    thread::spawn(move || {
+        let mut data = Vec::new();
+        let mut total = 0;
+        if let Some(mut pipe) = pipe {
+            let mut buffer = [0u8; 8192];
+            while let Ok(n @ 1..) = pipe.read(&mut buffer) {
+                let keep = n.min(MAX_OUTPUT_BYTES - data.len());
+                data.extend_from_slice(&buffer[..keep]);
+                total += n;
+            }
+        }
+        (data, total)
+    })
+}
+
+fn truncated(data: &[u8], total: usize) -> String {
+    if total <= data.len() {
+        return String::from_utf8_lossy(data).into_owned();
+    }
+    format!("{}\n... ({} more bytes not shown)", String::from_utf8_lossy(data), total - data.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(session_id: &str, exit_code: Option<i32>, stdout: &str, error: Option<&str>) -> SessionResult {
+        SessionResult {
+            session_id: session_id.to_string(),
+            exit_code,
+            stdout: stdout.to_string(),
+            error: error.map(str::to_string),
+            ..SessionResult::default()
+        }
+    }
+
+    #[test]
+    fn groups_identical_outcomes_largest_first() {
+        let results = vec![
+            result("a", Some(0), "ok\n", None),
+            result("b", Some(1), "ok\n", None),
+            result("c", Some(0), "ok\n", None),
+            result("d", Some(0), "other\n", None),
+            result("e", Some(0), "ok\n", None),
+        ];
+        let groups = group(&results);
+        let ids: Vec<Vec<&str>> = groups.iter()
+            .map(|group| group.iter().map(|result| result.session_id.as_str()).collect())
+            .collect();
+        assert_eq!(ids[0], ["a", "c", "e"]);
+        assert_eq!(ids.len(), 3);
+        assert!(ids[1..].contains(&vec!["b"]) && ids[1..].contains(&vec!["d"]));
+    }
+
+    #[test]
+    fn errors_group_apart_from_exit_codes() {
+        let results = vec![
+            result("a", None, "", Some("Timed out after 5s")),
+            result("b", None, "", Some("Timed out after 5s")),
+            result("c", None, "", Some("Policy denied: read-only session")),
+            result("d", None, "", None),
+        ];
+        let groups = group(&results);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].len(), 2);
+    }
+
+    #[test]
+    fn summary_reports_counts_and_group_output() {
+        let mut failed = result("web2", Some(2), "", None);
+        failed.stderr = "no such file\n".to_string();
+        let results = vec![
+            result("web1", Some(0), "up\n", None),
+            result("web3", Some(0), "up\n", None),
+            failed,
+            result("db1", None, "", Some("Timed out after 5s")),
+            result("db2", None, "", None),
+        ];
+        let text = summary(&results, &group(&results), 1.25);
+        assert!(text.starts_with("5 sessions: 2 succeeded, 3 failed (1.2s)"), "{}", text);
+        assert!(text.contains("=== exit 0 (2 sessions: web1, web3) ===\nup"));
+        assert!(text.contains("=== exit 2 (1 sessions: web2) ===\n[stderr]\nno such file"));
+        assert!(text.contains("=== error: Timed out after 5s (1 sessions: db1) ==="));
+        assert!(text.contains("=== killed (1 sessions: db2) ==="));
+    }
+
+    #[test]
+    fn output_is_capped_while_read() {
+        let data = vec![b'x'; MAX_OUTPUT_BYTES * 3 + 17];
+        let (kept, total) = collect_capped(Some(std::io::Cursor::new(data))).join().unwrap();
+        assert_eq!(kept.len(), MAX_OUTPUT_BYTES);
+        assert_eq!(total, MAX_OUTPUT_BYTES * 3 + 17);
+        assert!(truncated(&kept, total).ends_with(&format!("... ({} more bytes not shown)", MAX_OUTPUT_BYTES * 2 + 17)));
+
+        let (kept, total) = collect_capped(Some(&b"short"[..])).join().unwrap();
+        assert_eq!(truncated(&kept, total), "short");
+        assert_eq!(collect_capped(None::<&'static [u8]>).join().unwrap(), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn timeout_stops_the_command_on_the_host() {
+        assert_eq!(remote_command("uptime", None), "uptime");
+        assert_eq!(remote_command("echo 'a b'", Some(Duration::from_secs(30))),
+            "if command -v timeout > /dev/null 2>&1; then exec timeout -k 2 30 bash -c 'echo '\\''a b'\\'''; fi; echo 'a b'");
+    }
+}
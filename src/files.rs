@@ -0,0 +1,639 @@
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::redact;
+use crate::shell::quote;
+use crate::SshSession;
+
+// Remote file tools, implemented as plain shell pipelines through the relay
+
+const DEFAULT_MAX_READ_BYTES: u64 = 1024 * 1024;
+
+/// Base64 characters sent per command; keeps each relay line well below the
+/// kernel's single-argument limit (128 KiB)
+const UPLOAD_CHUNK_CHARS: usize = 64 * 1024;
+
+pub fn tool_definitions() -> Vec<Value> {
+    vec![
+        json!({
+            "name": "ssh_read_file",
+            "description": "Read a remote file (optionally a byte or line range) with size, mtime and sha256 metadata",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "path": { "type": "string", "description": "Remote file path" },
+                    "offset": { "type": "integer", "description": "Byte offset to start reading at" },
+                    "length": { "type": "integer", "description": "Number of bytes to read from offset" },
+                    "start_line": { "type": "integer", "description": "First line to read (1-based, cannot be combined with offset/length)" },
+                    "end_line": { "type": "integer", "description": "Last line to read (inclusive)" },
+                    "max_bytes": { "type": "integer", "description": "Maximum number of bytes to return", "default": DEFAULT_MAX_READ_BYTES },
+                    "encoding": {
+                        "type": "string",
+                        "enum": ["auto", "text", "base64", "resource"],
+                        "description": "auto returns text for UTF-8 content and base64 otherwise; resource returns an embedded MCP resource",
+                        "default": "auto"
+                    },
+                    "sudo": { "type": "boolean", "description": "Read with sudo", "default": false }
+                },
+                "required": ["session_id", "path"]
+            }
+        }),
+        json!({
+            "name": "ssh_write_file",
+            "description": "Write a remote file, by default atomically (write to a temp file, then rename)",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "path": { "type": "string", "description": "Remote file path" },
+                    "content": { "type": "string", "description": "File content" },
+                    "encoding": {
+                        "type": "string",
+                        "enum": ["text", "base64"],
+                        "description": "Encoding of content",
+                        "default": "text"
+                    },
+                    "mode": { "type": "string", "description": "Octal file mode, e.g. \"0644\" (defaults to the existing file's mode)" },
+                    "owner": { "type": "string", "description": "Owner as user or user:group (usually requires sudo)" },
+                    "sudo": { "type": "boolean", "description": "Write with sudo", "default": false },
+                    "create_parents": { "type": "boolean", "description": "Create missing parent directories", "default": false },
+                    "atomic": { "type": "boolean", "description": "Write to a temp file and rename it into place", "default": true },
+                    "expected_sha256": { "type": "string", "description": "Only write if the current file has this sha256 (use \"absent\" to require that the file does not exist)" }
+                },
+                "required": ["session_id", "path", "content"]
+            }
+        }),
+    ]
+}
+
+/// Handle `ssh_read_file`
+pub fn read_file(session_id: &str, session: &mut SshSession, arguments: &Value) -> Result<Value> {
+    let path = path_arg(arguments)?;
+    let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
+    let max_bytes = optional_u64(arguments, "max_bytes")?.unwrap_or(DEFAULT_MAX_READ_BYTES);
+    let encoding = arguments["encoding"].as_str().unwrap_or("auto");
+    if !["auto", "text", "base64", "resource"].contains(&encoding) {
+        bail!("Invalid encoding '{}' - must be one of auto, text, base64, resource", encoding);
+    }
+
+    let extract = range_command(path, arguments)?;
+    let mut remote = fetch(session, path, &extract, max_bytes, use_sudo)?;
+    let data = &mut remote.data;
+
+    let truncated = data.len() as u64 > max_bytes;
+    if truncated {
+        data.truncate(max_bytes as usize);
+    }
+
+    eprintln!("[MCP] Read {} bytes of {} on session '{}' (truncated: {})", data.len(), path, session_id, truncated);
+
+    let is_text = std::str::from_utf8(data).is_ok();
+    let encoding = match encoding {
+        "auto" if is_text => "text",
+        "auto" => "base64",
+        other => other,
+    };
+
+    let content = match encoding {
+        "resource" if is_text => json!({
+            "type": "resource",
+            "resource": {
+                "uri": resource_uri(session_id, path),
+                "mimeType": "text/plain",
+                "text": String::from_utf8_lossy(data)
+            }
+        }),
+        "resource" => json!({
+            "type": "resource",
+            "resource": {
+                "uri": resource_uri(session_id, path),
+                "mimeType": "application/octet-stream",
+                "blob": BASE64.encode(&data)
+            }
+        }),
+        "text" => json!({ "type": "text", "text": String::from_utf8_lossy(data) }),
+        _ => json!({ "type": "text", "text": BASE64.encode(&data) }),
+    };
+
+    Ok(json!({
+        "content": [content],
+        "metadata": {
+            "session_id": session_id,
+            "path": path,
+            "size": remote.size,
+            "mtime": remote.mtime,
+            "sha256": remote.sha256,
+            "bytes_returned": data.len(),
+            "truncated": truncated,
+            "encoding": encoding
+        }
+    }))
+}
+
+/// The command printing the byte or line range of `path` the read arguments ask for
+fn range_command(path: &str, arguments: &Value) -> Result<String> {
+    let offset = optional_u64(arguments, "offset")?;
+    let length = optional_u64(arguments, "length")?;
+    let start_line = optional_u64(arguments, "start_line")?;
+    let end_line = optional_u64(arguments, "end_line")?;
+    if (offset.is_some() || length.is_some()) && (start_line.is_some() || end_line.is_some()) {
+        bail!("Byte ranges (offset/length) and line ranges (start_line/end_line) cannot be combined");
+    }
+    if start_line == Some(0) || end_line == Some(0) {
+        bail!("Line numbers start at 1");
+    }
+
+    let quoted = quote(path);
+    Ok(if start_line.is_some() || end_line.is_some() {
+        let start = start_line.unwrap_or(1);
+        match end_line {
+            Some(end) if end < start => bail!("end_line must not be before start_line"),
+            Some(end) => format!("sed -n '{},{}p' -- {}", start, end, quoted),
+            None => format!("tail -n +{} -- {}", start, quoted),
+        }
+    } else {
+        // tail counts bytes from 1
+        let start = offset.unwrap_or(0).checked_add(1).context("offset is too large")?;
+        match length {
+            Some(length) => format!("tail -c +{} -- {} | head -c {}", start, quoted, length),
+            None => format!("tail -c +{} -- {}", start, quoted),
+        }
+    })
+}
+
+pub struct RemoteFile {
+    pub size: u64,
+    pub mtime: i64,
+    pub sha256: String,
+    pub data: Vec<u8>,
+}
+
+/// Fetch metadata of a regular file plus up to `limit + 1` bytes of `extract`'s
+/// output, so callers can tell whether the content was cut off
+pub fn fetch(session: &mut SshSession, path: &str, extract: &str, limit: u64, use_sudo: bool) -> Result<RemoteFile> {
+    let output = session.run_checked(&fetch_command(path, extract, limit)?, use_sudo)
+        .context(format!("Failed to read {}", path))?;
+    parse_fetched(path, &output)
+}
+
+/// Metadata line, checksum line, then the (capped) content as one base64 line
+fn fetch_command(path: &str, extract: &str, limit: u64) -> Result<String> {
+    Ok(format!(
+        "[ -f {p} ] || {{ echo \"Not a regular file: \"{p}; exit 1; }}; \
+         stat -L -c '%s %Y' -- {p} && sha256sum -- {p} | cut -d' ' -f1 && \
+         {{ {extract} | head -c {limit} | base64 -w0; }} && echo",
+        p = quote(path), extract = extract, limit = limit.checked_add(1).context("max_bytes is too large")?))
+}
+
+fn parse_fetched(path: &str, output: &str) -> Result<RemoteFile> {
+    let mut lines = output.lines();
+    let mut stat = lines.next().unwrap_or_default().split_whitespace();
+    let size = stat.next().and_then(|s| s.parse().ok())
+        .context(format!("Unexpected stat output while reading {}", path))?;
+    let mtime = stat.next().and_then(|s| s.parse().ok())
+        .context(format!("Unexpected stat output while reading {}", path))?;
+    let sha256 = lines.next().unwrap_or_default().trim().to_string();
+    let data = BASE64.decode(lines.next().unwrap_or_default().trim())
+        .context(format!("Invalid content encoding while reading {}", path))?;
+
+    Ok(RemoteFile { size, mtime, sha256, data })
+}
+
+/// Fetch a whole file, failing if it is larger than `limit`
+pub fn fetch_whole(session: &mut SshSession, path: &str, limit: u64, use_sudo: bool) -> Result<RemoteFile> {
+    let remote = fetch(session, path, &format!("cat -- {}", quote(path)), limit, use_sudo)?;
+    if remote.data.len() as u64 > limit {
+        bail!("{} is larger than {} bytes", path, limit);
+    }
+    Ok(remote)
+}
+
+/// Handle `ssh_write_file`
+pub fn write_file(session_id: &str, session: &mut SshSession, arguments: &Value) -> Result<Value> {
+    let path = path_arg(arguments)?;
+    let content = arguments["content"].as_str()
+        .context("Missing or invalid content parameter - must be a string")?;
+    let data = match arguments["encoding"].as_str().unwrap_or("text") {
+        "text" => content.as_bytes().to_vec(),
+        "base64" => BASE64.decode(content.trim())
+            .context("Invalid base64 in content parameter")?,
+        other => bail!("Invalid encoding '{}' - must be text or base64", other),
+    };
+    redact::check_write(None, &data)?;
+    let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
+    let create_parents = arguments["create_parents"].as_bool().unwrap_or(false);
+    let atomic = arguments["atomic"].as_bool().unwrap_or(true);
+
+    let mode = match arguments["mode"].as_str() {
+        Some(mode) if !mode.is_empty() && mode.len() <= 4 && mode.chars().all(|c| ('0'..='7').contains(&c)) => Some(mode),
+        Some(mode) => bail!("Invalid mode '{}' - must be octal, e.g. 0644", mode),
+        None => None,
+    };
+    let owner = match arguments["owner"].as_str() {
+        Some(owner) if !owner.is_empty() && !owner.starts_with('-')
+            && owner.chars().all(|c| c.is_alphanumeric() || "_-.:".contains(c)) => Some(owner),
+        Some(owner) => bail!("Invalid owner '{}'", owner),
+        None => None,
+    };
+    let expected_sha256 = arguments["expected_sha256"].as_str();
+
+    eprintln!("[MCP] Writing {} bytes to {} on session '{}' (sudo: {}, atomic: {})",
+        data.len(), path, session_id, use_sudo, atomic);
+
+    let sha256 = write_bytes(session, path, &data, &WriteOptions {
+        use_sudo,
+        create_parents,
+        atomic,
+        mode,
+        owner,
+        expected_sha256,
+        backup: None,
+    })?;
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": format!("Wrote {} bytes to {}", data.len(), path)
+        }],
+        "metadata": {
+            "session_id": session_id,
+            "path": path,
+            "bytes_written": data.len(),
+            "sha256": sha256,
+            "atomic": atomic,
+            "sudo": use_sudo
+        }
+    }))
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct WriteOptions<'a> {
+    pub use_sudo: bool,
+    pub create_parents: bool,
+    pub atomic: bool,
+    pub mode: Option<&'a str>,
+    pub owner: Option<&'a str>,
+    /// Current sha256 the file must have, or "absent"
+    pub expected_sha256: Option<&'a str>,
+    /// Copy the current file here once the guard has passed, just before replacing it
+    pub backup: Option<&'a str>,
+}
+
+/// Write bytes to a remote path and return their sha256
+pub fn write_bytes(session: &mut SshSession, path: &str, data: &[u8], options: &WriteOptions) -> Result<String> {
+    let WriteOptions { use_sudo, create_parents, atomic, expected_sha256, backup, .. } = *options;
+    let sha256 = sha256_hex(data);
+    let quoted = quote(path);
+    let (dir, name) = match path.rsplit_once('/') {
+        Some(("", name)) => ("/".to_string(), name),
+        Some((dir, name)) => (dir.to_string(), name),
+        None => (".".to_string(), path),
+    };
+    if name.is_empty() {
+        bail!("Path must name a file, not a directory: {}", path);
+    }
+
+    if create_parents {
+        session.run_checked(&format!("mkdir -p -- {}", quote(&dir)), use_sudo)
+            .context(format!("Failed to create parent directories of {}", path))?;
+    }
+
+    if let Some(expected) = expected_sha256 {
+        session.run_checked(&sha256_guard(&quoted, expected), use_sudo)
+            .context(format!("Refusing to write {}", path))?;
+    }
+    // In place, the upload itself overwrites the file
+    if let (Some(backup), false) = (backup, atomic) {
+        session.run_checked(&format!("cp -p -- {} {}", quoted, quote(backup)), use_sudo)
+            .context(format!("Failed to back up {}", path))?;
+    }
+
+    let target = if atomic {
+        let nonce = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+        format!("{}/.{}.mcp-tmp-{}", dir.trim_end_matches('/'), name, nonce)
+    } else {
+        path.to_string()
+    };
+
+    let existed = atomic || session.execute(&format!("[ -e {} ]", quoted), use_sudo)?.1 == 0;
+
+    let result = upload_bytes(session, data, &target, use_sudo).and_then(|()| {
+        session.run_checked(&finish_command(path, &target, &sha256, existed, options), use_sudo).map(|_| ())
+    });
+
+    if let Err(e) = result {
+        if atomic {
+            let _ = session.execute(&format!("rm -f -- {}", quote(&target)), use_sudo);
+        }
+        return Err(e.context(format!("Failed to write {}", path)));
+    }
+
+    Ok(sha256)
+}
+
+/// After `data` was uploaded to `target`: verify it, apply permissions, re-check
+/// the guard and rename it over `path`, all in one command
+fn finish_command(path: &str, target: &str, sha256: &str, existed: bool, options: &WriteOptions) -> String {
+    let (quoted, quoted_target) = (quote(path), quote(target));
+    // Uploads are created private; new files then get the usual umask-based mode
+    let default_mode = format!("chmod \"$(printf '%o' $((0666 & ~$(umask))))\" -- {}", quoted_target);
+
+    let mut steps = vec![format!(
+        "[ \"$(sha256sum -- {t} | cut -d' ' -f1)\" = {sha} ] || {{ echo 'Checksum mismatch after upload'; exit 1; }}",
+        t = quoted_target, sha = sha256)];
+    if options.atomic {
+        steps.push(format!(
+            "if [ -e {p} ]; then chmod --reference={p} -- {t} && {{ [ \"$(id -u)\" != 0 ] || chown --reference={p} -- {t}; }}; \
+             else {default}; fi",
+            p = quoted, t = quoted_target, default = default_mode));
+    } else if !existed {
+        steps.push(default_mode);
+    }
+    if let Some(mode) = options.mode {
+        steps.push(format!("chmod {} -- {}", mode, quoted_target));
+    }
+    if let Some(owner) = options.owner {
+        steps.push(format!("chown {} -- {}", owner, quoted_target));
+    }
+    if options.atomic {
+        if let Some(expected) = options.expected_sha256 {
+            steps.push(format!("{{ {}; }}", sha256_guard(&quoted, expected)));
+        }
+        if let Some(backup) = options.backup {
+            steps.push(format!("cp -p -- {} {}", quoted, quote(backup)));
+        }
+        steps.push(format!("mv -f -- {} {}", quoted_target, quoted));
+    }
+    steps.join(" && ")
+}
+
+/// Stream bytes into a remote file in base64 chunks, replacing its content
+pub fn upload_bytes(session: &mut SshSession, data: &[u8], remote_path: &str, use_sudo: bool) -> Result<()> {
+    for command in upload_commands(data, remote_path) {
+        session.run_checked(&command, use_sudo)
+            .context(format!("Failed to upload data to {}", remote_path))?;
+    }
+    Ok(())
+}
+
+/// Commands creating `remote_path` privately, then appending `data` to it chunk by chunk
+fn upload_commands(data: &[u8], remote_path: &str) -> Vec<String> {
+    let encoded = BASE64.encode(data);
+    let quoted = quote(remote_path);
+
+    let mut commands = vec![format!("(umask 077 && : > {})", quoted)];
+    // Chunk boundaries are multiples of 4 so each chunk decodes on its own
+    for chunk in encoded.as_bytes().chunks(UPLOAD_CHUNK_CHARS) {
+        let chunk = std::str::from_utf8(chunk).expect("base64 is ASCII");
+        commands.push(format!("printf %s '{}' | base64 -d >> {}", chunk, quoted));
+    }
+    commands
+}
+
+/// Shell snippet that fails unless the file's sha256 matches (or it is absent)
+fn sha256_guard(quoted_path: &str, expected: &str) -> String {
+    if expected == "absent" {
+        format!("[ ! -e {p} ] || {{ echo 'File already exists'; exit 3; }}", p = quoted_path)
+    } else {
+        format!(
+            "[ -e {p} ] || {{ echo 'File does not exist'; exit 3; }}; \
+             [ \"$(sha256sum -- {p} | cut -d' ' -f1)\" = {e} ] || {{ echo 'File changed: sha256 does not match expected_sha256'; exit 3; }}",
+            p = quoted_path, e = quote(&expected.to_ascii_lowercase()))
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn resource_uri(session_id: &str, path: &str) -> String {
+    format!("ssh://{}{}{}", session_id, if path.starts_with('/') { "" } else { "/" }, path)
+}
+
+pub fn path_arg(arguments: &Value) -> Result<&str> {
+    let path = arguments["path"].as_str()
+        .context("Missing or invalid path parameter - must be a string")?;
+    if path.is_empty() || path.contains(['\n', '\0']) {
+        bail!("Invalid path parameter");
+    }
+    Ok(path)
+}
+
+pub fn optional_u64(arguments: &Value, key: &str) -> Result<Option<u64>> {
+    match &arguments[key] {
+        Value::Null => Ok(None),
+        value => value.as_u64()
+            .map(Some)
+            .context(format!("Invalid {} parameter - must be a non-negative integer", key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mcp-ssh-files-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Run a command as the relay would, returning (stdout, exit code)
+    fn run(command: &str) -> (String, i32) {
+        let output = Command::new("bash").arg("-c").arg(command).output().unwrap();
+        (String::from_utf8_lossy(&output.stdout).into_owned(), output.status.code().unwrap_or(-1))
+    }
+
+    fn read_range(path: &str, range: Value, limit: u64) -> Result<RemoteFile> {
+        let extract = range_command(path, &range)?;
+        let (output, status) = run(&fetch_command(path, &extract, limit)?);
+        assert_eq!(status, 0, "{}", output);
+        parse_fetched(path, &output)
+    }
+
+    #[test]
+    fn reads_byte_and_line_ranges() {
+        let dir = temp_dir("ranges");
+        let path = dir.join("f.txt");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let whole = read_range(path, json!({}), 100).unwrap();
+        assert_eq!((whole.size, whole.data.as_slice()), (14, &b"one\ntwo\nthree\n"[..]));
+        assert_eq!(whole.sha256, sha256_hex(b"one\ntwo\nthree\n"));
+        assert_eq!(read_range(path, json!({ "offset": 0, "length": 3 }), 100).unwrap().data, b"one");
+        assert_eq!(read_range(path, json!({ "offset": 4 }), 100).unwrap().data, b"two\nthree\n");
+        assert_eq!(read_range(path, json!({ "offset": 4, "length": 0 }), 100).unwrap().data, b"");
+        assert_eq!(read_range(path, json!({ "offset": 14 }), 100).unwrap().data, b"");
+        assert_eq!(read_range(path, json!({ "offset": 1000, "length": 5 }), 100).unwrap().data, b"");
+        assert_eq!(read_range(path, json!({ "start_line": 2, "end_line": 2 }), 100).unwrap().data, b"two\n");
+        assert_eq!(read_range(path, json!({ "start_line": 3 }), 100).unwrap().data, b"three\n");
+        assert_eq!(read_range(path, json!({ "end_line": 1 }), 100).unwrap().data, b"one\n");
+        assert_eq!(read_range(path, json!({ "start_line": 10 }), 100).unwrap().data, b"");
+        // One byte past the limit shows the caller that the content was cut off
+        assert_eq!(read_range(path, json!({}), 5).unwrap().data, b"one\ntw");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        assert!(range_command("f", &json!({ "offset": 1, "start_line": 1 })).is_err());
+        assert!(range_command("f", &json!({ "start_line": 0 })).is_err());
+        assert!(range_command("f", &json!({ "end_line": 0 })).is_err());
+        assert!(range_command("f", &json!({ "start_line": 3, "end_line": 2 })).is_err());
+        assert!(range_command("f", &json!({ "offset": -1 })).is_err());
+        assert!(range_command("f", &json!({ "offset": u64::MAX })).is_err());
+        assert!(fetch_command("f", "cat f", u64::MAX).is_err());
+    }
+
+    #[test]
+    fn fetch_refuses_directories_and_missing_files() {
+        let dir = temp_dir("fetch");
+        let (output, status) = run(&fetch_command(dir.to_str().unwrap(), "true", 10).unwrap());
+        assert_eq!(status, 1);
+        assert!(output.starts_with("Not a regular file: "));
+        assert_eq!(run(&fetch_command(dir.join("missing").to_str().unwrap(), "true", 10).unwrap()).1, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn guard_checks_the_current_checksum() {
+        let dir = temp_dir("guard");
+        let path = dir.join("it's.txt");
+        let quoted = quote(path.to_str().unwrap());
+        assert_eq!(run(&sha256_guard(&quoted, "absent")).1, 0);
+        assert_eq!(run(&sha256_guard(&quoted, &sha256_hex(b"x"))), ("File does not exist\n".to_string(), 3));
+
+        fs::write(&path, "x").unwrap();
+        assert_eq!(run(&sha256_guard(&quoted, "absent")), ("File already exists\n".to_string(), 3));
+        assert_eq!(run(&sha256_guard(&quoted, &sha256_hex(b"x"))).1, 0);
+        assert_eq!(run(&sha256_guard(&quoted, &sha256_hex(b"x").to_ascii_uppercase())).1, 0);
+        let (output, status) = run(&sha256_guard(&quoted, &sha256_hex(b"y")));
+        assert_eq!(status, 3);
+        assert!(output.starts_with("File changed"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Upload `data` to `target` and run the finishing command, as `write_bytes` does
+    fn write_locally(path: &str, target: &str, data: &[u8], existed: bool, options: &WriteOptions) -> (String, i32) {
+        for command in upload_commands(data, target) {
+            assert_eq!(run(&command).1, 0);
+        }
+        run(&finish_command(path, target, &sha256_hex(data), existed, options))
+    }
+
+    #[test]
+    fn atomic_write_renames_into_place_with_the_old_mode() {
+        let dir = temp_dir("atomic");
+        let path = dir.join("f");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let backup = dir.join("f.bak");
+        let options = WriteOptions {
+            atomic: true,
+            expected_sha256: Some(&sha256_hex(b"old")),
+            backup: backup.to_str(),
+            ..WriteOptions::default()
+        };
+
+        let target = dir.join(".f.mcp-tmp-1");
+        let (output, status) = write_locally(path.to_str().unwrap(), target.to_str().unwrap(), b"new", true, &options);
+        assert_eq!(status, 0, "{}", output);
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        assert_eq!(fs::read(&backup).unwrap(), b"old");
+        assert!(!target.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn atomic_write_stops_when_the_file_changed_meanwhile() {
+        let dir = temp_dir("changed");
+        let path = dir.join("f");
+        fs::write(&path, "changed").unwrap();
+        let options = WriteOptions { atomic: true, expected_sha256: Some(&sha256_hex(b"old")), ..WriteOptions::default() };
+
+        let target = dir.join(".f.mcp-tmp-1");
+        let (output, status) = write_locally(path.to_str().unwrap(), target.to_str().unwrap(), b"new", true, &options);
+        assert_eq!(status, 3);
+        assert!(output.starts_with("File changed"));
+        assert_eq!(fs::read(&path).unwrap(), b"changed");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn finishing_fails_on_a_checksum_mismatch() {
+        let dir = temp_dir("mismatch");
+        let path = dir.join("f");
+        let target = dir.join(".f.mcp-tmp-1");
+        fs::write(&target, "corrupted").unwrap();
+        let options = WriteOptions { atomic: true, ..WriteOptions::default() };
+        let (output, status) = run(&finish_command(path.to_str().unwrap(), target.to_str().unwrap(), &sha256_hex(b"new"), true, &options));
+        assert_eq!((output.as_str(), status), ("Checksum mismatch after upload\n", 1));
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn in_place_write_sets_the_mode_of_new_files_only() {
+        let dir = temp_dir("in-place");
+        let path = dir.join("new");
+        let path = path.to_str().unwrap();
+        let options = WriteOptions { mode: Some("0600"), ..WriteOptions::default() };
+        let command = finish_command(path, path, &sha256_hex(b"x"), false, &options);
+        assert!(command.contains("umask") && command.ends_with(&format!("chmod 0600 -- {}", quote(path))));
+        assert!(!command.contains("mv -f"));
+        assert!(!finish_command(path, path, &sha256_hex(b"x"), true, &WriteOptions::default()).contains("umask"));
+
+        let (output, status) = write_locally(path, path, b"x", false, &options);
+        assert_eq!(status, 0, "{}", output);
+        assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn uploads_in_chunks_that_decode_on_their_own() {
+        // 3 bytes encode to 4 characters, so a full chunk holds exactly this many bytes
+        let per_chunk = UPLOAD_CHUNK_CHARS / 4 * 3;
+        let chunks = |len: usize| upload_commands(&vec![0xa5; len], "/tmp/f").len() - 1;
+        assert_eq!(chunks(0), 0);
+        assert_eq!(chunks(1), 1);
+        assert_eq!(chunks(per_chunk), 1);
+        assert_eq!(chunks(per_chunk + 1), 2);
+        assert_eq!(chunks(per_chunk * 2), 2);
+        assert_eq!(chunks(per_chunk * 2 + 1), 3);
+
+        let dir = temp_dir("chunks");
+        let path = dir.join("f");
+        for len in [0, 1, per_chunk - 1, per_chunk, per_chunk + 1, per_chunk * 2 + 2] {
+            let data: Vec<u8> = (0..len).map(|n| (n * 7 % 256) as u8).collect();
+            for command in upload_commands(&data, path.to_str().unwrap()) {
+                assert_eq!(run(&command).1, 0);
+            }
+            assert_eq!(fs::read(&path).unwrap(), data, "{} bytes", len);
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn validates_arguments() {
+        assert!(path_arg(&json!({ "path": "a\nb" })).is_err());
+        assert!(path_arg(&json!({ "path": "" })).is_err());
+        assert_eq!(optional_u64(&json!({ "n": 5 }), "n").unwrap(), Some(5));
+        assert_eq!(optional_u64(&json!({}), "n").unwrap(), None);
+        assert!(optional_u64(&json!({ "n": "5" }), "n").is_err());
+        assert_eq!(resource_uri("s", "/etc/hosts"), "ssh://s/etc/hosts");
+        assert_eq!(resource_uri("s", "notes.txt"), "ssh://s/notes.txt");
+    }
+}
@@ -0,0 +1,262 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+
+use crate::SshSession;
+
+// Port forwards attached to a session's control master
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ForwardKind {
+    Local,
+    Remote,
+    Dynamic,
+}
+
+impl ForwardKind {
+    fn flag(self) -> &'static str {
+        match self {
+            ForwardKind::Local => "-L",
+            ForwardKind::Remote => "-R",
+            ForwardKind::Dynamic => "-D",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ForwardKind::Local => "local",
+            ForwardKind::Remote => "remote",
+            ForwardKind::Dynamic => "dynamic",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Forward {
+    pub id: String,
+    pub kind: ForwardKind,
+    pub spec: String,
+    /// Port picked by the server when a remote forward asked for port 0
+    pub allocated_port: Option<u16>,
+}
+
+impl Forward {
+    fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "kind": self.kind.name(),
+            "spec": self.spec,
+            "allocated_port": self.allocated_port,
+        })
+    }
+
+    fn describe(&self) -> String {
+        match self.allocated_port {
+            Some(port) => format!("{} {} {} (allocated port {})", self.id, self.kind.name(), self.spec, port),
+            None => format!("{} {} {}", self.id, self.kind.name(), self.spec),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ForwardTable {
+    next_id: usize,
+    forwards: Vec<Forward>,
+}
+
+impl ForwardTable {
+    pub fn len(&self) -> usize {
+        self.forwards.len()
+    }
+}
+
+pub fn tool_definitions() -> Vec<Value> {
+    vec![
+        json!({
+            "name": "ssh_forward_local",
+            "description": "Forward a local port to a host:port reachable from the remote side (ssh -L)",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "local_port": { "type": "integer", "description": "Local port to listen on" },
+                    "remote_host": { "type": "string", "description": "Destination host as seen from the remote side", "default": "localhost" },
+                    "remote_port": { "type": "integer", "description": "Destination port" },
+                    "bind_address": { "type": "string", "description": "Local address to bind (defaults to loopback)" }
+                },
+                "required": ["session_id", "local_port", "remote_port"]
+            }
+        }),
+        json!({
+            "name": "ssh_forward_remote",
+            "description": "Forward a remote port to a host:port reachable from this machine (ssh -R)",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "remote_port": { "type": "integer", "description": "Remote port to listen on (0 lets the server pick one)" },
+                    "local_host": { "type": "string", "description": "Destination host as seen from this machine", "default": "localhost" },
+                    "local_port": { "type": "integer", "description": "Destination port" },
+                    "bind_address": { "type": "string", "description": "Remote address to bind (defaults to loopback)" }
+                },
+                "required": ["session_id", "remote_port", "local_port"]
+            }
+        }),
+        json!({
+            "name": "ssh_forward_dynamic",
+            "description": "Open a local SOCKS proxy that tunnels through the session (ssh -D)",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "local_port": { "type": "integer", "description": "Local SOCKS port to listen on" },
+                    "bind_address": { "type": "string", "description": "Local address to bind (defaults to loopback)" }
+                },
+                "required": ["session_id", "local_port"]
+            }
+        }),
+        json!({
+            "name": "ssh_list_forwards",
+            "description": "List port forwards of one or all sessions",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Only list forwards of this session" }
+                }
+            }
+        }),
+        json!({
+            "name": "ssh_cancel_forward",
+            "description": "Cancel a port forward",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "forward_id": { "type": "string", "description": "Forward ID as returned by the forward tools" }
+                },
+                "required": ["session_id", "forward_id"]
+            }
+        }),
+    ]
+}
+
+/// Handle `ssh_forward_local`, `ssh_forward_remote` and `ssh_forward_dynamic`
+pub fn add_forward(session: &mut SshSession, kind: ForwardKind, arguments: &Value) -> Result<Value> {
+    let bind = match arguments["bind_address"].as_str() {
+        Some(address) => Some(format_host(address)?),
+        None => None,
+    };
+
+    let spec = match kind {
+        ForwardKind::Local => {
+            let listen = port_arg(arguments, "local_port", false)?;
+            let host = format_host(arguments["remote_host"].as_str().unwrap_or("localhost"))?;
+            let port = port_arg(arguments, "remote_port", false)?;
+            format!("{}:{}:{}", listen, host, port)
+        }
+        ForwardKind::Remote => {
+            let listen = port_arg(arguments, "remote_port", true)?;
+            let host = format_host(arguments["local_host"].as_str().unwrap_or("localhost"))?;
+            let port = port_arg(arguments, "local_port", false)?;
+            format!("{}:{}:{}", listen, host, port)
+        }
+        ForwardKind::Dynamic => port_arg(arguments, "local_port", false)?.to_string(),
+    };
+    let spec = match bind {
+        Some(bind) => format!("{}:{}", bind, spec),
+        None => spec,
+    };
+
+    eprintln!("[SSH] Adding {} forward {} on {}", kind.name(), spec, session.host);
+
+    let output = session.control.ssh_command()
+        .args(["-O", "forward", kind.flag(), &spec, &session.host])
+        .output()
+        .context("Failed to execute ssh forward command")?;
+    if !output.status.success() {
+        bail!("Failed to add {} forward {} on {}: {}",
+            kind.name(), spec, session.host, String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    // ssh prints the allocated port for dynamic remote listeners
+    let allocated_port = String::from_utf8_lossy(&output.stdout).trim().parse().ok();
+
+    let table = &mut session.forwards;
+    table.next_id += 1;
+    let forward = Forward {
+        id: format!("fwd-{}", table.next_id),
+        kind,
+        spec,
+        allocated_port,
+    };
+    let text = format!("Added forward {}", forward.describe());
+    let metadata = forward.to_json();
+    table.forwards.push(forward);
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": text
+        }],
+        "metadata": metadata
+    }))
+}
+
+/// Handle `ssh_cancel_forward`
+pub fn cancel_forward(session: &mut SshSession, forward_id: &str) -> Result<Value> {
+    let index = session.forwards.forwards.iter()
+        .position(|forward| forward.id == forward_id)
+        .context(format!("No forward '{}' on this session. Use ssh_list_forwards to see active forwards.", forward_id))?;
+    let forward = session.forwards.forwards[index].clone();
+
+    eprintln!("[SSH] Cancelling forward {} on {}", forward.describe(), session.host);
+
+    let output = session.control.ssh_command()
+        .args(["-O", "cancel", forward.kind.flag(), &forward.spec, &session.host])
+        .output()
+        .context("Failed to execute ssh cancel command")?;
+    if !output.status.success() {
+        bail!("Failed to cancel forward {} on {}: {}",
+            forward.id, session.host, String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    session.forwards.forwards.remove(index);
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": format!("Cancelled forward {}", forward.describe())
+        }]
+    }))
+}
+
+/// Forwards of one session as (text lines, json entries)
+pub fn list_forwards(session_id: &str, session: &SshSession) -> (Vec<String>, Vec<Value>) {
+    session.forwards.forwards.iter()
+        .map(|forward| {
+            let mut entry = forward.to_json();
+            entry["session_id"] = json!(session_id);
+            (format!("  - [{}] {}", session_id, forward.describe()), entry)
+        })
+        .unzip()
+}
+
+fn port_arg(arguments: &Value, key: &str, allow_zero: bool) -> Result<u16> {
+    let port = arguments[key].as_u64()
+        .context(format!("Missing or invalid {} parameter - must be an integer", key))?;
+    if port > u16::MAX as u64 || (port == 0 && !allow_zero) {
+        bail!("Invalid {} parameter: {}", key, port);
+    }
+    Ok(port as u16)
+}
+
+fn format_host(host: &str) -> Result<String> {
+    if host.is_empty() || host.contains(|c: char| c.is_whitespace() || c.is_control() || c == '/' || c == '[' || c == ']') {
+        bail!("Invalid host or address in forward: '{}'", host);
+    }
+    // IPv6 literals need brackets inside a forward spec
+    if host.contains(':') {
+        Ok(format!("[{}]", host))
+    } else {
+        Ok(host.to_string())
+    }
+}
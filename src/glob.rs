@@ -0,0 +1,153 @@
+// Minimal glob matching for file filters and session/host patterns
+//
+// `*` matches within one path segment, `**` across segments, `?` one character
+// and `[abc]`/`[a-z]`/`[!a]` a character class.
+
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text, false)
+}
+
+/// Whether some path below the directory `dir` can match the pattern
+pub fn glob_match_below(pattern: &str, dir: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = format!("{}/", dir.trim_end_matches('/')).chars().collect();
+    match_from(&pattern, &text, true)
+}
+
+/// Match a relative path; patterns without a `/` also match the file name alone
+pub fn path_match(pattern: &str, path: &str) -> bool {
+    if glob_match(pattern, path) {
+        return true;
+    }
+    !pattern.contains('/') && glob_match(pattern, path.rsplit('/').next().unwrap_or(path))
+}
+
+/// Apply include (any must match, if given) and exclude (none may match) filters
+pub fn filter_path(path: &str, include: &[String], exclude: &[String]) -> bool {
+    (include.is_empty() || include.iter().any(|pattern| path_match(pattern, path)))
+        && !exclude.iter().any(|pattern| path_match(pattern, path))
+}
+
+/// With `prefix`, `text` only has to be the start of a longer match
+fn match_from(pattern: &[char], text: &[char], prefix: bool) -> bool {
+    if prefix && text.is_empty() {
+        return !pattern.is_empty();
+    }
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            // `**/` may also match zero directories
+            let rest = &pattern[2..];
+            let rest_no_slash = rest.strip_prefix(&['/']).unwrap_or(rest);
+            (0..=text.len()).any(|i| match_from(rest, &text[i..], prefix) || match_from(rest_no_slash, &text[i..], prefix))
+        }
+        Some('*') => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if match_from(rest, &text[i..], prefix) {
+                    return true;
+                }
+                if text.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => !text.is_empty() && text[0] != '/' && match_from(&pattern[1..], &text[1..], prefix),
+        Some('[') => match (text.first(), class_end(pattern)) {
+            (Some(&c), Some(end)) => class_matches(&pattern[1..end], c) && match_from(&pattern[end + 1..], &text[1..], prefix),
+            (Some(&c), None) => c == '[' && match_from(&pattern[1..], &text[1..], prefix),
+            (None, _) => false,
+        },
+        Some(&p) => text.first() == Some(&p) && match_from(&pattern[1..], &text[1..], prefix),
+    }
+}
+
+fn class_end(pattern: &[char]) -> Option<usize> {
+    // A `]` right after `[` or `[!` is a literal member
+    let start = if pattern.get(1) == Some(&'!') { 2 } else { 1 };
+    pattern.iter().skip(start + 1).position(|&c| c == ']').map(|i| i + start + 1)
+}
+
+fn class_matches(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!') | Some('^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            matched |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+    matched != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_stays_within_a_segment() {
+        assert!(glob_match("*.rs", "main.rs"));
+        assert!(!glob_match("*.rs", "src/main.rs"));
+        assert!(glob_match("src/*", "src/main.rs"));
+        assert!(!glob_match("src/*", "src/bin/main.rs"));
+    }
+
+    #[test]
+    fn double_star_crosses_segments() {
+        assert!(glob_match("**/*.rs", "main.rs"));
+        assert!(glob_match("**/*.rs", "src/bin/main.rs"));
+        assert!(glob_match("src/**", "src/bin/main.rs"));
+        assert!(glob_match("a/**/b", "a/b"));
+        assert!(glob_match("a/**/b", "a/x/y/b"));
+        assert!(!glob_match("a/**/b", "a/x/c"));
+    }
+
+    #[test]
+    fn question_mark_and_classes() {
+        assert!(glob_match("?.txt", "a.txt"));
+        assert!(!glob_match("?", "/"));
+        assert!(glob_match("web[0-9]", "web7"));
+        assert!(!glob_match("web[!0-9]", "web7"));
+        assert!(glob_match("web[^0-9]", "webx"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("a[", "a["));
+    }
+
+    #[test]
+    fn matches_below_directories() {
+        assert!(glob_match_below("/etc/**", "/etc"));
+        assert!(glob_match_below("/etc/nginx/*.conf", "/etc"));
+        assert!(glob_match_below("/etc/nginx/*.conf", "/etc/nginx/"));
+        assert!(glob_match_below("/etc/*", "/"));
+        assert!(!glob_match_below("/etc/nginx/*.conf", "/etc/nginx/sites"));
+        assert!(!glob_match_below("/etc", "/etc"));
+        assert!(!glob_match_below("/var/**", "/etc"));
+    }
+
+    #[test]
+    fn path_match_falls_back_to_the_file_name() {
+        assert!(path_match("*.log", "var/log/syslog.log"));
+        assert!(!path_match("log/*.log", "var/log/syslog.log"));
+        assert!(path_match("var/log/*.log", "var/log/syslog.log"));
+    }
+
+    #[test]
+    fn filters_include_then_exclude() {
+        let include = vec!["*.rs".to_string()];
+        let exclude = vec!["target/**".to_string()];
+        assert!(filter_path("src/main.rs", &include, &exclude));
+        assert!(!filter_path("target/debug/build.rs", &include, &exclude));
+        assert!(!filter_path("README.md", &include, &exclude));
+        assert!(filter_path("README.md", &[], &exclude));
+    }
+}
@@ -0,0 +1,469 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use serde_yaml_ng::Value as Yaml;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::glob;
+use crate::ssh_config;
+
+// Host groups from the config file and imported inventories
+//
+// Supported sources: Ansible INI and YAML inventories, and the concrete `Host`
+// aliases of an OpenSSH client config (collected in the group `ssh_config`).
+
+/// A host as an inventory describes it
+#[derive(Debug, Clone, Default)]
+pub struct InventoryHost {
+    /// Inventory name, used as session ID
+    pub name: String,
+    /// Address to connect to when it differs from the name (`ansible_host`)
+    pub address: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_file: Option<String>,
+}
+
+impl InventoryHost {
+    fn named(name: &str) -> Self {
+        InventoryHost { name: name.to_string(), ..InventoryHost::default() }
+    }
+
+    pub fn address(&self) -> &str {
+        self.address.as_deref().unwrap_or(&self.name)
+    }
+
+    /// `ssh_connect` arguments for this host; explicit arguments win over inventory values
+    pub fn connect_arguments(&self, arguments: &Value) -> Value {
+        let mut merged = arguments.clone();
+        if let Some(map) = merged.as_object_mut() {
+            map.remove("group");
+            map.insert("host".to_string(), json!(self.address()));
+            map.insert("session_id".to_string(), json!(self.name));
+            for (key, value) in [("user", json!(self.user)), ("port", json!(self.port)), ("identity_file", json!(self.identity_file))] {
+                if map.get(key).is_none_or(Value::is_null) && !value.is_null() {
+                    map.insert(key.to_string(), value);
+                }
+            }
+        }
+        merged
+    }
+
+    /// Merge variables from a later definition of the same host
+    fn merge(&mut self, other: InventoryHost) {
+        self.address = other.address.or(self.address.take());
+        self.user = other.user.or(self.user.take());
+        self.port = other.port.or(self.port);
+        self.identity_file = other.identity_file.or(self.identity_file.take());
+    }
+
+    /// Apply an Ansible connection variable
+    fn set_variable(&mut self, key: &str, value: &str) {
+        match key {
+            "ansible_host" | "ansible_ssh_host" => self.address = Some(value.to_string()),
+            "ansible_user" | "ansible_ssh_user" => self.user = Some(value.to_string()),
+            "ansible_port" | "ansible_ssh_port" => self.port = value.parse().ok(),
+            "ansible_ssh_private_key_file" | "ansible_private_key_file" => self.identity_file = Some(value.to_string()),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Inventory {
+    pub hosts: BTreeMap<String, InventoryHost>,
+    /// Group name to member host names
+    pub groups: BTreeMap<String, Vec<String>>,
+}
+
+impl Inventory {
+    /// Import inventory files, then add the config's groups. Config group
+    /// members may be globs, matched against all imported hosts.
+    pub fn load(config_groups: &BTreeMap<String, Vec<String>>, files: &[PathBuf]) -> Result<Self> {
+        let mut inventory = Inventory::default();
+
+        for path in files {
+            let path = expand_home(path);
+            let before = inventory.hosts.len();
+            let read = || std::fs::read_to_string(&path).context(format!("Failed to read inventory {}", path.display()));
+            match InventoryFormat::detect(&path) {
+                InventoryFormat::Yaml => inventory.parse_yaml(&read()?),
+                InventoryFormat::SshConfig => inventory.import_ssh_config(&path),
+                InventoryFormat::Ini => inventory.parse_ini(&read()?),
+            }
+            .context(format!("Invalid inventory {}", path.display()))?;
+            eprintln!("[MAIN] Imported {} hosts from {}", inventory.hosts.len() - before, path.display());
+        }
+
+        for (group, members) in config_groups {
+            let mut names = Vec::new();
+            for member in members {
+                if member.contains(['*', '?', '[']) {
+                    names.extend(inventory.hosts.keys().filter(|name| glob::glob_match(member, name)).cloned());
+                } else {
+                    names.push(member.clone());
+                }
+            }
+            for name in names {
+                inventory.add_member(group, InventoryHost::named(&name));
+            }
+        }
+
+        Ok(inventory)
+    }
+
+    pub fn members(&self, group: &str) -> Option<Vec<&InventoryHost>> {
+        let names = self.groups.get(group)?;
+        Some(names.iter().filter_map(|name| self.hosts.get(name)).collect())
+    }
+
+    /// Connected sessions of a group: those named after a member, as `ssh_connect_group`
+    /// names them. Sessions opened to a member's address under another ID are not included.
+    /// `None` if there is no such group.
+    pub fn session_ids<S>(&self, group: &str, sessions: &HashMap<String, S>) -> Option<Vec<String>> {
+        let members = self.groups.get(group)?;
+        let mut ids: Vec<String> = sessions.keys()
+            .filter(|id| members.contains(id))
+            .cloned()
+            .collect();
+        ids.sort();
+        Some(ids)
+    }
+
+    fn add_member(&mut self, group: &str, host: InventoryHost) {
+        let members = self.groups.entry(group.to_string()).or_default();
+        if !members.contains(&host.name) {
+            members.push(host.name.clone());
+        }
+        self.add_host(host);
+    }
+
+    fn add_host(&mut self, host: InventoryHost) {
+        match self.hosts.get_mut(&host.name) {
+            Some(existing) => existing.merge(host),
+            None => {
+                self.hosts.insert(host.name.clone(), host);
+            }
+        }
+    }
+
+    /// Add the members of child groups to their parents, innermost first
+    fn resolve_children(&mut self, children: &BTreeMap<String, Vec<String>>) {
+        fn collect(group: &str, inventory: &Inventory, children: &BTreeMap<String, Vec<String>>,
+                   seen: &mut Vec<String>, members: &mut Vec<String>) {
+            if seen.iter().any(|name| name == group) {
+                return;
+            }
+            seen.push(group.to_string());
+            for name in inventory.groups.get(group).into_iter().flatten() {
+                if !members.contains(name) {
+                    members.push(name.clone());
+                }
+            }
+            for child in children.get(group).into_iter().flatten() {
+                collect(child, inventory, children, seen, members);
+            }
+        }
+
+        for parent in children.keys() {
+            let mut members = Vec::new();
+            collect(parent, self, children, &mut Vec::new(), &mut members);
+            self.groups.insert(parent.clone(), members);
+        }
+    }
+
+    /// Ansible INI: `[group]` host lines with `key=value` variables, `[group:children]`
+    /// and `web[01:03].example.com` ranges; `[group:vars]` sections are ignored
+    fn parse_ini(&mut self, text: &str) -> Result<()> {
+        let mut section = "ungrouped".to_string();
+        let mut children: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                section = name.trim().to_string();
+                continue;
+            }
+
+            if let Some(group) = section.strip_suffix(":children") {
+                children.entry(group.to_string()).or_default().push(line.to_string());
+                continue;
+            }
+            if section.contains(':') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let pattern = parts.next().unwrap_or_default();
+            let variables: Vec<(&str, &str)> = parts.filter_map(|part| part.split_once('=')).collect();
+            for name in expand_range(pattern)? {
+                let mut host = InventoryHost::named(&name);
+                for (key, value) in &variables {
+                    host.set_variable(key, value.trim_matches(['"', '\'']));
+                }
+                self.add_member(&section, host);
+                self.add_member("all", InventoryHost::named(&name));
+            }
+        }
+
+        self.resolve_children(&children);
+        Ok(())
+    }
+
+    /// Ansible YAML: nested `hosts`, `children` and (ignored) `vars` mappings
+    fn parse_yaml(&mut self, text: &str) -> Result<()> {
+        let document: Yaml = serde_yaml_ng::from_str(text)?;
+        let Some(top) = document.as_mapping() else {
+            bail!("Expected a mapping of groups at the top level");
+        };
+
+        let mut children: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut pending: Vec<(String, &Yaml)> = top.iter()
+            .filter_map(|(name, body)| Some((name.as_str()?.to_string(), body)))
+            .collect();
+
+        while let Some((group, body)) = pending.pop() {
+            self.groups.entry(group.clone()).or_default();
+            if let Some(hosts) = body.get("hosts").and_then(Yaml::as_mapping) {
+                for (name, variables) in hosts {
+                    let Some(name) = name.as_str() else { continue };
+                    for name in expand_range(name)? {
+                        let mut host = InventoryHost::named(&name);
+                        for (key, value) in variables.as_mapping().into_iter().flatten() {
+                            let value = match value {
+                                Yaml::String(s) => s.clone(),
+                                Yaml::Number(n) => n.to_string(),
+                                _ => continue,
+                            };
+                            if let Some(key) = key.as_str() {
+                                host.set_variable(key, &value);
+                            }
+                        }
+                        self.add_member(&group, host);
+                    }
+                }
+            }
+            if let Some(nested) = body.get("children").and_then(Yaml::as_mapping) {
+                for (name, child_body) in nested {
+                    let Some(name) = name.as_str() else { continue };
+                    children.entry(group.clone()).or_default().push(name.to_string());
+                    pending.push((name.to_string(), child_body));
+                }
+            }
+        }
+
+        self.resolve_children(&children);
+        Ok(())
+    }
+
+    /// OpenSSH client config: concrete `Host` aliases, including those from `Include` files
+    fn import_ssh_config(&mut self, path: &Path) -> Result<()> {
+        // Connect by alias and let ssh resolve HostName, User, Port etc. itself
+        for known in ssh_config::load(path)? {
+            self.add_member("ssh_config", InventoryHost::named(&known.alias));
+        }
+        Ok(())
+    }
+}
+
+enum InventoryFormat {
+    Ini,
+    Yaml,
+    SshConfig,
+}
+
+impl InventoryFormat {
+    fn detect(path: &Path) -> Self {
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        if name.ends_with(".yml") || name.ends_with(".yaml") {
+            InventoryFormat::Yaml
+        } else if name == "config" || name.ends_with("ssh_config") {
+            InventoryFormat::SshConfig
+        } else {
+            InventoryFormat::Ini
+        }
+    }
+}
+
+/// Most hosts a host pattern may expand to, over all its ranges
+const MAX_RANGE_SIZE: u64 = 10_000;
+
+/// Expand Ansible numeric and alphabetic ranges: `web[01:03]` gives web01, web02, web03
+fn expand_range(pattern: &str) -> Result<Vec<String>> {
+    let (Some(open), Some(close)) = (pattern.find('['), pattern.find(']')) else {
+        return Ok(vec![pattern.to_string()]);
+    };
+    if close < open {
+        bail!("Invalid host range: {}", pattern);
+    }
+    let (prefix, range, suffix) = (&pattern[..open], &pattern[open + 1..close], &pattern[close + 1..]);
+    let (start, end) = range.split_once(':').context(format!("Invalid host range: {}", pattern))?;
+
+    // Check the size before expanding, so a huge range fails instead of exhausting memory
+    let too_large = |first: u64, last: u64| last.saturating_sub(first) >= MAX_RANGE_SIZE;
+    let values: Vec<String> = if let (Ok(first), Ok(last)) = (start.parse::<u64>(), end.parse::<u64>()) {
+        if too_large(first, last) {
+            bail!("Host range too large: {}", pattern);
+        }
+        // Keep zero padding as written
+        let width = if start.starts_with('0') { start.len() } else { 0 };
+        (first..=last).map(|n| format!("{:0width$}", n, width = width)).collect()
+    } else if let (Some(first), Some(last)) = (single_char(start), single_char(end)) {
+        (first..=last).map(String::from).collect()
+    } else {
+        bail!("Invalid host range: {}", pattern);
+    };
+
+    let mut hosts = Vec::new();
+    for value in values {
+        hosts.extend(expand_range(&format!("{}{}{}", prefix, value, suffix))?);
+        if hosts.len() as u64 > MAX_RANGE_SIZE {
+            bail!("Host range too large: {}", pattern);
+        }
+    }
+    Ok(hosts)
+}
+
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    chars.next().filter(|c| c.is_ascii_alphabetic() && chars.next().is_none())
+}
+
+/// Replace a leading `~` with the home directory
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), std::env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_ranges() {
+        assert_eq!(expand_range("web[01:03].example.com").unwrap(), ["web01.example.com", "web02.example.com", "web03.example.com"]);
+        assert_eq!(expand_range("db[8:10]").unwrap(), ["db8", "db9", "db10"]);
+        assert_eq!(expand_range("node[a:c]").unwrap(), ["nodea", "nodeb", "nodec"]);
+        assert_eq!(expand_range("r[1:2]u[1:2]").unwrap(), ["r1u1", "r1u2", "r2u1", "r2u2"]);
+        assert_eq!(expand_range("plain.example.com").unwrap(), ["plain.example.com"]);
+        assert!(expand_range("web[1-3]").is_err());
+        assert!(expand_range("web]1:3[").is_err());
+    }
+
+    #[test]
+    fn rejects_huge_ranges_before_expanding() {
+        let error = expand_range("web[0:18446744073709551615]").unwrap_err().to_string();
+        assert!(error.starts_with("Host range too large"), "{}", error);
+        assert!(expand_range("web[1:10000]").is_ok());
+        assert!(expand_range("web[1:200][1:200]").is_err());
+    }
+
+    #[test]
+    fn parses_ini_inventories() {
+        let mut inventory = Inventory::default();
+        inventory.parse_ini("
+# comment
+bastion ansible_host=10.0.0.1 ansible_user=admin
+
+[web]
+web[1:2] ansible_port=2222
+[db]
+db1 ansible_ssh_private_key_file='~/.ssh/db'
+
+[prod:children]
+web
+db
+
+[prod:vars]
+ignored=1
+").unwrap();
+
+        assert_eq!(inventory.groups["web"], ["web1", "web2"]);
+        assert_eq!(inventory.groups["prod"], ["web1", "web2", "db1"]);
+        assert_eq!(inventory.groups["ungrouped"], ["bastion"]);
+        assert_eq!(inventory.groups["all"], ["bastion", "web1", "web2", "db1"]);
+        assert_eq!(inventory.hosts["bastion"].address(), "10.0.0.1");
+        assert_eq!(inventory.hosts["bastion"].user.as_deref(), Some("admin"));
+        assert_eq!(inventory.hosts["web2"].port, Some(2222));
+        assert_eq!(inventory.hosts["web2"].address(), "web2");
+        assert_eq!(inventory.hosts["db1"].identity_file.as_deref(), Some("~/.ssh/db"));
+    }
+
+    #[test]
+    fn parses_yaml_inventories() {
+        let mut inventory = Inventory::default();
+        inventory.parse_yaml("
+all:
+  hosts:
+    bastion:
+      ansible_host: 10.0.0.1
+  children:
+    web:
+      hosts:
+        web[1:2]:
+          ansible_port: 2222
+      vars:
+        ignored: 1
+    db:
+      hosts:
+        db1:
+").unwrap();
+
+        assert_eq!(inventory.groups["web"], ["web1", "web2"]);
+        assert_eq!(inventory.groups["db"], ["db1"]);
+        let mut all = inventory.groups["all"].clone();
+        all.sort();
+        assert_eq!(all, ["bastion", "db1", "web1", "web2"]);
+        assert_eq!(inventory.hosts["bastion"].address(), "10.0.0.1");
+        assert_eq!(inventory.hosts["web1"].port, Some(2222));
+        assert!(inventory.parse_yaml("- not a mapping").is_err());
+    }
+
+    #[test]
+    fn config_groups_match_imported_hosts() {
+        let mut config_groups = BTreeMap::new();
+        config_groups.insert("dev".to_string(), vec!["dev.example.com".to_string()]);
+        let inventory = Inventory::load(&config_groups, &[]).unwrap();
+        assert_eq!(inventory.groups["dev"], ["dev.example.com"]);
+        assert!(inventory.members("missing").is_none());
+    }
+
+    #[test]
+    fn merges_explicit_connect_arguments() {
+        let mut host = InventoryHost::named("web1");
+        host.set_variable("ansible_host", "10.0.0.5");
+        host.set_variable("ansible_user", "deploy");
+        host.set_variable("ansible_port", "2222");
+
+        let merged = host.connect_arguments(&json!({ "group": "web", "user": "root", "port": null }));
+        assert_eq!(merged, json!({ "host": "10.0.0.5", "session_id": "web1", "user": "root", "port": 2222 }));
+    }
+
+    #[test]
+    fn expands_the_home_directory() {
+        let home = PathBuf::from(std::env::var_os("HOME").unwrap());
+        assert_eq!(expand_home(Path::new("~/hosts.ini")), home.join("hosts.ini"));
+        assert_eq!(expand_home(Path::new("~")), home);
+        assert_eq!(expand_home(Path::new("/etc/~hosts")), PathBuf::from("/etc/~hosts"));
+        assert_eq!(expand_home(Path::new("~other/hosts")), PathBuf::from("~other/hosts"));
+    }
+
+    #[test]
+    fn group_sessions_match_by_session_id_only() {
+        let mut inventory = Inventory::default();
+        let mut host = InventoryHost::named("web1");
+        host.address = Some("10.0.0.5".to_string());
+        inventory.add_member("web", host);
+        inventory.add_member("web", InventoryHost::named("web2"));
+
+        // `other` is connected to web1's address but was not opened for the group
+        let sessions: HashMap<String, ()> = ["web1", "other", "db1"].into_iter().map(|id| (id.to_string(), ())).collect();
+        assert_eq!(inventory.session_ids("web", &sessions), Some(vec!["web1".to_string()]));
+        assert_eq!(inventory.session_ids("db", &sessions), None);
+    }
+}
@@ -0,0 +1,531 @@
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+
+use crate::files;
+use crate::redact;
+use crate::shell::quote;
+use crate::time;
+use crate::SshSession;
+
+// Background jobs: commands detached from the relay, with their state in files
+//
+// Each job gets a directory on the host holding the command, its pid, the
+// combined stdout/stderr and, once it ends, its exit code. The job runs in its
+// own session (setsid) under nohup, so it outlives the relay and the SSH
+// connection, and any later session to the same host and user can find it.
+
+/// Job directories live here on the host
+const JOBS_DIR: &str = "\"${XDG_STATE_HOME:-$HOME/.local/state}/mcp-ssh/jobs\"";
+const DEFAULT_MAX_OUTPUT_BYTES: u64 = 64 * 1024;
+/// Characters of the command shown in job listings
+const COMMAND_PREVIEW_BYTES: usize = 200;
+
+pub fn tool_definitions() -> Vec<Value> {
+    vec![
+        json!({
+            "name": "ssh_job_start",
+            "description": "Start a long-running command in the background on a session and return a job ID. The job keeps running if the session disconnects; stdout and stderr are captured together",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "command": { "type": "string", "description": "Command to run (with bash)" },
+                    "sudo": { "type": "boolean", "description": "Run with sudo", "default": false }
+                },
+                "required": ["session_id", "command"]
+            }
+        }),
+        json!({
+            "name": "ssh_job_status",
+            "description": "Show whether a background job is running, its exit code once finished, and how much output it has produced",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "job_id": { "type": "string", "description": "Job ID from ssh_job_start" }
+                },
+                "required": ["session_id", "job_id"]
+            }
+        }),
+        json!({
+            "name": "ssh_job_output",
+            "description": "Read a background job's output from a byte offset; pass the returned next_offset to continue where the last read stopped",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "job_id": { "type": "string", "description": "Job ID from ssh_job_start" },
+                    "offset": { "type": "integer", "description": "Byte offset to read from", "default": 0 },
+                    "max_bytes": { "type": "integer", "description": "Maximum number of bytes to return", "default": DEFAULT_MAX_OUTPUT_BYTES }
+                },
+                "required": ["session_id", "job_id"]
+            }
+        }),
+        json!({
+            "name": "ssh_job_kill",
+            "description": "Stop a background job and all processes it started, optionally deleting its files",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "job_id": { "type": "string", "description": "Job ID from ssh_job_start" },
+                    "signal": { "type": "string", "description": "Signal to send", "enum": ["TERM", "INT", "HUP", "KILL"], "default": "TERM" },
+                    "remove": { "type": "boolean", "description": "Also delete the job's directory, including its output", "default": false }
+                },
+                "required": ["session_id", "job_id"]
+            }
+        }),
+        json!({
+            "name": "ssh_job_list",
+            "description": "List background jobs on a session's host, including jobs started from earlier sessions",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" }
+                },
+                "required": ["session_id"]
+            }
+        }),
+    ]
+}
+
+/// A job as read back from its directory
+struct Job {
+    id: String,
+    pid: u32,
+    sudo: bool,
+    started: i64,
+    output_bytes: u64,
+    /// `running`, `exited`, `killed`, or `lost` (gone without recording an exit code)
+    state: String,
+    exit_code: Option<i32>,
+    signal: Option<String>,
+    finished: Option<i64>,
+    command: String,
+}
+
+impl Job {
+    fn to_line(&self) -> String {
+        let state = match (self.state.as_str(), self.exit_code, &self.signal) {
+            ("exited", Some(code), _) => format!("exited {}", code),
+            ("killed", _, Some(signal)) => format!("killed (SIG{})", signal),
+            (state, _, _) => state.to_string(),
+        };
+        format!("{} {:<12} started {} {:>10} bytes  {}{}", self.id, state, time::format_rfc3339(self.started),
+            self.output_bytes, if self.sudo { "[sudo] " } else { "" }, self.command.lines().next().unwrap_or_default())
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "job_id": self.id,
+            "pid": self.pid,
+            "sudo": self.sudo,
+            "state": self.state,
+            "exit_code": self.exit_code,
+            "signal": self.signal,
+            "started": time::format_rfc3339(self.started),
+            "finished": self.finished.map(time::format_rfc3339),
+            "output_bytes": self.output_bytes,
+            "command": self.command
+        })
+    }
+}
+
+/// Handle `ssh_job_start`
+pub fn start(session_id: &str, session: &mut SshSession, arguments: &Value) -> Result<Value> {
+    let command = arguments["command"].as_str()
+        .context("Missing or invalid command parameter - must be a string")?;
+    if command.is_empty() {
+        bail!("Command parameter cannot be empty");
+    }
+    let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
+    session.stats.command();
+
+    let now = time::now_millis();
+    session.requests += 1;
+    let suffix = &files::sha256_hex(format!("{}:{}:{}", session.request_token, session.requests, now).as_bytes())[..6];
+    let job_id = format!("{}-{}", time::format_rfc3339(now / 1000).replace(['-', ':'], ""), suffix);
+
+    // The job starts in the relay's directory with its environment; sudo resets
+    // the environment, so the session's variables are passed on explicitly.
+    // The wrapper records the exit code atomically, so a half-written file is never read
+    let run = if use_sudo {
+        let env: String = session.env.iter().map(|(name, value)| format!("{}={} ", name, quote(value))).collect();
+        Some(format!("SUDO_ASKPASS=/usr/bin/ssh-askpass sudo -A env {}", env))
+    } else {
+        None
+    };
+    let script = start_script(&job_id, command, run.as_deref());
+
+    eprintln!("[SSH] Starting job {} on {}: {} (sudo: {})", job_id, session.host, redact::text(command), use_sudo);
+    let output = session.run_checked(&script, false)
+        .context(format!("Failed to start job on session '{}'", session_id))?;
+    let pid: u32 = output.trim().parse()
+        .context(format!("Unexpected output starting job: {}", output.trim()))?;
+
+    eprintln!("[SSH] Job {} running on {} as pid {}", job_id, session.host, pid);
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": format!("Started job {} on session '{}' (pid {}). Poll it with ssh_job_status and read output with ssh_job_output.",
+                job_id, session_id, pid)
+        }],
+        "metadata": {
+            "session_id": session_id,
+            "job_id": job_id,
+            "pid": pid,
+            "command": command,
+            "sudo": use_sudo
+        }
+    }))
+}
+
+/// Handle `ssh_job_status`
+pub fn status(session_id: &str, session: &mut SshSession, arguments: &Value) -> Result<Value> {
+    let job = find_job(session, job_id_arg(arguments)?)?;
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": job.to_line()
+        }],
+        "metadata": {
+            "session_id": session_id,
+            "job": job.to_json()
+        }
+    }))
+}
+
+/// Handle `ssh_job_output`
+pub fn output(session_id: &str, session: &mut SshSession, arguments: &Value) -> Result<Value> {
+    let job_id = job_id_arg(arguments)?;
+    let offset = files::optional_u64(arguments, "offset")?.unwrap_or(0);
+    let max_bytes = files::optional_u64(arguments, "max_bytes")?.unwrap_or(DEFAULT_MAX_OUTPUT_BYTES).max(1);
+    // tail counts bytes from 1
+    let start = offset.checked_add(1).context("offset is too large")?;
+
+    // Status first, so output read afterwards is at least as recent
+    let job = find_job(session, job_id)?;
+    let encoded = session.run_checked(&format!("{} | base64 -w0; echo", output_command(job_id, start, max_bytes)), false)
+        .context(format!("Failed to read output of job {}", job_id))?;
+    let mut data = BASE64.decode(encoded.trim())
+        .context("Invalid output encoding")?;
+    trim_partial_char(&mut data);
+    let next_offset = offset + data.len() as u64;
+    let complete = job.state != "running" && next_offset >= job.output_bytes;
+
+    let mut text = String::from_utf8_lossy(&data).into_owned();
+    if text.is_empty() {
+        text = format!("(no output after offset {})", offset);
+    }
+    text.push_str(&format!("\n\n[job {}: {}; bytes {}-{} of {}{}]", job_id, job.state, offset, next_offset, job.output_bytes,
+        if complete { ", complete" } else { "" }));
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": text
+        }],
+        "metadata": {
+            "session_id": session_id,
+            "job_id": job_id,
+            "state": job.state,
+            "exit_code": job.exit_code,
+            "offset": offset,
+            "next_offset": next_offset,
+            "output_bytes": job.output_bytes,
+            "complete": complete
+        }
+    }))
+}
+
+/// Handle `ssh_job_kill`
+pub fn kill(session_id: &str, session: &mut SshSession, arguments: &Value) -> Result<Value> {
+    let job_id = job_id_arg(arguments)?;
+    let signal = arguments["signal"].as_str().unwrap_or("TERM");
+    if !["TERM", "INT", "HUP", "KILL"].contains(&signal) {
+        bail!("Invalid signal '{}' - must be one of TERM, INT, HUP, KILL", signal);
+    }
+    let remove = arguments["remove"].as_bool().unwrap_or(false);
+
+    let job = find_job(session, job_id)?;
+    // Signalling a job and removing its files both change the host. A job started
+    // with sudo runs as root and can only be signalled with sudo
+    session.policy.check_operation("ssh_job_kill", job.sudo && job.state == "running", true)?;
+    if job.state == "running" {
+        // The job leads its own process group, which takes its children along
+        session.run_checked(&format!(
+            "echo {sig} > {jobs}/{id}/killed; kill -s {sig} -- -{pid} 2>/dev/null || kill -s {sig} {pid}",
+            sig = signal, jobs = JOBS_DIR, id = job_id, pid = job.pid), job.sudo)
+            .context(format!("Failed to kill job {}", job_id))?;
+        eprintln!("[SSH] Sent SIG{} to job {} on {}", signal, job_id, session.host);
+    }
+    if remove {
+        session.run_checked(&format!("rm -rf -- {}/{}", JOBS_DIR, job_id), false)
+            .context(format!("Failed to remove job {}", job_id))?;
+    }
+
+    let mut text = match job.state.as_str() {
+        "running" => format!("Sent SIG{} to job {}", signal, job_id),
+        state => format!("Job {} was not running ({})", job_id, state),
+    };
+    if remove {
+        text.push_str("; removed its files");
+    }
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": text
+        }],
+        "metadata": {
+            "session_id": session_id,
+            "job_id": job_id,
+            "signalled": job.state == "running",
+            "removed": remove
+        }
+    }))
+}
+
+/// Handle `ssh_job_list`
+pub fn list(session_id: &str, session: &mut SshSession) -> Result<Value> {
+    let jobs = read_jobs(session, "*")?;
+
+    let text = if jobs.is_empty() {
+        format!("No background jobs on session '{}'", session_id)
+    } else {
+        format!("Background jobs on session '{}' ({}):\n{}", session_id, jobs.len(),
+            jobs.iter().map(|job| format!("  {}", job.to_line())).collect::<Vec<_>>().join("\n"))
+    };
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": text
+        }],
+        "metadata": {
+            "session_id": session_id,
+            "jobs": jobs.iter().map(Job::to_json).collect::<Vec<_>>()
+        }
+    }))
+}
+
+fn find_job(session: &mut SshSession, job_id: &str) -> Result<Job> {
+    read_jobs(session, job_id)?.pop()
+        .context(format!("No job '{}' on {}. Use ssh_job_list to see jobs.", job_id, session.host))
+}
+
+/// Read the state of jobs whose ID matches the shell glob `ids`, oldest first
+fn read_jobs(session: &mut SshSession, ids: &str) -> Result<Vec<Job>> {
+    let output = session.run_checked(&read_jobs_script(ids), false)
+        .context("Failed to read job state")?;
+    Ok(parse_jobs(&output))
+}
+
+/// Create the job directory and start `command` detached from the relay; prints its pid.
+/// `run` prefixes the command, e.g. to run it with sudo
+fn start_script(job_id: &str, command: &str, run: Option<&str>) -> String {
+    // The wrapper records the exit code atomically, so a half-written file is never read
+    let wrapper = format!(
+        "{run}bash \"$0/command\" > \"$0/output\" 2>&1 < /dev/null; echo $? > \"$0/exit.tmp\"; mv \"$0/exit.tmp\" \"$0/exit\"",
+        run = run.unwrap_or_default());
+    format!(
+        "jobs={jobs}; d=\"$jobs\"/{id}; mkdir -p -m 700 \"$jobs\" && mkdir -m 700 \"$d\" && \
+         printf %s {code} | base64 -d > \"$d/command\" && date +%s > \"$d/started\"{sudo_marker} || exit 1; \
+         if command -v setsid > /dev/null; then detach=setsid; else detach=; fi; \
+         nohup $detach bash -c {wrapper} \"$d\" > /dev/null 2>&1 & \
+         echo $! > \"$d/pid\"; echo $!",
+        jobs = JOBS_DIR, id = job_id, code = quote(&BASE64.encode(command)), wrapper = quote(&wrapper),
+        sudo_marker = if run.is_some() { " && : > \"$d/sudo\"" } else { "" })
+}
+
+/// Up to `max_bytes` of a job's output, from the 1-based byte `start`
+fn output_command(job_id: &str, start: u64, max_bytes: u64) -> String {
+    format!("tail -c +{start} {jobs}/{id}/output 2>/dev/null | head -c {max}",
+        start = start, jobs = JOBS_DIR, id = job_id, max = max_bytes)
+}
+
+/// Leave a character cut off at the end for the next read
+fn trim_partial_char(data: &mut Vec<u8>) {
+    if let Err(e) = std::str::from_utf8(data) {
+        if e.error_len().is_none() && e.valid_up_to() > 0 {
+            data.truncate(e.valid_up_to());
+        }
+    }
+}
+
+fn read_jobs_script(ids: &str) -> String {
+    // One line per job; the command is base64 so it stays on its line.
+    // Zombies count as gone: an orphaned job may wait to be reaped. Liveness is
+    // checked before the exit file, which the job writes before it ends, so a job
+    // ending in between is not reported as lost.
+    format!(
+        "alive() {{ s=$(ps -o stat= -p \"$1\" 2>/dev/null) && case $s in Z*) false;; esac; }}; \
+         for d in {jobs}/{ids}; do [ -f \"$d/pid\" ] || continue; pid=$(cat \"$d/pid\"); \
+         if alive \"$pid\"; then up=1; else up=; fi; \
+         if [ -f \"$d/exit\" ]; then state=exited; code=$(cat \"$d/exit\"); finished=$(stat -c %Y \"$d/exit\"); \
+         elif [ -f \"$d/killed\" ] && [ -z \"$up\" ]; then state=killed; code=$(cat \"$d/killed\"); finished=$(stat -c %Y \"$d/killed\"); \
+         elif [ -n \"$up\" ]; then state=running; code=-; finished=-; \
+         else state=lost; code=-; finished=-; fi; \
+         printf '%s %s %s %s %s %s %s %s %s\\n' \"${{d##*/}}\" \"$pid\" \"$([ -f \"$d/sudo\" ] && echo 1 || echo 0)\" \
+           \"$(cat \"$d/started\")\" \"$(stat -c %s \"$d/output\" 2>/dev/null || echo 0)\" \"$state\" \"$code\" \"$finished\" \
+           \"$(head -c {preview} \"$d/command\" | base64 -w0)\"; done",
+        jobs = JOBS_DIR, ids = ids, preview = COMMAND_PREVIEW_BYTES)
+}
+
+/// Parse `read_jobs_script` output, skipping malformed lines
+fn parse_jobs(output: &str) -> Vec<Job> {
+    let mut jobs: Vec<Job> = output.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let [id, pid, sudo, started, size, state, code, finished, command] = fields[..] else { return None };
+            let command = String::from_utf8_lossy(&BASE64.decode(command).ok()?).into_owned();
+            Some(Job {
+                id: id.to_string(),
+                pid: pid.parse().ok()?,
+                sudo: sudo == "1",
+                started: started.parse().unwrap_or_default(),
+                output_bytes: size.parse().unwrap_or_default(),
+                state: state.to_string(),
+                exit_code: code.parse().ok().filter(|_| state == "exited"),
+                signal: Some(code.to_string()).filter(|_| state == "killed"),
+                finished: finished.parse().ok(),
+                command,
+            })
+        })
+        .collect();
+    jobs.sort_by(|a, b| a.id.cmp(&b.id));
+    jobs
+}
+
+/// Job IDs are interpolated into remote paths, so only accept what `start` generates
+fn job_id_arg(arguments: &Value) -> Result<&str> {
+    let job_id = arguments["job_id"].as_str()
+        .context("Missing or invalid job_id parameter - must be a string")?;
+    if job_id.is_empty() || !job_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        bail!("Invalid job_id '{}'", job_id);
+    }
+    Ok(job_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process::Command;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Run a job script as the relay would, with the jobs directory under `state`
+    fn run(state: &PathBuf, script: &str) -> String {
+        let output = Command::new("bash").arg("-c").arg(script).env("XDG_STATE_HOME", state).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn wait_for_exit(state: &PathBuf, job_id: &str) -> Job {
+        for _ in 0..200 {
+            let job = parse_jobs(&run(state, &read_jobs_script(job_id))).pop().expect("job is listed");
+            if job.state != "running" {
+                return job;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("job {} did not finish", job_id);
+    }
+
+    fn temp_state(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mcp-ssh-jobs-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn accepts_only_generated_job_ids() {
+        assert_eq!(job_id_arg(&json!({ "job_id": "20261018T211212Z-1107a7" })).unwrap(), "20261018T211212Z-1107a7");
+        for job_id in ["", "*", "../x", "a b", "a/b", "$(id)", "a;b"] {
+            assert!(job_id_arg(&json!({ "job_id": job_id })).is_err(), "{}", job_id);
+        }
+        assert!(job_id_arg(&json!({ "job_id": 5 })).is_err());
+    }
+
+    #[test]
+    fn parses_job_state_lines() {
+        let command = BASE64.encode("make build\nmake test");
+        let output = format!(
+            "j3 300 1 1700000200 0 running - - {c}\n\
+             j1 100 0 1700000000 42 exited 3 1700000100 {c}\n\
+             j2 200 0 1700000000 7 killed TERM 1700000150 {c}\n\
+             j4 400 0 1700000300 0 lost - - {c}\n\
+             j5 not-a-pid 0 0 0 lost - - {c}\n\
+             j6 600 0 0 0 lost - -\n", c = command);
+        let jobs = parse_jobs(&output);
+        assert_eq!(jobs.iter().map(|job| job.id.as_str()).collect::<Vec<_>>(), ["j1", "j2", "j3", "j4"]);
+
+        let exited = &jobs[0];
+        assert_eq!((exited.pid, exited.sudo, exited.output_bytes), (100, false, 42));
+        assert_eq!((exited.exit_code, exited.signal.as_deref(), exited.finished), (Some(3), None, Some(1_700_000_100)));
+        assert_eq!(exited.command, "make build\nmake test");
+        assert!(exited.to_line().contains("exited 3") && exited.to_line().ends_with("make build"));
+
+        let killed = &jobs[1];
+        assert_eq!((killed.exit_code, killed.signal.as_deref()), (None, Some("TERM")));
+        assert!(killed.to_line().contains("killed (SIGTERM)"));
+
+        let running = &jobs[2];
+        assert_eq!((running.sudo, running.exit_code, running.finished), (true, None, None));
+        assert!(running.to_line().contains("[sudo] make build"));
+        assert_eq!(running.to_json()["finished"], Value::Null);
+    }
+
+    #[test]
+    fn runs_a_job_and_reads_its_status_and_output() {
+        let state = temp_state("lifecycle");
+        let pid: u32 = run(&state, &start_script("job-1", "printf 'one\\ntwo\\n'; echo oops >&2; exit 3", None))
+            .trim().parse().unwrap();
+
+        let job = wait_for_exit(&state, "job-1");
+        assert_eq!((job.pid, job.state.as_str(), job.exit_code, job.sudo), (pid, "exited", Some(3), false));
+        assert_eq!(job.output_bytes, 13);
+        assert!(job.finished.is_some());
+
+        assert_eq!(run(&state, &output_command("job-1", 1, 100)), "one\ntwo\noops\n");
+        assert_eq!(run(&state, &output_command("job-1", 5, 4)), "two\n");
+        assert_eq!(run(&state, &output_command("job-1", 14, 100)), "");
+        assert_eq!(run(&state, &output_command("missing", 1, 100)), "");
+        assert!(parse_jobs(&run(&state, &read_jobs_script("missing"))).is_empty());
+        fs::remove_dir_all(&state).unwrap();
+    }
+
+    #[test]
+    fn job_ids_are_unique_directories() {
+        let state = temp_state("unique");
+        run(&state, &start_script("job-1", "true", None));
+        let output = Command::new("bash").arg("-c").arg(start_script("job-1", "true", None))
+            .env("XDG_STATE_HOME", &state).output().unwrap();
+        assert!(!output.status.success());
+        wait_for_exit(&state, "job-1");
+        fs::remove_dir_all(&state).unwrap();
+    }
+
+    #[test]
+    fn keeps_a_split_character_for_the_next_read() {
+        let mut data = "ab€".as_bytes()[..4].to_vec();
+        trim_partial_char(&mut data);
+        assert_eq!(data, b"ab");
+
+        // Invalid bytes are not a split character and stay for lossy decoding
+        let mut data = b"ab\xff".to_vec();
+        trim_partial_char(&mut data);
+        assert_eq!(data, b"ab\xff");
+
+        // A lone partial character is returned rather than never making progress
+        let mut data = "€".as_bytes()[..2].to_vec();
+        trim_partial_char(&mut data);
+        assert_eq!(data.len(), 2);
+    }
+}
@@ -0,0 +1,256 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// Keepalive, heartbeat and idle timeout for sessions
+//
+// ServerAlive options make ssh notice a dead peer (e.g. a NAT mapping that
+// expired) on its own. The heartbeat checks every session in the background so
+// a dead one is reported by `ssh_list_sessions` before a command runs into it,
+// and the idle timeout disconnects sessions nobody has used for a while.
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeepaliveConfig {
+    /// ssh `ServerAliveInterval` in seconds; 0 leaves it to the ssh config
+    #[serde(default = "default_server_alive_interval")]
+    pub server_alive_interval: u64,
+    /// ssh `ServerAliveCountMax`
+    #[serde(default = "default_server_alive_count_max")]
+    pub server_alive_count_max: u64,
+    /// Seconds between heartbeats; 0 turns the heartbeat off
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval: u64,
+    /// Disconnect sessions unused for this many seconds; none by default
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+}
+
+fn default_server_alive_interval() -> u64 {
+    30
+}
+
+fn default_server_alive_count_max() -> u64 {
+    3
+}
+
+fn default_heartbeat_interval() -> u64 {
+    60
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        KeepaliveConfig {
+            server_alive_interval: default_server_alive_interval(),
+            server_alive_count_max: default_server_alive_count_max(),
+            heartbeat_interval: default_heartbeat_interval(),
+            idle_timeout: None,
+        }
+    }
+}
+
+impl KeepaliveConfig {
+    /// ServerAlive options for sessions whose `ssh_options` do not set them
+    pub fn apply(&self, ssh_options: &mut BTreeMap<String, String>) {
+        if self.server_alive_interval == 0 {
+            return;
+        }
+        ssh_options.entry("ServerAliveInterval".to_string())
+            .or_insert_with(|| self.server_alive_interval.to_string());
+        ssh_options.entry("ServerAliveCountMax".to_string())
+            .or_insert_with(|| self.server_alive_count_max.to_string());
+    }
+
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        (self.heartbeat_interval > 0).then(|| Duration::from_secs(self.heartbeat_interval))
+    }
+
+    /// Idle timeout of a session: its `idle_timeout` argument, else the config's; 0 means never
+    pub fn idle_timeout(&self, arguments: &Value) -> Result<Option<Duration>> {
+        let seconds = match &arguments["idle_timeout"] {
+            Value::Null => self.idle_timeout,
+            value => Some(value.as_u64()
+                .context("Invalid idle_timeout parameter - must be a non-negative integer (seconds)")?),
+        };
+        Ok(seconds.filter(|&seconds| seconds > 0).map(Duration::from_secs))
+    }
+}
+
+pub fn schema_property() -> Value {
+    json!({
+        "type": "integer",
+        "description": "Disconnect the session after this many seconds without use (0 = never; defaults to the config's keepalive.idle_timeout)"
+    })
+}
+
+/// What the server knows about a session's connection
+#[derive(Debug)]
+pub struct Health {
+    pub last_used: Instant,
+    /// Last successful heartbeat
+    pub last_heartbeat: Option<Instant>,
+    /// Why the last heartbeat failed; `None` while alive
+    pub failure: Option<String>,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Health {
+    pub fn new(idle_timeout: Option<Duration>) -> Self {
+        Health {
+            last_used: Instant::now(),
+            last_heartbeat: None,
+            failure: None,
+            idle_timeout,
+        }
+    }
+
+    pub fn touch(&mut self) {
+        self.last_used = Instant::now();
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle_timeout.is_some_and(|timeout| self.last_used.elapsed() >= timeout)
+    }
+
+    pub fn record(&mut self, result: Result<(), String>) {
+        match result {
+            Ok(()) => {
+                self.last_heartbeat = Some(Instant::now());
+                self.failure = None;
+            }
+            Err(reason) => self.failure = Some(reason),
+        }
+    }
+
+    pub fn status(&self) -> &'static str {
+        if self.failure.is_some() { "dead" } else { "alive" }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "status": self.status(),
+            "failure": self.failure,
+            "idle_seconds": self.last_used.elapsed().as_secs(),
+            "idle_timeout": self.idle_timeout.map(|timeout| timeout.as_secs()),
+            "last_heartbeat_seconds_ago": self.last_heartbeat.map(|at| at.elapsed().as_secs())
+        })
+    }
+
+    /// One-line summary for session listings
+    pub fn describe(&self) -> String {
+        let mut text = match &self.failure {
+            Some(reason) => format!("dead: {}", reason),
+            None => "alive".to_string(),
+        };
+        text.push_str(&format!(", idle {}s", self.last_used.elapsed().as_secs()));
+        if let Some(timeout) = self.idle_timeout {
+            text.push_str(&format!(" of {}s", timeout.as_secs()));
+        }
+        text
+    }
+}
+
+/// Reject keepalive settings ssh would refuse
+pub fn validate(config: &KeepaliveConfig) -> Result<()> {
+    if config.server_alive_interval > 0 && config.server_alive_count_max == 0 {
+        bail!("keepalive.server_alive_count_max must be at least 1");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::ConnectOptions;
+
+    fn config(json: Value) -> KeepaliveConfig {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn apply_fills_in_only_missing_options() {
+        let mut ssh_options = ConnectOptions::from_arguments(&json!({
+            "ssh_options": { "serveraliveinterval": 5, "ProxyJump": "bastion" }
+        })).unwrap().ssh_options;
+        KeepaliveConfig::default().apply(&mut ssh_options);
+        assert_eq!(ssh_options["ServerAliveInterval"], "5");
+        assert_eq!(ssh_options["ServerAliveCountMax"], "3");
+        assert_eq!(ssh_options["ProxyJump"], "bastion");
+        assert_eq!(ssh_options.len(), 3);
+
+        let mut ssh_options = BTreeMap::from([("ServerAliveCountMax".to_string(), "10".to_string())]);
+        config(json!({ "server_alive_interval": 15 })).apply(&mut ssh_options);
+        assert_eq!(ssh_options["ServerAliveInterval"], "15");
+        assert_eq!(ssh_options["ServerAliveCountMax"], "10");
+    }
+
+    #[test]
+    fn apply_leaves_options_alone_when_disabled() {
+        let mut ssh_options = BTreeMap::new();
+        config(json!({ "server_alive_interval": 0 })).apply(&mut ssh_options);
+        assert!(ssh_options.is_empty());
+    }
+
+    #[test]
+    fn parses_config_with_defaults() {
+        let parsed = config(json!({}));
+        assert_eq!((parsed.server_alive_interval, parsed.server_alive_count_max, parsed.heartbeat_interval), (30, 3, 60));
+        assert_eq!(parsed.idle_timeout, None);
+        assert_eq!(parsed.heartbeat_interval(), Some(Duration::from_secs(60)));
+        assert_eq!(config(json!({ "heartbeat_interval": 0 })).heartbeat_interval(), None);
+        assert!(serde_json::from_value::<KeepaliveConfig>(json!({ "heartbeat": 5 })).is_err());
+    }
+
+    #[test]
+    fn validates_count_max() {
+        assert!(validate(&KeepaliveConfig::default()).is_ok());
+        assert!(validate(&config(json!({ "server_alive_count_max": 0 }))).is_err());
+        assert!(validate(&config(json!({ "server_alive_interval": 0, "server_alive_count_max": 0 }))).is_ok());
+    }
+
+    #[test]
+    fn idle_timeout_argument_overrides_the_config() {
+        let configured = config(json!({ "idle_timeout": 600 }));
+        assert_eq!(configured.idle_timeout(&json!({})).unwrap(), Some(Duration::from_secs(600)));
+        assert_eq!(configured.idle_timeout(&json!({ "idle_timeout": 30 })).unwrap(), Some(Duration::from_secs(30)));
+        assert_eq!(configured.idle_timeout(&json!({ "idle_timeout": 0 })).unwrap(), None);
+        assert_eq!(KeepaliveConfig::default().idle_timeout(&json!({})).unwrap(), None);
+        assert_eq!(config(json!({ "idle_timeout": 0 })).idle_timeout(&json!({})).unwrap(), None);
+        for invalid in [json!(-1), json!("60"), json!(1.5)] {
+            assert!(configured.idle_timeout(&json!({ "idle_timeout": invalid })).is_err());
+        }
+    }
+
+    #[test]
+    fn records_heartbeats() {
+        let mut health = Health::new(None);
+        assert_eq!((health.status(), health.last_heartbeat.is_some()), ("alive", false));
+
+        health.record(Err("control socket gone".to_string()));
+        assert_eq!(health.status(), "dead");
+        assert!(health.describe().starts_with("dead: control socket gone, idle "));
+        assert_eq!(health.to_json()["failure"], "control socket gone");
+
+        health.record(Ok(()));
+        assert_eq!((health.status(), health.failure.as_deref()), ("alive", None));
+        assert_eq!(health.to_json()["last_heartbeat_seconds_ago"], 0);
+    }
+
+    #[test]
+    fn idles_only_with_a_timeout() {
+        let mut health = Health::new(Some(Duration::from_secs(60)));
+        assert!(!health.is_idle());
+        assert!(health.describe().ends_with(" of 60s"));
+        health.last_used = Instant::now() - Duration::from_secs(61);
+        assert!(health.is_idle());
+        health.touch();
+        assert!(!health.is_idle());
+
+        let mut health = Health::new(None);
+        health.last_used = Instant::now() - Duration::from_secs(86_400);
+        assert!(!health.is_idle());
+        assert_eq!(health.to_json()["idle_timeout"], Value::Null);
+    }
+}
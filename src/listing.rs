@@ -0,0 +1,331 @@
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+
+use crate::files;
+use crate::glob;
+use crate::shell::quote;
+use crate::time::format_rfc3339;
+use crate::SshSession;
+
+// Structured directory listings and stat, built on `find -printf`
+
+const DEFAULT_MAX_ENTRIES: u64 = 1000;
+
+/// Most entries fetched when a `pattern` has to be matched here rather than by find
+const MAX_SCANNED_ENTRIES: usize = 100_000;
+
+/// type, size, mode, owner, group, mtime, symlink target, path - all NUL-terminated
+const FIND_FORMAT: &str = r"%y\0%s\0%m\0%u\0%g\0%T@\0%l\0%P\0";
+const FIELDS: usize = 8;
+
+pub fn tool_definitions() -> Vec<Value> {
+    vec![
+        json!({
+            "name": "ssh_list_dir",
+            "description": "List a remote directory with structured entries (name, type, size, mode, owner, mtime, symlink target)",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "path": { "type": "string", "description": "Remote directory" },
+                    "depth": { "type": "integer", "description": "Recursion depth (1 lists direct children only)", "default": 1 },
+                    "pattern": { "type": "string", "description": "Only return entries whose name or relative path matches this glob" },
+                    "include_hidden": { "type": "boolean", "description": "Include dot files", "default": true },
+                    "max_entries": { "type": "integer", "description": "Maximum number of entries to return; larger listings are cut short on the host and report no total", "default": DEFAULT_MAX_ENTRIES },
+                    "sudo": { "type": "boolean", "description": "List with sudo", "default": false }
+                },
+                "required": ["session_id", "path"]
+            }
+        }),
+        json!({
+            "name": "ssh_stat",
+            "description": "Get structured metadata (type, size, mode, owner, mtime, symlink target) of a remote path",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "path": { "type": "string", "description": "Remote path" },
+                    "sudo": { "type": "boolean", "description": "Stat with sudo", "default": false }
+                },
+                "required": ["session_id", "path"]
+            }
+        }),
+    ]
+}
+
+#[derive(Debug)]
+struct Entry {
+    path: String,
+    kind: &'static str,
+    size: u64,
+    mode: String,
+    owner: String,
+    group: String,
+    mtime: i64,
+    target: Option<String>,
+}
+
+impl Entry {
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.path.rsplit('/').next().unwrap_or(&self.path),
+            "path": self.path,
+            "type": self.kind,
+            "size": self.size,
+            "mode": self.mode,
+            "owner": self.owner,
+            "group": self.group,
+            "mtime": self.mtime,
+            "symlink_target": self.target,
+        })
+    }
+
+    fn to_line(&self) -> String {
+        let mut line = format!("{:<9} {} {}:{} {:>10} {} {}",
+            self.kind, self.mode, self.owner, self.group, self.size, format_rfc3339(self.mtime), self.path);
+        if let Some(target) = &self.target {
+            line.push_str(&format!(" -> {}", target));
+        }
+        line
+    }
+}
+
+/// Handle `ssh_list_dir`
+pub fn list_dir(session_id: &str, session: &mut SshSession, arguments: &Value) -> Result<Value> {
+    let path = files::path_arg(arguments)?;
+    let depth = files::optional_u64(arguments, "depth")?.unwrap_or(1);
+    if depth == 0 {
+        bail!("depth must be at least 1");
+    }
+    let pattern = arguments["pattern"].as_str();
+    let include_hidden = arguments["include_hidden"].as_bool().unwrap_or(true);
+    let max_entries = files::optional_u64(arguments, "max_entries")?.unwrap_or(DEFAULT_MAX_ENTRIES) as usize;
+    let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
+
+    // Hidden entries are pruned by find; globs are matched here, so listings
+    // with a pattern fetch more entries than they return
+    let limit = if pattern.is_some() { MAX_SCANNED_ENTRIES } else { max_entries.saturating_add(1) };
+    let entries = run_find(session, &list_command(path, depth, include_hidden), limit, use_sudo)
+        .context(format!("Failed to list {}", path))?;
+    let complete = entries.len() < limit;
+
+    let mut entries: Vec<Entry> = entries.into_iter()
+        .filter(|entry| pattern.is_none_or(|pattern| glob::path_match(pattern, &entry.path)))
+        .collect();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    // Unknown once find's output was cut short
+    let total = complete.then_some(entries.len());
+    let truncated = !complete || entries.len() > max_entries;
+    entries.truncate(max_entries);
+
+    eprintln!("[MCP] Listed {} entries of {} on session '{}' (truncated: {})", entries.len(), path, session_id, truncated);
+
+    let mut text = if entries.is_empty() {
+        format!("No entries in {}", path)
+    } else {
+        let count = match total {
+            Some(total) => format!("{} entries", total),
+            None => format!("first {} entries", entries.len()),
+        };
+        format!("{} ({}):\n{}", path, count,
+            entries.iter().map(|entry| format!("  {}", entry.to_line())).collect::<Vec<_>>().join("\n"))
+    };
+    match (truncated, total) {
+        (true, Some(total)) => text.push_str(&format!("\n  ... {} more entries not shown (max_entries = {})", total - max_entries, max_entries)),
+        (true, None) => text.push_str(&format!("\n  ... more entries not shown (max_entries = {})", max_entries)),
+        (false, _) => {}
+    }
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": text
+        }],
+        "metadata": {
+            "session_id": session_id,
+            "path": path,
+            "total": total,
+            "truncated": truncated,
+            "entries": entries.iter().map(Entry::to_json).collect::<Vec<_>>()
+        }
+    }))
+}
+
+/// Handle `ssh_stat`
+pub fn stat(session_id: &str, session: &mut SshSession, arguments: &Value) -> Result<Value> {
+    let path = files::path_arg(arguments)?;
+    let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
+
+    // %P is empty for the starting point itself, so report the given path
+    let mut entries = run_find(session, &stat_command(path), 1, use_sudo)
+        .context(format!("Failed to stat {}", path))?;
+    let mut entry = entries.pop().context(format!("No stat output for {}", path))?;
+    entry.path = path.to_string();
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": entry.to_line()
+        }],
+        "metadata": {
+            "session_id": session_id,
+            "entry": entry.to_json()
+        }
+    }))
+}
+
+fn list_command(path: &str, depth: u64, include_hidden: bool) -> String {
+    format!("[ -d {p} ] || {{ echo \"Not a directory: \"{p}; exit 1; }}; find {p} -mindepth 1 -maxdepth {d} {h}-printf '{f}'",
+        p = quote(path), d = depth, h = if include_hidden { "" } else { "-name '.*' -prune -o " }, f = FIND_FORMAT)
+}
+
+fn stat_command(path: &str) -> String {
+    format!("[ -e {p} ] || [ -L {p} ] || {{ echo \"No such file or directory: \"{p}; exit 1; }}; find {p} -maxdepth 0 -printf '{f}'",
+        p = quote(path), f = FIND_FORMAT)
+}
+
+/// Run a find command printing `FIND_FORMAT` and parse at most `limit` entries
+fn run_find(session: &mut SshSession, command: &str, limit: usize, use_sudo: bool) -> Result<Vec<Entry>> {
+    // base64 so arbitrary file names survive the line-based relay; find's own
+    // errors (e.g. unreadable subdirectories) would corrupt it
+    let output = session.run_checked(&format!("{{ {} 2>/dev/null; }} | head -z -n {} | base64 -w0 && echo",
+        command, limit.saturating_mul(FIELDS)), use_sudo)?;
+    let data = BASE64.decode(output.trim()).context("Invalid listing encoding")?;
+    parse_entries(&data)
+}
+
+/// Parse `FIND_FORMAT` records
+fn parse_entries(data: &[u8]) -> Result<Vec<Entry>> {
+    // A failing test prints its message instead of find output
+    if !data.is_empty() && !data.ends_with(&[0]) {
+        bail!("{}", String::from_utf8_lossy(data).trim());
+    }
+
+    let fields: Vec<String> = data.split(|&b| b == 0)
+        .map(|field| String::from_utf8_lossy(field).into_owned())
+        .collect();
+
+    Ok(fields.chunks_exact(FIELDS)
+        .map(|chunk| Entry {
+            kind: match chunk[0].as_str() {
+                "f" => "file",
+                "d" => "directory",
+                "l" => "symlink",
+                "p" => "fifo",
+                "s" => "socket",
+                "c" => "char_device",
+                "b" => "block_device",
+                _ => "unknown",
+            },
+            size: chunk[1].parse().unwrap_or_default(),
+            mode: format!("{:0>4}", chunk[2]),
+            owner: chunk[3].clone(),
+            group: chunk[4].clone(),
+            mtime: chunk[5].split('.').next().and_then(|secs| secs.parse().ok()).unwrap_or_default(),
+            target: Some(chunk[6].clone()).filter(|target| !target.is_empty()),
+            path: chunk[7].clone(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mcp-ssh-listing-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn find(command: &str) -> Result<Vec<Entry>> {
+        let output = Command::new("bash").arg("-c").arg(format!("{{ {} 2>/dev/null; }}", command)).output().unwrap();
+        let mut entries = parse_entries(&output.stdout)?;
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+
+    #[test]
+    fn lists_awkward_names_symlinks_and_directories() {
+        let dir = temp_dir("list");
+        fs::create_dir_all(dir.join("empty dir")).unwrap();
+        fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+        fs::write(dir.join("a b.txt"), "12345").unwrap();
+        fs::write(dir.join("new\nline"), "").unwrap();
+        fs::set_permissions(dir.join("a b.txt"), fs::Permissions::from_mode(0o640)).unwrap();
+        symlink("a b.txt", dir.join("link")).unwrap();
+        symlink("missing", dir.join("dangling")).unwrap();
+
+        let entries = find(&list_command(dir.to_str().unwrap(), 1, true)).unwrap();
+        let paths: Vec<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["a b.txt", "dangling", "empty dir", "link", "new\nline", "sub"]);
+
+        let file = &entries[0];
+        assert_eq!((file.kind, file.size, file.mode.as_str(), file.target.as_deref()), ("file", 5, "0640", None));
+        assert!(file.mtime > 0);
+        assert_eq!(entries[1].target.as_deref(), Some("missing"));
+        assert_eq!(entries[2].kind, "directory");
+        assert_eq!((entries[3].kind, entries[3].target.as_deref()), ("symlink", Some("a b.txt")));
+        assert_eq!(entries[4].to_json()["name"], "new\nline");
+        assert!(entries[3].to_line().ends_with(" link -> a b.txt"));
+
+        let deeper = find(&list_command(dir.to_str().unwrap(), 2, true)).unwrap();
+        assert!(deeper.iter().any(|entry| entry.path == "sub/deeper"));
+        assert_eq!(deeper.iter().find(|entry| entry.path == "sub/deeper").unwrap().to_json()["name"], "deeper");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn empty_directories_and_hidden_files() {
+        let dir = temp_dir("hidden");
+        assert!(find(&list_command(dir.to_str().unwrap(), 1, true)).unwrap().is_empty());
+
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join(".git/config"), "").unwrap();
+        fs::write(dir.join("visible"), "").unwrap();
+        assert_eq!(find(&list_command(dir.to_str().unwrap(), 2, true)).unwrap().len(), 3);
+        let shown = find(&list_command(dir.to_str().unwrap(), 2, false)).unwrap();
+        assert_eq!(shown.iter().map(|entry| entry.path.as_str()).collect::<Vec<_>>(), ["visible"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stat_reports_the_entry_itself() {
+        let dir = temp_dir("stat");
+        symlink("target", dir.join("link")).unwrap();
+        let entries = find(&stat_command(dir.join("link").to_str().unwrap())).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].kind, entries[0].path.as_str(), entries[0].target.as_deref()), ("symlink", "", Some("target")));
+        assert_eq!(find(&stat_command(dir.to_str().unwrap())).unwrap()[0].kind, "directory");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failing_checks_become_errors() {
+        let dir = temp_dir("errors");
+        fs::write(dir.join("file"), "").unwrap();
+        let error = find(&list_command(dir.join("file").to_str().unwrap(), 1, true)).unwrap_err().to_string();
+        assert!(error.starts_with("Not a directory: "), "{}", error);
+        let error = find(&stat_command(dir.join("missing").to_str().unwrap())).unwrap_err().to_string();
+        assert!(error.starts_with("No such file or directory: "), "{}", error);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_records_field_by_field() {
+        let entries = parse_entries(b"p\x000\x00644\x00root\x00wheel\x001700000000.5\x00\x00fifo\x00").unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!((entry.kind, entry.mode.as_str(), entry.owner.as_str(), entry.group.as_str()), ("fifo", "0644", "root", "wheel"));
+        assert_eq!(entry.mtime, 1_700_000_000);
+        assert!(parse_entries(b"").unwrap().is_empty());
+    }
+}
@@ -1,65 +1,296 @@
 use serde_json::{json, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::io::{self, BufRead, BufReader, Write};
-use std::process::{Command, Stdio, Child, ChildStdin, ChildStdout};
+use std::process::{Stdio, Child, ChildStdin, ChildStdout};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use anyhow::{Result, Context, bail};
 
+mod approval;
+mod audit;
+mod config;
+mod control;
+mod copy;
+mod edit;
+mod fanout;
+mod files;
+mod forward;
+mod glob;
+mod inventory;
+mod jobs;
+mod keepalive;
+mod listing;
+mod options;
+mod persist;
+mod policy;
+mod protocol;
+mod readonly;
+mod redact;
+mod shell;
+mod ssh_config;
+mod state;
+mod stats;
+mod sync;
+mod time;
+mod transfer;
+
+use approval::ApprovalRequest;
+use audit::AuditLog;
+use config::Config;
+use control::{ControlMaster, MuxClient};
+use forward::{ForwardKind, ForwardTable};
+use keepalive::Health;
+use options::ConnectOptions;
+use persist::Persistence;
+use policy::Policy;
+use state::SessionStore;
+use stats::SessionStats;
+
 // MCP Server for SSH Sessions with bash relay
 
 #[derive(Debug)]
 struct SshSession {
     host: String,
+    options: ConnectOptions,
+    control: ControlMaster,
+    forwards: ForwardTable,
+    profile: Option<String>,
+    policy: Policy,
+    /// Prefix of request ids, so command output cannot fake the end-of-output marker
+    request_token: String,
+    requests: u64,
+    /// Set when the relay runs inside a remote tmux/screen session
+    persist: Option<Persistence>,
+    /// Working directory and environment variables relay commands run with
+    cwd: Option<String>,
+    env: BTreeMap<String, String>,
+    health: Health,
+    stats: SessionStats,
+    /// X11 display the relay reported, if forwarding works
+    x11_display: Option<String>,
     child: Child,
     stdin: ChildStdin,
     reader: BufReader<ChildStdout>,
 }
 
+/// A session's master connection, working directory and environment, for side
+/// channels that run without holding the sessions lock
+#[derive(Debug, Clone)]
+struct SideChannel {
+    host: String,
+    client: MuxClient,
+    cwd: Option<String>,
+    env: BTreeMap<String, String>,
+}
+
+impl SideChannel {
+    /// Command running `command` over a separate channel of the master connection,
+    /// independent of the relay (for streaming and parallel work). Like relay
+    /// commands, it runs in the session's `cwd` with its `env`.
+    fn command(&self, command: &str, use_sudo: bool) -> std::process::Command {
+        let mut setup = String::new();
+        if let Some(cwd) = &self.cwd {
+            setup.push_str(&format!("cd -- {} || exit 1; ", shell::quote(cwd)));
+        }
+        for (name, value) in &self.env {
+            setup.push_str(&format!("export {}={}; ", name, shell::quote(value)));
+        }
+        self.home_command(&format!("{}{}", setup, command), use_sudo)
+    }
+    
+    /// Side channel command starting in the home directory, without the session's `env`
+    fn home_command(&self, command: &str, use_sudo: bool) -> std::process::Command {
+        let remote = if use_sudo {
+            format!("SUDO_ASKPASS=/usr/bin/ssh-askpass sudo -A bash -c {}", shell::quote(command))
+        } else {
+            format!("bash -c {}", shell::quote(command))
+        };
+        
+        let mut side = self.client.ssh_command();
+        if use_sudo {
+            // The askpass dialog needs X11 forwarding on this channel too
+            side.arg("-Y");
+        }
+        side.args([&self.host, &remote]);
+        side
+    }
+}
+
 impl SshSession {
-    fn connect(host: &str) -> Result<Self> {
-        eprintln!("[SSH] Connecting to {}", host);
+    fn connect(host: &str, options: ConnectOptions, mut persist: Option<Persistence>) -> Result<Self> {
+        eprintln!("[SSH] Connecting to {}", options.display_target(host));
         
         // Create bash relay script
         let relay_script = r#"#!/bin/bash
 
 export SUDO_ASKPASS='/usr/bin/ssh-askpass'
 
-# Check X11 forwarding
-[ -n "$DISPLAY" ] && echo "X11:$DISPLAY" >&2 || echo "X11:NONE" >&2
-
-# Command execution
+# Command execution: interpreter, code
 run() {
-    bash -c "$*" 2>&1
-    echo "<<<EXIT:$?>>>"
+    "$1" "$(code_flag "$1")" "$2"
 }
 
-# Sudo command execution  
+# Sudo command execution
 sudo_run() {
-    sudo -A bash -c "$*" 2>&1
-    echo "<<<EXIT:$?>>>"
+    sudo -A "$1" "$(code_flag "$1")" "$2"
+}
+
+code_flag() {
+    if [ "$1" = perl ]; then printf '%s' -e; else printf '%s' -c; fi
+}
+
+report_x11() {
+    [ -n "$DISPLAY" ] && echo "X11:$DISPLAY" >&2 || echo "X11:NONE" >&2
+}
+
+# Main loop: one request per line,
+# "<id> <run|sudo_run> <interpreter> <byte length> <base64 code or -> <base64 stdin or ->".
+# Empty fields are sent as "-", since read collapses runs of spaces.
+# The code is decoded and executed exactly once, never eval'ed.
+# "cd", "export" and "unset" with interpreter "-" change the directory and
+# environment of the relay itself, which later commands inherit.
+relay_loop() {
+    while IFS=' ' read -r id mode interpreter length payload input; do
+        if [ "$payload" = - ]; then
+            code=
+        else
+            # The trailing x keeps newlines that command substitution would strip
+            code=$(printf '%s' "$payload" | base64 -d; printf x)
+            code=${code%x}
+        fi
+        
+        # A persistent relay uses the X11 forwarding of the connection attached now
+        if [ -n "$session_dir" ]; then
+            DISPLAY=$(cat "$session_dir/display" 2>/dev/null)
+            export DISPLAY
+        fi
+        
+        if [ "$(printf '%s' "$code" | wc -c)" != "$length" ]; then
+            echo "Relay: corrupted command payload"
+            status=125
+        else
+            case "$mode:$interpreter" in
+                run:bash|run:sh|run:python3|run:perl|sudo_run:bash|sudo_run:sh|sudo_run:python3|sudo_run:perl)
+                    if [ "$input" = - ]; then
+                        "$mode" "$interpreter" "$code" < /dev/null 2>&1
+                    else
+                        printf '%s' "$input" | base64 -d | "$mode" "$interpreter" "$code" 2>&1
+                    fi
+                    status=$?
+                    ;;
+                cd:-)
+                    if [ -d "$code" ]; then
+                        cd -- "$code" 2>&1 && pwd; status=$?
+                    else
+                        echo "No such directory: $code"; status=1
+                    fi
+                    ;;
+                export:-) export -- "$code" 2>&1; status=$? ;;
+                unset:-) unset -v -- "$code" 2>&1; status=$? ;;
+                *) echo "Relay: unknown mode $mode or interpreter $interpreter"; status=125 ;;
+            esac
+        fi
+        
+        # Always on a line of its own, even after output without a trailing newline
+        printf '\n<<<MCP-EXIT:%s:%d>>>\n' "$id" "$status"
+    done
+}
+
+# Persistent sessions: "attach <name> <tmux|screen>" is the SSH session's command.
+# It starts "serve <dir>" inside tmux or screen unless that is still running, and
+# bridges this connection to it through two FIFOs in the session directory.
+mux_has() {
+    case "$1" in
+        tmux) tmux has-session -t "=$2" 2>/dev/null ;;
+        screen) screen -ls "$2" 2>/dev/null | grep -q "[0-9]\.$2[[:space:]]" ;;
+    esac
+}
+
+mux_start() {
+    case "$1" in
+        tmux) tmux new-session -d -s "$2" "$(printf '%q ' bash "$3/relay.sh" serve "$3")" ;;
+        screen) screen -dmS "$2" bash "$3/relay.sh" serve "$3" ;;
+    esac
+}
+
+attach() {
+    name=$1
+    mux=$2
+    dir="${XDG_STATE_HOME:-$HOME/.local/state}/mcp-ssh/sessions/$name"
+    command -v "$mux" > /dev/null || { echo "Relay error: $mux is not installed" >&2; exit 1; }
+    
+    if mux_has "$mux" "$name" && [ -p "$dir/in" ]; then
+        echo "SESSION:ATTACHED" >&2
+    else
+        mkdir -p -m 700 "$dir" && rm -f "$dir/in" "$dir/out" && mkfifo -m 600 "$dir/in" "$dir/out" \
+            && cp "$0" "$dir/relay.sh" && mux_start "$mux" "$name" "$dir" \
+            || { echo "Relay error: failed to start $mux session $name" >&2; exit 1; }
+        echo "SESSION:NEW" >&2
+    fi
+    
+    rm -f -- "$0"
+    printf '%s' "$DISPLAY" > "$dir/display"
+    # Only one connection reads the output; a previous one may not have noticed it is gone
+    [ -f "$dir/reader" ] && kill "$(cat "$dir/reader")" 2>/dev/null
+    cat "$dir/out" &
+    reader=$!
+    echo "$reader" > "$dir/reader"
+    
+    report_x11
+    echo "READY" >&2
+    cat > "$dir/in"
+    kill "$reader" 2>/dev/null
 }
 
-echo "READY" >&2
+serve() {
+    session_dir=$1
+    # Holding the read end too means output never fails while nobody is attached;
+    # it waits in the FIFO for the next connection
+    exec 3<> "$session_dir/out"
+    while :; do
+        relay_loop < "$session_dir/in" >&3
+    done
+}
 
-# Main loop
-while IFS= read -r line; do
-    eval "$line"
-done
+case "$1" in
+    attach) attach "$2" "$3" ;;
+    serve) serve "$2" ;;
+    *)
+        # The uploaded copy is not needed once running; bash reads on from the open file
+        rm -f -- "$0"
+        report_x11
+        echo "READY" >&2
+        relay_loop
+        ;;
+esac
 "#;
         
-        // Write and upload script
-        let local_script = "/tmp/mcp_ssh_relay.sh";
-        std::fs::write(local_script, relay_script)
+        // Authenticate once; the relay upload and the session reuse this connection
+        let control = ControlMaster::start(host, &options)?;
+        
+        // Write and upload script. Each connection has its own files, as sessions
+        // (also to the same host) may be connecting at the same time
+        let local_script = control.dir().join("relay.sh");
+        std::fs::write(&local_script, relay_script)
             .context("Failed to write relay script to local temp file")?;
         
-        let remote_script = "/tmp/mcp_ssh_relay_remote.sh";
-        eprintln!("[SSH] Uploading relay script to {}", host);
+        let mktemp = control.ssh_command()
+            .args([host, "mktemp \"${TMPDIR:-/tmp}/mcp_ssh_relay.XXXXXX\""])
+            .stdin(Stdio::null())
+            .output()
+            .context("Failed to execute ssh command")?;
+        let remote_script = String::from_utf8_lossy(&mktemp.stdout).trim().to_string();
+        if !mktemp.status.success() || !remote_script.starts_with('/') {
+            bail!("Failed to create a temp file for the relay script on {}: {}", host, String::from_utf8_lossy(&mktemp.stderr).trim());
+        }
+        eprintln!("[SSH] Uploading relay script to {}:{}", host, remote_script);
         
-        let scp = Command::new("scp")
-            .args(["-q", "-o", "ConnectTimeout=10", "-o", "StrictHostKeyChecking=no", 
-                   local_script, &format!("{}:{}", host, remote_script)])
+        let scp = control.scp_command()
+            .arg("-q")
+            .arg(&local_script)
+            .arg(format!("{}:{}", host, remote_script))
             .output()
             .context("Failed to execute scp command")?;
         
@@ -70,9 +301,10 @@ done
         
         // Start SSH session
         eprintln!("[SSH] Starting SSH session with {}", host);
-        let mut child = Command::new("ssh")
-            .args(["-Y", "-o", "ConnectTimeout=10", "-o", "StrictHostKeyChecking=no",
-                   host, "bash", remote_script])
+        let mut child = control.ssh_command()
+            .arg("-Y")
+            .args([host, "bash", &shell::quote(&remote_script)])
+            .args(persist.iter().flat_map(Persistence::relay_args))
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
@@ -88,88 +320,167 @@ done
         let host_clone = host.to_string();
         thread::spawn(move || {
             let reader = BufReader::new(stderr);
-            for line in reader.lines() {
-                if let Ok(line) = line {
-                    eprintln!("[{}] {}", host_clone, line);
-                    if line.contains("READY") {
-                        let _ = tx.send(());
-                    }
+            let mut reattached = false;
+            let mut x11_display = None;
+            for line in reader.lines().map_while(Result::ok) {
+                eprintln!("[{}] {}", host_clone, redact::text(&line));
+                if line.contains("READY") {
+                    let _ = tx.send(Ok((reattached, x11_display.take())));
+                } else if let Some(display) = line.strip_prefix("X11:") {
+                    x11_display = Some(display.to_string()).filter(|display| display != "NONE");
+                } else if line == "SESSION:ATTACHED" {
+                    reattached = true;
+                } else if let Some(error) = line.strip_prefix("Relay error: ") {
+                    let _ = tx.send(Err(error.to_string()));
                 }
             }
         });
         
         // Wait for ready signal with timeout
         eprintln!("[SSH] Waiting for relay to be ready on {}", host);
-        match rx.recv_timeout(Duration::from_secs(10)) {
-            Ok(_) => {
+        let ready_timeout = options.connect_timeout();
+        let x11_display = match rx.recv_timeout(Duration::from_secs(ready_timeout)) {
+            Ok(Ok((reattached, x11_display))) => {
                 eprintln!("[SSH] Relay ready on {}", host);
+                if let Some(persist) = persist.as_mut() {
+                    persist.reattached = reattached;
+                }
+                x11_display
+            }
+            Ok(Err(error)) => {
+                let _ = child.kill();
+                bail!("SSH relay failed to start on {}: {}", host, error);
             }
             Err(_) => {
                 // Try to kill the child process
                 let _ = child.kill();
-                bail!("SSH relay failed to start on {} within 10 seconds", host);
+                bail!("SSH relay failed to start on {} within {} seconds", host, ready_timeout);
             }
-        }
+        };
         
-        Ok(SshSession {
+        let mut session = SshSession {
             host: host.to_string(),
+            options,
+            control,
+            forwards: ForwardTable::default(),
+            profile: None,
+            policy: Policy::default(),
+            request_token: request_token(host),
+            requests: 0,
+            persist,
+            cwd: None,
+            env: BTreeMap::new(),
+            health: Health::new(None),
+            stats: SessionStats::new(),
+            x11_display,
             child,
             stdin,
             reader: BufReader::new(stdout),
-        })
+        };
+        
+        // Output of a command that ran while no server was attached is still
+        // queued; a no-op request reads past it
+        if let Some(description) = session.persist.as_ref().map(Persistence::describe) {
+            eprintln!("[SSH] Using {} on {}", description, host);
+            if session.persist.as_ref().is_some_and(|persist| persist.reattached) {
+                session.run_checked("true", false)
+                    .context(format!("Failed to resynchronize with {}", description))?;
+            }
+        }
+        
+        Ok(session)
     }
     
     fn execute(&mut self, command: &str, use_sudo: bool) -> Result<(String, i32)> {
-        eprintln!("[SSH] Executing on {}: {} (sudo: {})", self.host, command, use_sudo);
-        
-        // Escape command for safe eval in bash
-        let escaped = command
-            .replace('\\', "\\\\")
-            .replace('"', "\\\"")
-            .replace('$', "\\$")
-            .replace('`', "\\`");
+        self.run_script("bash", command, None, use_sudo)
+    }
+    
+    /// Run `code` with one of the relay's `INTERPRETERS`, optionally feeding it `input` on stdin
+    fn run_script(&mut self, interpreter: &str, code: &str, input: Option<&[u8]>, use_sudo: bool) -> Result<(String, i32)> {
+        eprintln!("[SSH] Executing on {} with {}: {} (sudo: {}, stdin: {} bytes)",
+            self.host, interpreter, redact::text(code), use_sudo, input.map_or(0, <[u8]>::len));
         
-        let request = if use_sudo {
-            format!(r#"sudo_run "{}""#, escaped)
-        } else {
-            format!(r#"run "{}""#, escaped)
+        if !INTERPRETERS.contains(&interpreter) {
+            bail!("Unsupported interpreter '{}'. Supported: {}", interpreter, INTERPRETERS.join(", "));
+        }
+        let (output, exit_code) = self.relay(if use_sudo { "sudo_run" } else { "run" }, interpreter, code, input)?;
+        if use_sudo && exit_code == 0 {
+            self.stats.last_sudo = Some(Instant::now());
+        }
+        Ok((output, exit_code))
+    }
+    
+    /// Change the directory later relay commands run in
+    fn set_cwd(&mut self, dir: &str) -> Result<()> {
+        let (output, exit_code) = self.relay("cd", "-", dir, None)?;
+        if exit_code != 0 {
+            bail!("Cannot change directory to {} on {}: {}", dir, self.host, output.trim());
+        }
+        // The relay prints where it ended up, so relative directories are stored absolute
+        let cwd = output.lines().last().map(str::trim).filter(|line| line.starts_with('/'));
+        self.cwd = Some(cwd.unwrap_or(dir).to_string());
+        Ok(())
+    }
+    
+    /// Set (or with `None` unset) an environment variable for later relay commands
+    fn set_env(&mut self, name: &str, value: Option<&str>) -> Result<()> {
+        let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            bail!("Invalid environment variable name '{}'", name);
+        }
+        self.policy.check_env(name)?;
+        let (output, exit_code) = match value {
+            Some(value) => self.relay("export", "-", &format!("{}={}", name, value), None)?,
+            None => self.relay("unset", "-", name, None)?,
         };
-        
-        // Send command
-        writeln!(self.stdin, "{}", request)
+        if exit_code != 0 {
+            bail!("Cannot set {} on {}: {}", name, self.host, output.trim());
+        }
+        match value {
+            Some(value) => self.env.insert(name.to_string(), value.to_string()),
+            None => self.env.remove(name),
+        };
+        Ok(())
+    }
+    
+    /// Send one request to the relay and read its output up to the end marker
+    fn relay(&mut self, mode: &str, interpreter: &str, code: &str, input: Option<&[u8]>) -> Result<(String, i32)> {
+        self.requests += 1;
+        let id = format!("{}-{}", self.request_token, self.requests);
+        let request = protocol::encode_request(&id, mode, interpreter, code, input)?;
+        self.stdin.write_all(request.as_bytes())
             .context(format!("Failed to send command to {}", self.host))?;
+        self.stats.sent(request.len());
         self.stdin.flush()
             .context(format!("Failed to flush stdin to {}", self.host))?;
         
-        // Read output with timeout
+        // Read output up to the marker line
         let mut output = Vec::new();
-        let mut exit_code = 0;
         let mut lines_read = 0;
         
-        loop {
-            let mut line = String::new();
-            match self.reader.read_line(&mut line) {
+        let exit_code = loop {
+            let mut line = Vec::new();
+            match self.reader.read_until(b'\n', &mut line) {
                 Ok(0) => {
                     eprintln!("[SSH] Unexpected EOF from {} after {} lines", self.host, lines_read);
-                    break; // EOF
+                    self.health.record(Err("relay closed the connection".to_string()));
+                    bail!("Connection to {} lost before the command finished; reconnect the session", self.host);
                 }
-                Ok(_) => {
+                Ok(bytes) => {
                     lines_read += 1;
-                    let line = line.trim_end().to_string();
+                    self.stats.received(bytes);
                     
-                    if let Some(code_str) = line.strip_prefix("<<<EXIT:") {
-                        if let Some(code_str) = code_str.strip_suffix(">>>") {
-                            exit_code = code_str.parse()
-                                .context(format!("Invalid exit code format from {}: {}", self.host, code_str))?;
-                        }
+                    if let Some(status) = protocol::parse_end_marker(&line, &id) {
+                        let exit_code = status.context(format!("Invalid response from {}", self.host))?;
                         eprintln!("[SSH] Command completed on {} with exit code: {}", self.host, exit_code);
-                        break;
+                        break exit_code;
                     }
-                    output.push(line);
                     
-                    // Safety check to prevent infinite loops
-                    if lines_read > 10000 {
-                        bail!("Too many lines read from {} (>10000), aborting", self.host);
+                    // Safety check to prevent runaway output; keep reading up to
+                    // the marker so the next command starts in sync
+                    if lines_read <= 10000 {
+                        output.extend_from_slice(&line);
                     }
                 }
                 Err(e) => {
@@ -177,22 +488,98 @@ done
                         .context(format!("Error reading output from {}", self.host)));
                 }
             }
+        };
+        
+        if lines_read > 10001 {
+            bail!("Too many lines read from {} (>10000), output discarded", self.host);
         }
         
-        let output_text = output.join("\n");
-        eprintln!("[SSH] Got {} lines of output from {}", output.len(), self.host);
+        // The relay puts the marker on a line of its own
+        let output = protocol::finish_output(output);
+        eprintln!("[SSH] Got {} lines of output from {}", output.lines().count(), self.host);
         
-        Ok((output_text, exit_code))
+        Ok((output, exit_code))
+    }
+    
+    /// Execute a command and fail with its output if it exits non-zero
+    fn run_checked(&mut self, command: &str, use_sudo: bool) -> Result<String> {
+        let (output, exit_code) = self.execute(command, use_sudo)?;
+        if exit_code != 0 {
+            bail!("Command failed on {} with exit code {}: {}", self.host, exit_code, output.trim());
+        }
+        Ok(output)
+    }
+    
+    /// What running side channel commands needs, detached from the session
+    fn side_channel(&self) -> SideChannel {
+        SideChannel {
+            host: self.host.clone(),
+            client: self.control.client(),
+            cwd: self.cwd.clone(),
+            env: self.env.clone(),
+        }
     }
     
+    fn side_command(&self, command: &str, use_sudo: bool) -> std::process::Command {
+        self.side_channel().command(command, use_sudo)
+    }
+    
+    fn home_command(&self, command: &str, use_sudo: bool) -> std::process::Command {
+        self.side_channel().home_command(command, use_sudo)
+    }
+    
+    /// Everything `ssh_list_sessions` reports about the session
+    fn summary(&self, session_id: &str) -> Value {
+        let last_activity = time::now_millis() - self.health.last_used.elapsed().as_millis() as i64;
+        let mut summary = json!({
+            "session_id": session_id,
+            "host": self.host,
+            "user": self.options.user,
+            "port": self.options.port,
+            "transport": self.persist.as_ref().map_or("relay", |persist| persist.multiplexer.name()),
+            "remote_session": self.persist.as_ref().map(|persist| &persist.name),
+            "profile": self.profile,
+            "read_only": self.policy.read_only,
+            "last_activity": time::format_rfc3339(last_activity.div_euclid(1000)),
+            "x11_display": self.x11_display,
+            "sudo": self.stats.sudo_readiness(self.x11_display.is_some()),
+            "cwd": self.cwd,
+            // Values may be secrets
+            "env": self.env.keys().collect::<Vec<_>>(),
+            "forwards": self.forwards.len()
+        });
+        for extra in [self.stats.to_json(), self.health.to_json()] {
+            if let (Some(summary), Value::Object(extra)) = (summary.as_object_mut(), extra) {
+                summary.extend(extra);
+            }
+        }
+        summary
+    }
+    
+    /// Disconnect, ending a persistent session's remote tmux/screen session unless `keep_remote`
+    fn close(self, keep_remote: bool) -> Result<()> {
+        if let (Some(persist), false) = (&self.persist, keep_remote) {
+            eprintln!("[SSH] Ending {} on {}", persist.describe(), self.host);
+            let status = self.home_command(&persist.terminate_command(), false)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .status();
+            if !status.is_ok_and(|status| status.success()) {
+                eprintln!("[SSH] Failed to end {} on {}", persist.describe(), self.host);
+            }
+        }
+        self.disconnect()
+    }
+    
+    /// Close the connection; a persistent session's relay keeps running remotely
     fn disconnect(mut self) -> Result<()> {
-        eprintln!("[SSH] Disconnecting from {}", self.host);
+        eprintln!("[SSH] Disconnecting from {}", self.options.display_target(&self.host));
         
         // Close stdin to signal the relay to exit
         drop(self.stdin);
         
         // Wait for the SSH process to finish, with timeout
-        match self.child.wait() {
+        let result = match self.child.wait() {
             Ok(status) => {
                 eprintln!("[SSH] SSH process to {} exited with status: {}", self.host, status);
                 Ok(())
@@ -203,18 +590,121 @@ done
                 let _ = self.child.kill();
                 Err(anyhow::Error::from(e).context(format!("Failed to cleanly disconnect from {}", self.host)))
             }
+        };
+        
+        // Tear down the master connection (and with it all forwards) and its socket directory
+        if self.forwards.len() > 0 {
+            eprintln!("[SSH] Tearing down {} forwards on {}", self.forwards.len(), self.host);
+        }
+        self.control.close();
+        
+        result
+    }
+}
+
+/// Listing entry of a connected session for `ssh_list_sessions`
+fn describe_session(session_id: &str, session: &SshSession) -> String {
+    let transport = match &session.persist {
+        Some(persist) => format!("relay in {}", persist.describe()),
+        None => "relay".to_string(),
+    };
+    let mut text = format!("  - {}: {}, {}, {}",
+        session_id, session.options.display_target(&session.host), transport, session.health.describe());
+    text.push_str(&format!("\n      connected {}, {} commands, {} bytes sent, {} bytes received",
+        time::format_rfc3339(session.stats.connected_at.div_euclid(1000)),
+        session.stats.commands(), session.stats.bytes_sent(), session.stats.bytes_received()));
+    text.push_str(&format!("\n      X11 {}, sudo {}, cwd {}",
+        session.x11_display.as_deref().unwrap_or("none"),
+        session.stats.sudo_readiness(session.x11_display.is_some()),
+        session.cwd.as_deref().unwrap_or("~")));
+    text
+}
+
+/// Read an `env` argument: variable names to values, `null` meaning unset
+fn env_arguments(env: &Value) -> Result<Vec<(&str, Option<&str>)>> {
+    match env {
+        Value::Null => Ok(Vec::new()),
+        Value::Object(map) => map.iter()
+            .map(|(name, value)| match value {
+                Value::String(value) => Ok((name.as_str(), Some(value.as_str()))),
+                Value::Null => Ok((name.as_str(), None)),
+                _ => bail!("Invalid value for environment variable {} - must be a string or null", name),
+            })
+            .collect(),
+        _ => bail!("Invalid env parameter - must be an object of strings"),
+    }
+}
+
+/// Per-session result of connecting several sessions at once
+fn connect_report(subject: String, results: &[(String, Value, Result<String>)]) -> Value {
+    let connected = results.iter().filter(|(_, _, result)| result.is_ok()).count();
+    let mut text = format!("{}: {} of {} sessions connected", subject, connected, results.len());
+    for (session_id, _, result) in results {
+        match result {
+            Ok(message) => text.push_str(&format!("\n  ok     {}: {}", session_id, message)),
+            Err(e) => text.push_str(&format!("\n  failed {}: {:#}", session_id, e)),
         }
     }
+    
+    json!({
+        "content": [{
+            "type": "text",
+            "text": text
+        }],
+        "metadata": {
+            "connected": connected,
+            "failed": results.len() - connected,
+            "sessions": results.iter().map(|(session_id, arguments, result)| json!({
+                "session_id": session_id,
+                "host": arguments["host"],
+                "connected": result.is_ok(),
+                "error": result.as_ref().err().map(|e| format!("{:#}", e))
+            })).collect::<Vec<_>>()
+        }
+    })
+}
+
+/// Protocol versions this server speaks, newest first
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Interpreters the relay can run code with
+const INTERPRETERS: &[&str] = &["bash", "sh", "python3", "perl"];
+
+/// Random-enough prefix for relay request ids
+fn request_token(host: &str) -> String {
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+    files::sha256_hex(format!("{}:{}:{}", std::process::id(), nanos, host).as_bytes())[..16].to_string()
 }
 
 struct McpServer {
     sessions: Arc<Mutex<HashMap<String, SshSession>>>,
+    config: Config,
+    audit: Option<AuditLog>,
+    /// Saved session definitions, for reconnecting after a restart
+    store: Option<SessionStore>,
+    /// Sessions being connected, by ID, with their host
+    connecting: Mutex<BTreeMap<String, String>>,
+    /// `initialize` params sent by the client (protocol version, capabilities, client info)
+    client: Mutex<Value>,
+    /// Messages read while waiting for a response to one of our own requests
+    deferred: Mutex<VecDeque<String>>,
+    next_request_id: AtomicU64,
+    /// Set when stdin closes, to stop background threads
+    shutdown: AtomicBool,
 }
 
 impl McpServer {
-    fn new() -> Self {
+    fn new(config: Config, audit: Option<AuditLog>, store: Option<SessionStore>) -> Self {
         McpServer {
             sessions: Arc::new(Mutex::new(HashMap::new())),
+            config,
+            audit,
+            store,
+            connecting: Mutex::new(BTreeMap::new()),
+            client: Mutex::new(Value::Null),
+            deferred: Mutex::new(VecDeque::new()),
+            next_request_id: AtomicU64::new(1),
+            shutdown: AtomicBool::new(false),
         }
     }
     
@@ -223,18 +713,33 @@ impl McpServer {
             .context("Missing method in request")?;
         
         match method {
-            "initialize" => self.handle_initialize(),
+            "initialize" => self.handle_initialize(&request["params"]),
             "tools/list" => self.handle_list_tools(),
             "tools/call" => self.handle_tool_call(&request),
+            "resources/list" => Ok(json!({ "resources": ssh_config::resource_definitions() })),
+            "resources/read" => self.handle_read_resource(&request["params"]),
             _ => bail!("Unknown method: {}", method),
         }
     }
     
-    fn handle_initialize(&self) -> Result<Value> {
+    fn handle_initialize(&self, params: &Value) -> Result<Value> {
+        // Answer with the client's version if we speak it, else our newest
+        let requested = params["protocolVersion"].as_str().unwrap_or_default();
+        let version = PROTOCOL_VERSIONS.iter().copied()
+            .find(|version| *version == requested)
+            .unwrap_or(PROTOCOL_VERSIONS[0]);
+        
+        eprintln!("[MCP] Client {} {} (protocol {}, elicitation: {})",
+            params["clientInfo"]["name"].as_str().unwrap_or("unknown"),
+            params["clientInfo"]["version"].as_str().unwrap_or(""),
+            version, params["capabilities"]["elicitation"].is_object());
+        *self.client.lock().unwrap() = params.clone();
+        
         Ok(json!({
-            "protocolVersion": "2024-11-05",
+            "protocolVersion": version,
             "capabilities": {
-                "tools": {}
+                "tools": {},
+                "resources": {}
             },
             "serverInfo": {
                 "name": "mcp-ssh-sessions",
@@ -243,40 +748,113 @@ impl McpServer {
         }))
     }
     
+    fn handle_read_resource(&self, params: &Value) -> Result<Value> {
+        let uri = params["uri"].as_str()
+            .context("Missing or invalid uri parameter - must be a string")?;
+        match uri {
+            ssh_config::HOSTS_RESOURCE_URI => ssh_config::read_hosts_resource(),
+            _ => bail!("Unknown resource: {}", uri),
+        }
+    }
+    
     fn handle_list_tools(&self) -> Result<Value> {
-        Ok(json!({
+        let mut connect_properties = json!({
+            "host": {
+                "type": "string",
+                "description": "Hostname or IP to connect to"
+            },
+            "session_id": {
+                "type": "string",
+                "description": "Optional session ID (defaults to host)"
+            },
+            "profile": {
+                "type": "string",
+                "description": "Config profile to apply (defaults to the profile matching the host)"
+            },
+            "read_only": {
+                "type": "boolean",
+                "description": "Forbid sudo, file writes and commands that modify the host",
+                "default": false
+            },
+            "persist": persist::schema_property(),
+            "cwd": {
+                "type": "string",
+                "description": "Working directory for commands run in the session"
+            },
+            "env": {
+                "type": "object",
+                "additionalProperties": { "type": "string" },
+                "description": "Environment variables for commands run in the session"
+            },
+            "idle_timeout": keepalive::schema_property()
+        });
+        if let (Some(properties), Value::Object(extra)) = (connect_properties.as_object_mut(), options::tool_schema_properties()) {
+            properties.extend(extra);
+        }
+        
+        // Group members take host and session ID from the inventory
+        let mut group_properties = connect_properties.clone();
+        if let Some(properties) = group_properties.as_object_mut() {
+            properties.remove("host");
+            properties.remove("session_id");
+            properties.insert("group".to_string(), json!({
+                "type": "string",
+                "description": "Group from the config or an imported inventory"
+            }));
+            properties.insert("max_parallel".to_string(), json!({
+                "type": "integer",
+                "description": "Maximum number of connections opened at once",
+                "default": 10
+            }));
+        }
+        
+        let mut tools = json!({
             "tools": [
                 {
                     "name": "ssh_connect",
                     "description": "Connect to an SSH host",
                     "inputSchema": {
                         "type": "object",
-                        "properties": {
-                            "host": {
-                                "type": "string",
-                                "description": "Hostname or IP to connect to"
-                            },
-                            "session_id": {
-                                "type": "string",
-                                "description": "Optional session ID (defaults to host)"
-                            }
-                        },
+                        "properties": connect_properties,
                         "required": ["host"]
                     }
                 },
+                {
+                    "name": "ssh_connect_group",
+                    "description": "Connect to every host of an inventory group; each session is named after its host. Group names can then be used as session_id",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": group_properties,
+                        "required": ["group"]
+                    }
+                },
                 {
                     "name": "ssh_execute",
-                    "description": "Execute a command on a connected SSH session",
+                    "description": "Execute a command, or a multi-line script with a chosen interpreter, on a connected SSH session",
                     "inputSchema": {
                         "type": "object",
                         "properties": {
                             "session_id": {
                                 "type": "string",
-                                "description": "Session ID or hostname"
+                                "description": "Session ID or hostname, or a group to run the command on all its sessions"
                             },
                             "command": {
                                 "type": "string",
-                                "description": "Command to execute"
+                                "description": "Command to execute (run with bash)"
+                            },
+                            "script": {
+                                "type": "string",
+                                "description": "Multi-line script to run instead of command; heredocs and functions work"
+                            },
+                            "interpreter": {
+                                "type": "string",
+                                "enum": INTERPRETERS,
+                                "description": "Interpreter for script",
+                                "default": "bash"
+                            },
+                            "stdin": {
+                                "type": "string",
+                                "description": "Text piped into the command's standard input (otherwise stdin is empty)"
                             },
                             "sudo": {
                                 "type": "boolean",
@@ -284,18 +862,23 @@ impl McpServer {
                                 "default": false
                             }
                         },
-                        "required": ["session_id", "command"]
+                        "required": ["session_id"]
                     }
                 },
                 {
                     "name": "ssh_disconnect",
-                    "description": "Disconnect an SSH session",
+                    "description": "Disconnect an SSH session, or all sessions of a group",
                     "inputSchema": {
                         "type": "object",
                         "properties": {
                             "session_id": {
                                 "type": "string",
-                                "description": "Session ID or hostname"
+                                "description": "Session ID or hostname, or a group name"
+                            },
+                            "keep_remote": {
+                                "type": "boolean",
+                                "description": "For persistent sessions: leave the remote tmux/screen session running to re-attach later",
+                                "default": false
                             }
                         },
                         "required": ["session_id"]
@@ -308,9 +891,66 @@ impl McpServer {
                         "type": "object",
                         "properties": {}
                     }
+                },
+                {
+                    "name": "ssh_configure_session",
+                    "description": "Change the working directory or environment variables of a session; with save_sessions on, the changes are saved and reapplied when it is restored",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "session_id": {
+                                "type": "string",
+                                "description": "Session ID or hostname"
+                            },
+                            "cwd": {
+                                "type": "string",
+                                "description": "New working directory"
+                            },
+                            "env": {
+                                "type": "object",
+                                "additionalProperties": { "type": ["string", "null"] },
+                                "description": "Environment variables to set; null unsets a variable"
+                            }
+                        },
+                        "required": ["session_id"]
+                    }
+                },
+                {
+                    "name": "ssh_restore_sessions",
+                    "description": "Reconnect sessions saved in the session state file that are not connected, e.g. after a server restart, and report which came back",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "session_ids": {
+                                "type": "array",
+                                "items": { "type": "string" },
+                                "description": "Saved sessions to restore (defaults to all)"
+                            },
+                            "max_parallel": {
+                                "type": "integer",
+                                "description": "Maximum number of connections opened at once",
+                                "default": 10
+                            }
+                        }
+                    }
                 }
             ]
-        }))
+        });
+        
+        if let Some(list) = tools["tools"].as_array_mut() {
+            list.extend(forward::tool_definitions());
+            list.extend(files::tool_definitions());
+            list.extend(edit::tool_definitions());
+            list.extend(transfer::tool_definitions());
+            list.extend(sync::tool_definitions());
+            list.extend(listing::tool_definitions());
+            list.extend(copy::tool_definitions());
+            list.extend(fanout::tool_definitions());
+            list.extend(jobs::tool_definitions());
+            list.extend(ssh_config::tool_definitions());
+        }
+        
+        Ok(tools)
     }
     
     fn handle_tool_call(&self, request: &Value) -> Result<Value> {
@@ -318,62 +958,202 @@ impl McpServer {
             .context("Missing tool name")?;
         let arguments = &request["params"]["arguments"];
         
+        // Listings of local state never touch a host and are not audited
+        if self.audit.is_none() || matches!(tool_name, "ssh_list_sessions" | "ssh_list_forwards" | "ssh_list_hosts") {
+            return self.dispatch_tool_call(tool_name, arguments, request);
+        }
+        
+        // Resolve the target before the call, since disconnecting removes the session
+        let mut fields = self.audit_target(arguments);
+        let started = Instant::now();
+        let result = self.dispatch_tool_call(tool_name, arguments, request);
+        
+        fields.insert("tool".to_string(), json!(tool_name));
+        fields.insert("command".to_string(), match &arguments["command"] {
+            Value::Null => arguments["script"].clone(),
+            command => command.clone(),
+        });
+        fields.insert("sudo".to_string(), json!(arguments["sudo"].as_bool().unwrap_or(false)
+            || arguments["source_sudo"].as_bool().unwrap_or(false)
+            || arguments["dest_sudo"].as_bool().unwrap_or(false)));
+        fields.insert("arguments".to_string(), audit::summarize_arguments(arguments));
+        fields.insert("duration_ms".to_string(), json!(started.elapsed().as_millis() as u64));
+        match &result {
+            Ok(value) => {
+                let output: String = value["content"].as_array().into_iter().flatten()
+                    .filter_map(|item| item["text"].as_str().or(item["resource"]["blob"].as_str()).or(item["resource"]["text"].as_str()))
+                    .collect();
+                fields.insert("outcome".to_string(), json!("ok"));
+                fields.insert("exit_code".to_string(), value["metadata"]["exit_code"].clone());
+                fields.insert("output_sha256".to_string(), json!(files::sha256_hex(output.as_bytes())));
+            }
+            Err(e) => {
+                fields.insert("outcome".to_string(), json!("error"));
+                fields.insert("error".to_string(), json!(format!("{:#}", e)));
+            }
+        }
+        self.audit_record("tool_call", fields);
+        
+        result
+    }
+    
+    /// Client, session and host fields identifying who acted where
+    fn audit_target(&self, arguments: &Value) -> serde_json::Map<String, Value> {
+        let mut fields = serde_json::Map::new();
+        fields.insert("client".to_string(), self.client.lock().unwrap()["clientInfo"].clone());
+        
+        let session_id = arguments["session_id"].as_str()
+            .or(arguments["source_session_id"].as_str())
+            .or(arguments["host"].as_str());
+        fields.insert("session_id".to_string(), json!(session_id));
+        
+        let sessions = self.sessions.lock().unwrap();
+        match session_id.and_then(|session_id| sessions.get(session_id)) {
+            Some(session) => {
+                fields.insert("host".to_string(), json!(session.host));
+                fields.insert("user".to_string(), json!(session.options.user));
+                fields.insert("profile".to_string(), json!(session.profile));
+            }
+            // Not connected yet: ssh_connect names the host itself
+            None => {
+                fields.insert("host".to_string(), arguments["host"].clone());
+                fields.insert("user".to_string(), arguments["user"].clone());
+            }
+        }
+        if let Some(dest_id) = arguments["dest_session_id"].as_str() {
+            fields.insert("dest_session_id".to_string(), json!(dest_id));
+            fields.insert("dest_host".to_string(), json!(sessions.get(dest_id).map(|session| &session.host)));
+        }
+        fields
+    }
+    
+    fn audit_record(&self, event: &str, fields: serde_json::Map<String, Value>) {
+        if let Some(audit) = &self.audit {
+            audit.record(event, fields);
+        }
+    }
+    
+    fn dispatch_tool_call(&self, tool_name: &str, arguments: &Value, request: &Value) -> Result<Value> {
         match tool_name {
             "ssh_connect" => {
-                let host = arguments["host"].as_str()
-                    .context("Missing or invalid host parameter - must be a string")?;
+                let text = self.connect(arguments)?;
                 
-                if host.is_empty() {
-                    bail!("Host parameter cannot be empty");
+                Ok(json!({
+                    "content": [{
+                        "type": "text",
+                        "text": text
+                    }]
+                }))
+            }
+            
+            "ssh_connect_group" => self.connect_group(arguments),
+            
+            "ssh_restore_sessions" => self.restore_sessions(arguments),
+            
+            "ssh_configure_session" => {
+                let cwd = match &arguments["cwd"] {
+                    Value::Null => None,
+                    Value::String(dir) => Some(dir.as_str()),
+                    _ => bail!("Invalid cwd parameter - must be a string"),
+                };
+                let env = env_arguments(&arguments["env"])?;
+                if cwd.is_none() && env.is_empty() {
+                    bail!("Nothing to configure - pass cwd or env");
                 }
                 
-                let session_id = arguments["session_id"].as_str()
-                    .unwrap_or(host);
-                
-                eprintln!("[MCP] Attempting to connect to {} with session ID: {}", host, session_id);
-                
-                // Check if session already exists
-                {
-                    let sessions = self.sessions.lock().unwrap();
-                    if sessions.contains_key(session_id) {
-                        bail!("Session '{}' already exists. Use ssh_disconnect first or choose a different session_id.", session_id);
+                let (session_id, state) = self.with_session(arguments, |session_id, session| {
+                    if let Some(dir) = cwd {
+                        session.set_cwd(dir)?;
                     }
-                }
-                
-                let session = SshSession::connect(host)
-                    .context(format!("Failed to establish SSH connection to {}", host))?;
+                    for (name, value) in &env {
+                        session.set_env(name, *value)?;
+                    }
+                    eprintln!("[MCP] Configured session '{}' (cwd: {:?}, {} variables)", session_id, session.cwd, session.env.len());
+                    Ok((session_id.to_string(), (session.cwd.clone(), session.env.clone())))
+                })?;
+                let (cwd, env) = state;
                 
-                let mut sessions = self.sessions.lock().unwrap();
-                sessions.insert(session_id.to_string(), session);
+                if let Some(store) = &self.store {
+                    store.update(&session_id, |saved| {
+                        saved["cwd"] = json!(cwd);
+                        saved["env"] = json!(env);
+                    });
+                }
                 
-                eprintln!("[MCP] Successfully connected to {} (session: {})", host, session_id);
+                let mut text = format!("Configured session {}", session_id);
+                if let Some(dir) = &cwd {
+                    text.push_str(&format!("\nWorking directory: {}", dir));
+                }
+                if !env.is_empty() {
+                    text.push_str(&format!("\nEnvironment: {}", env.keys().cloned().collect::<Vec<_>>().join(", ")));
+                }
                 
                 Ok(json!({
                     "content": [{
                         "type": "text",
-                        "text": format!("Successfully connected to {} (session: {})", host, session_id)
-                    }]
+                        "text": text
+                    }],
+                    "metadata": {
+                        "session_id": session_id,
+                        "cwd": cwd,
+                        "env": env
+                    }
                 }))
             }
             
             "ssh_execute" => {
                 let session_id = arguments["session_id"].as_str()
                     .context("Missing or invalid session_id parameter - must be a string")?;
-                let command = arguments["command"].as_str()
-                    .context("Missing or invalid command parameter - must be a string")?;
                 let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
                 
+                // A group name runs the command on all its connected sessions
+                let group = self.group_sessions(&self.sessions.lock().unwrap(), session_id);
+                if group.is_some() {
+                    if !arguments["script"].is_null() || !arguments["stdin"].is_null() {
+                        bail!("Scripts and stdin are not supported on groups; pass a command or a single session");
+                    }
+                    let mut arguments = arguments.clone();
+                    arguments["sessions"] = json!([session_id]);
+                    return self.dispatch_tool_call("ssh_execute_many", &arguments, request);
+                }
+                
+                let (command, interpreter) = match (arguments["command"].as_str(), arguments["script"].as_str()) {
+                    (Some(command), None) => (command, "bash"),
+                    (None, Some(script)) => (script, arguments["interpreter"].as_str().unwrap_or("bash")),
+                    (Some(_), Some(_)) => bail!("Pass either command or script, not both"),
+                    (None, None) => bail!("Missing or invalid command parameter - must be a string"),
+                };
                 if command.is_empty() {
                     bail!("Command parameter cannot be empty");
                 }
+                if !INTERPRETERS.contains(&interpreter) {
+                    bail!("Unsupported interpreter '{}'. Supported: {}", interpreter, INTERPRETERS.join(", "));
+                }
+                let input = match &arguments["stdin"] {
+                    Value::Null => None,
+                    Value::String(input) => Some(input.as_bytes()),
+                    _ => bail!("Invalid stdin parameter - must be a string"),
+                };
+                
+                eprintln!("[MCP] Executing {} on session '{}': {} (sudo: {})",
+                    if interpreter == "bash" { "command" } else { interpreter }, session_id, redact::text(command), use_sudo);
                 
-                eprintln!("[MCP] Executing command on session '{}': {} (sudo: {})", session_id, command, use_sudo);
+                self.approve_on_session(session_id, tool_name, command, use_sudo, |session| {
+                    session.policy.check_script(interpreter, command, use_sudo)
+                        .inspect_err(|e| eprintln!("[MCP] {} (session '{}', profile: {}, command: {})",
+                            e, session_id, session.profile.as_deref().unwrap_or("none"), redact::text(command)))?;
+                    Ok(session.policy.command_approval(command, use_sudo))
+                })?;
                 
+                // The approval was asked without the sessions lock; the session may be gone meanwhile
                 let mut sessions = self.sessions.lock().unwrap();
                 let session = sessions.get_mut(session_id)
                     .context(format!("No active session found with ID '{}'. Use ssh_list_sessions to see available sessions.", session_id))?;
                 
-                let (output, exit_code) = session.execute(command, use_sudo)
+                session.stats.command();
+                let result = session.run_script(interpreter, command, input, use_sudo);
+                session.health.touch();
+                let (output, exit_code) = result
                     .context(format!("Failed to execute command on session '{}'", session_id))?;
                 
                 eprintln!("[MCP] Command executed on session '{}' with exit code: {}", session_id, exit_code);
@@ -387,49 +1167,177 @@ impl McpServer {
                         "exit_code": exit_code,
                         "session_id": session_id,
                         "command": command,
+                        "interpreter": interpreter,
+                        "stdin_bytes": input.map_or(0, <[u8]>::len),
                         "sudo": use_sudo
                     }
                 }))
             }
             
-            "ssh_disconnect" => {
-                let session_id = arguments["session_id"].as_str()
-                    .context("Missing or invalid session_id parameter - must be a string")?;
-                
-                eprintln!("[MCP] Attempting to disconnect session '{}'", session_id);
-                
-                let mut sessions = self.sessions.lock().unwrap();
-                let session = sessions.remove(session_id)
-                    .context(format!("No active session found with ID '{}'. Use ssh_list_sessions to see available sessions.", session_id))?;
-                
-                session.disconnect()
-                    .context(format!("Failed to cleanly disconnect session '{}'", session_id))?;
-                
-                eprintln!("[MCP] Successfully disconnected session '{}'", session_id);
+            "ssh_execute_many" => {
+                let command = arguments["command"].as_str()
+                    .context("Missing or invalid command parameter - must be a string")?;
+                let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
+                
+                // Policies are checked under the sessions lock; approvals and the
+                // commands themselves run without it
+                let mut targets = Vec::new();
+                let mut approvals = Vec::new();
+                let mut denied = Vec::new();
+                let arguments = {
+                    let sessions = self.sessions.lock().unwrap();
+                    let arguments = self.expand_groups(&sessions, arguments)?;
+                    for (session_id, session) in fanout::select(&sessions, &arguments)? {
+                        // Each session's own policy decides; denied sessions are reported, not run
+                        let target = (session_id.to_string(), session.side_channel());
+                        match session.policy.check_command(command, use_sudo) {
+                            Ok(()) => match session.policy.command_approval(command, use_sudo) {
+                                Some(reason) => approvals.push((target, reason)),
+                                None => targets.push(target),
+                            },
+                            Err(e) => {
+                                eprintln!("[MCP] {} (session '{}')", e, session_id);
+                                denied.push((session_id.to_string(), session.host.clone(), e.to_string()));
+                            }
+                        }
+                    }
+                    arguments
+                };
+                for ((session_id, channel), reason) in approvals {
+                    let approved = self.require_approval(&ApprovalRequest {
+                        session_id: &session_id,
+                        host: &channel.host,
+                        tool_name,
+                        target: command,
+                        use_sudo,
+                        reason: &reason,
+                    });
+                    match approved {
+                        Ok(()) => targets.push((session_id, channel)),
+                        Err(e) => {
+                            eprintln!("[MCP] {} (session '{}')", e, session_id);
+                            denied.push((session_id, channel.host, e.to_string()));
+                        }
+                    }
+                }
+                targets.sort_by(|(a, _), (b, _)| a.cmp(b));
+                
+                let (result, received) = fanout::execute_many(&targets, denied, &arguments)?;
+                // Sessions may have been disconnected meanwhile
+                let mut sessions = self.sessions.lock().unwrap();
+                for (session_id, bytes) in received {
+                    if let Some(session) = sessions.get_mut(&session_id) {
+                        session.stats.command();
+                        session.stats.sent(command.len());
+                        session.stats.received(bytes);
+                        session.health.touch();
+                    }
+                }
+                Ok(result)
+            }
+            
+            "ssh_job_start" | "ssh_job_status" | "ssh_job_output" | "ssh_job_kill" | "ssh_job_list" => {
+                if let (true, Some(session_id), Some(command)) =
+                    (tool_name == "ssh_job_start", arguments["session_id"].as_str(), arguments["command"].as_str()) {
+                    let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
+                    self.approve_on_session(session_id, tool_name, command, use_sudo, |session| {
+                        session.policy.check_command(command, use_sudo)
+                            .inspect_err(|e| eprintln!("[MCP] {} (session '{}', profile: {}, command: {})",
+                                e, session_id, session.profile.as_deref().unwrap_or("none"), redact::text(command)))?;
+                        Ok(session.policy.command_approval(command, use_sudo))
+                    })?;
+                }
+                
+                self.with_session(arguments, |session_id, session| {
+                    match tool_name {
+                        "ssh_job_start" => jobs::start(session_id, session, arguments),
+                        "ssh_job_status" => jobs::status(session_id, session, arguments),
+                        "ssh_job_output" => jobs::output(session_id, session, arguments),
+                        "ssh_job_kill" => jobs::kill(session_id, session, arguments),
+                        _ => jobs::list(session_id, session),
+                    }
+                })
+            }
+            
+            "ssh_disconnect" => {
+                let session_id = arguments["session_id"].as_str()
+                    .context("Missing or invalid session_id parameter - must be a string")?;
+                
+                let keep_remote = arguments["keep_remote"].as_bool().unwrap_or(false);
+                
+                eprintln!("[MCP] Attempting to disconnect session '{}'", session_id);
+                
+                let mut sessions = self.sessions.lock().unwrap();
+                if let Some(ids) = self.group_sessions(&sessions, session_id) {
+                    let mut failures = Vec::new();
+                    for id in &ids {
+                        if let Some(session) = sessions.remove(id) {
+                            self.forget_session(id, keep_remote);
+                            if let Err(e) = session.close(keep_remote) {
+                                eprintln!("[MCP] Failed to cleanly disconnect session '{}': {:#}", id, e);
+                                failures.push(format!("{}: {:#}", id, e));
+                            }
+                        }
+                    }
+                    eprintln!("[MCP] Disconnected {} sessions of group '{}'", ids.len(), session_id);
+                    if !failures.is_empty() {
+                        bail!("Failed to cleanly disconnect sessions of group '{}': {}", session_id, failures.join("; "));
+                    }
+                    return Ok(json!({
+                        "content": [{
+                            "type": "text",
+                            "text": format!("Disconnected {} sessions of group '{}': {}", ids.len(), session_id, ids.join(", "))
+                        }]
+                    }));
+                }
+                
+                let session = sessions.remove(session_id)
+                    .context(format!("No active session found with ID '{}'. Use ssh_list_sessions to see available sessions.", session_id))?;
+                let kept = match (&session.persist, keep_remote) {
+                    (Some(persist), true) => format!(" ({} kept running)", persist.describe()),
+                    _ => String::new(),
+                };
+                
+                self.forget_session(session_id, keep_remote);
+                session.close(keep_remote)
+                    .context(format!("Failed to cleanly disconnect session '{}'", session_id))?;
+                
+                eprintln!("[MCP] Successfully disconnected session '{}'", session_id);
                 
                 Ok(json!({
                     "content": [{
                         "type": "text",
-                        "text": format!("Successfully disconnected session: {}", session_id)
+                        "text": format!("Successfully disconnected session: {}{}", session_id, kept)
                     }]
                 }))
             }
             
             "ssh_list_sessions" => {
                 let sessions = self.sessions.lock().unwrap();
+                let connecting = self.connecting.lock().unwrap();
                 let session_count = sessions.len();
-                let session_list: Vec<String> = sessions.keys().cloned().collect();
+                let mut session_list: Vec<(&String, &SshSession)> = sessions.iter().collect();
+                session_list.sort_by_key(|(id, _)| *id);
                 
                 eprintln!("[MCP] Listing {} active sessions", session_count);
                 
-                let response_text = if session_list.is_empty() {
+                let mut details: Vec<Value> = session_list.iter()
+                    .map(|(id, session)| session.summary(id))
+                    .collect();
+                let mut lines: Vec<String> = session_list.iter()
+                    .map(|(id, session)| describe_session(id, session))
+                    .collect();
+                // Restored sessions were saved before; anything else is a new connection
+                for (id, host) in connecting.iter() {
+                    let status = if self.store.as_ref().is_some_and(|store| store.contains(id)) { "reconnecting" } else { "connecting" };
+                    details.push(json!({ "session_id": id, "host": host, "status": status }));
+                    lines.push(format!("  - {}: {} to {}", id, status, host));
+                }
+                
+                let response_text = if lines.is_empty() {
                     "No active SSH sessions".to_string()
                 } else {
-                    format!("Active SSH sessions ({}):\n{}", session_count, 
-                        session_list.iter()
-                            .map(|s| format!("  - {}", s))
-                            .collect::<Vec<_>>()
-                            .join("\n"))
+                    format!("Active SSH sessions ({}):\n{}", session_count, lines.join("\n"))
                 };
                 
                 Ok(json!({
@@ -439,41 +1347,624 @@ impl McpServer {
                     }],
                     "metadata": {
                         "session_count": session_count,
-                        "sessions": session_list
+                        "sessions": session_list.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+                        "details": details
+                    }
+                }))
+            }
+            
+            "ssh_list_hosts" => ssh_config::list_hosts(arguments),
+            
+            "ssh_forward_local" | "ssh_forward_remote" | "ssh_forward_dynamic" => {
+                let kind = match tool_name {
+                    "ssh_forward_local" => ForwardKind::Local,
+                    "ssh_forward_remote" => ForwardKind::Remote,
+                    _ => ForwardKind::Dynamic,
+                };
+                self.with_session(arguments, |_, session| forward::add_forward(session, kind, arguments))
+            }
+            
+            "ssh_cancel_forward" => {
+                let forward_id = arguments["forward_id"].as_str()
+                    .context("Missing or invalid forward_id parameter - must be a string")?;
+                self.with_session(arguments, |_, session| forward::cancel_forward(session, forward_id))
+            }
+            
+            "ssh_list_forwards" => {
+                let sessions = self.sessions.lock().unwrap();
+                let filter = match arguments["session_id"].as_str() {
+                    Some(name) => match self.group_sessions(&sessions, name) {
+                        Some(ids) => Some(ids),
+                        None if sessions.contains_key(name) => Some(vec![name.to_string()]),
+                        None => bail!("No active session found with ID '{}'. Use ssh_list_sessions to see available sessions.", name),
+                    },
+                    None => None,
+                };
+                
+                let mut lines = Vec::new();
+                let mut entries = Vec::new();
+                for (session_id, session) in sessions.iter() {
+                    if filter.as_ref().is_some_and(|filter| !filter.contains(session_id)) {
+                        continue;
+                    }
+                    let (session_lines, session_entries) = forward::list_forwards(session_id, session);
+                    lines.extend(session_lines);
+                    entries.extend(session_entries);
+                }
+                
+                let response_text = if lines.is_empty() {
+                    "No active port forwards".to_string()
+                } else {
+                    format!("Active port forwards ({}):\n{}", lines.len(), lines.join("\n"))
+                };
+                
+                Ok(json!({
+                    "content": [{
+                        "type": "text",
+                        "text": response_text
+                    }],
+                    "metadata": {
+                        "forwards": entries
                     }
                 }))
             }
             
+            "ssh_read_file" | "ssh_write_file" | "ssh_edit_file" | "ssh_upload_dir" | "ssh_download_dir"
+            | "ssh_sync" | "ssh_list_dir" | "ssh_stat" => {
+                let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
+                let dry_run = arguments["dry_run"].as_bool().unwrap_or(false);
+                let writes = match tool_name {
+                    "ssh_write_file" | "ssh_upload_dir" => true,
+                    "ssh_edit_file" | "ssh_sync" => !dry_run,
+                    _ => false,
+                };
+                
+                let write_path = match writes {
+                    true => arguments["path"].as_str().or(arguments["remote_path"].as_str()),
+                    false => None,
+                };
+                let directory = matches!(tool_name, "ssh_upload_dir" | "ssh_sync");
+                
+                if let Some(session_id) = arguments["session_id"].as_str() {
+                    let target = arguments["path"].as_str().or(arguments["remote_path"].as_str()).unwrap_or_default();
+                    self.approve_on_session(session_id, tool_name, target, use_sudo, |session| {
+                        session.policy.check_operation(tool_name, use_sudo, writes)?;
+                        Ok(session.policy.operation_approval(use_sudo, write_path, session.cwd.as_deref(), directory))
+                    })?;
+                }
+                
+                self.with_session(arguments, |session_id, session| {
+                    match tool_name {
+                        "ssh_read_file" => files::read_file(session_id, session, arguments),
+                        "ssh_write_file" => files::write_file(session_id, session, arguments),
+                        "ssh_edit_file" => edit::edit_file(session_id, session, arguments),
+                        "ssh_upload_dir" => transfer::upload_dir(session_id, session, arguments),
+                        "ssh_download_dir" => transfer::download_dir(session_id, session, arguments),
+                        "ssh_sync" => sync::sync(session_id, session, arguments),
+                        "ssh_list_dir" => listing::list_dir(session_id, session, arguments),
+                        _ => listing::stat(session_id, session, arguments),
+                    }
+                })
+            }
+            
+            "ssh_copy_between" => {
+                let source_id = arguments["source_session_id"].as_str()
+                    .context("Missing or invalid source_session_id parameter - must be a string")?;
+                let dest_id = arguments["dest_session_id"].as_str()
+                    .context("Missing or invalid dest_session_id parameter - must be a string")?;
+                
+                let source_sudo = arguments["source_sudo"].as_bool().unwrap_or(false);
+                let dest_sudo = arguments["dest_sudo"].as_bool().unwrap_or(false);
+                
+                // Both endpoints are checked before any approval is asked, and approvals
+                // are asked without holding the sessions lock
+                let approvals = {
+                    let sessions = self.sessions.lock().unwrap();
+                    let endpoints = [
+                        (source_id, "source_path", source_sudo, false),
+                        (dest_id, "dest_path", dest_sudo, true),
+                    ];
+                    let mut checked = Vec::new();
+                    for (session_id, path_key, use_sudo, writes) in endpoints {
+                        let session_id = self.resolve_session_id(&sessions, session_id)?;
+                        let session = sessions.get(&session_id)
+                            .context(format!("No active session found with ID '{}'. Use ssh_list_sessions to see available sessions.", session_id))?;
+                        session.policy.check_operation(tool_name, use_sudo, writes)?;
+                        checked.push((session_id, session, path_key, use_sudo, writes));
+                    }
+                    
+                    let mut approvals = Vec::new();
+                    for (session_id, session, path_key, use_sudo, writes) in checked {
+                        let path = arguments[path_key].as_str().unwrap_or_default();
+                        // The destination may be a directory to copy into
+                        if let Some(reason) = session.policy.operation_approval(use_sudo, Some(path).filter(|_| writes), session.cwd.as_deref(), true) {
+                            approvals.push((session_id, session.host.clone(), path, use_sudo, reason));
+                        }
+                    }
+                    approvals
+                };
+                for (session_id, host, path, use_sudo, reason) in &approvals {
+                    self.require_approval(&ApprovalRequest {
+                        session_id,
+                        host,
+                        tool_name,
+                        target: path,
+                        use_sudo: *use_sudo,
+                        reason,
+                    })?;
+                }
+                
+                let mut sessions = self.sessions.lock().unwrap();
+                let source_id = &self.resolve_session_id(&sessions, source_id)?;
+                let dest_id = &self.resolve_session_id(&sessions, dest_id)?;
+                let source = sessions.get(source_id)
+                    .context(format!("No active session found with ID '{}'. Use ssh_list_sessions to see available sessions.", source_id))?;
+                let dest = sessions.get(dest_id)
+                    .context(format!("No active session found with ID '{}'. Use ssh_list_sessions to see available sessions.", dest_id))?;
+                
+                let progress_token = &request["params"]["_meta"]["progressToken"];
+                let mut progress = |done: u64, total: Option<u64>, message: &str| {
+                    if !progress_token.is_null() {
+                        self.send_notification("notifications/progress", json!({
+                            "progressToken": progress_token,
+                            "progress": done,
+                            "total": total,
+                            "message": message
+                        }));
+                    }
+                };
+                
+                let result = copy::copy_between(source, dest, arguments, &mut progress);
+                for id in [source_id, dest_id] {
+                    if let Some(session) = sessions.get_mut(id) {
+                        session.health.touch();
+                    }
+                }
+                result
+            }
+            
             _ => bail!("Unknown tool: {}", tool_name),
         }
     }
     
-    fn run(&self) -> Result<()> {
-        let stdin = io::stdin();
-        let stdout = io::stdout();
-        let mut stdout = stdout.lock();
+    /// Open a session as `ssh_connect` describes it; returns the result text
+    fn connect(&self, arguments: &Value) -> Result<String> {
+        let host = arguments["host"].as_str()
+            .context("Missing or invalid host parameter - must be a string")?;
         
-        // Send initial server info notification
-        let server_info = json!({
-            "jsonrpc": "2.0",
-            "method": "notifications/initialized",
-            "params": {
-                "protocolVersion": "2024-11-05",
-                "capabilities": {
-                    "tools": {}
-                },
-                "serverInfo": {
-                    "name": "mcp-ssh-sessions",
-                    "version": "0.1.0"
+        options::validate_host(host)?;
+        let mut connect_options = ConnectOptions::from_arguments(arguments)?;
+        self.config.keepalive.apply(&mut connect_options.ssh_options);
+        let idle_timeout = self.config.keepalive.idle_timeout(arguments)?;
+        
+        let session_id = arguments["session_id"].as_str()
+            .unwrap_or(host);
+        
+        eprintln!("[MCP] Attempting to connect to {} with session ID: {}", host, session_id);
+        
+        // Check if session already exists
+        {
+            let sessions = self.sessions.lock().unwrap();
+            if sessions.contains_key(session_id) {
+                bail!("Session '{}' already exists. Use ssh_disconnect first or choose a different session_id.", session_id);
+            }
+        }
+        
+        let profile = self.config.select_profile(arguments["profile"].as_str(), host)?;
+        let read_only = arguments["read_only"].as_bool().unwrap_or(false);
+        let persist = Persistence::from_arguments(arguments, session_id)?;
+        let cwd = match &arguments["cwd"] {
+            Value::Null => None,
+            Value::String(dir) => Some(dir.as_str()),
+            _ => bail!("Invalid cwd parameter - must be a string"),
+        };
+        let env = env_arguments(&arguments["env"])?;
+        
+        let target = connect_options.display_target(host);
+        self.connecting.lock().unwrap().insert(session_id.to_string(), host.to_string());
+        let connected = SshSession::connect(host, connect_options, persist)
+            .context(format!("Failed to establish SSH connection to {}", target))
+            .and_then(|mut session| {
+                session.health.idle_timeout = idle_timeout;
+                if let Some((name, profile)) = profile {
+                    eprintln!("[MCP] Applying profile '{}' to session '{}'", name, session_id);
+                    session.profile = Some(name.to_string());
+                    session.policy = profile.policy.clone();
+                }
+                // Can only tighten a profile, never lift its read-only flag
+                if read_only {
+                    session.policy.read_only = true;
+                }
+                // The policy is in place before `env` is applied, so it is checked too
+                let setup = cwd.map_or(Ok(()), |dir| session.set_cwd(dir))
+                    .and_then(|()| env.iter().try_for_each(|(name, value)| session.set_env(name, *value)));
+                match setup {
+                    Ok(()) => Ok(session),
+                    Err(e) => {
+                        let _ = session.disconnect();
+                        Err(e)
+                    }
+                }
+            });
+        let session = connected.inspect_err(|_| {
+            self.connecting.lock().unwrap().remove(session_id);
+        })?;
+        
+        let mut sessions = self.sessions.lock().unwrap();
+        self.connecting.lock().unwrap().remove(session_id);
+        // Another request may have taken the ID while this one was connecting
+        if sessions.contains_key(session_id) {
+            let _ = session.disconnect();
+            bail!("Session '{}' already exists. Use ssh_disconnect first or choose a different session_id.", session_id);
+        }
+        sessions.insert(session_id.to_string(), session);
+        
+        if let Some(store) = &self.store {
+            let mut saved = arguments.clone();
+            saved["session_id"] = json!(session_id);
+            store.save(session_id, &saved);
+        }
+        
+        eprintln!("[MCP] Successfully connected to {} (session: {})", target, session_id);
+        
+        let mut profile_note = match profile {
+            Some((name, _)) => format!(", profile: {}", name),
+            None => String::new(),
+        };
+        if read_only || profile.is_some_and(|(_, profile)| profile.policy.read_only) {
+            profile_note.push_str(", read-only");
+        }
+        if let Some(persist) = &sessions[session_id].persist {
+            profile_note.push_str(&format!(", {}", persist.describe()));
+        }
+        
+        Ok(format!("Successfully connected to {} (session: {}{})", target, session_id, profile_note))
+    }
+    
+    /// Handle `ssh_connect_group`: connect to every member of a group in parallel
+    fn connect_group(&self, arguments: &Value) -> Result<Value> {
+        let group = arguments["group"].as_str()
+            .context("Missing or invalid group parameter - must be a string")?;
+        let members = self.config.hosts.members(group)
+            .context(format!("Unknown group '{}'. Configured groups: {}", group,
+                self.config.hosts.groups.keys().cloned().collect::<Vec<_>>().join(", ")))?;
+        if members.is_empty() {
+            bail!("Group '{}' has no hosts", group);
+        }
+        let max_parallel = files::optional_u64(arguments, "max_parallel")?.unwrap_or(10).max(1) as usize;
+        
+        eprintln!("[MCP] Connecting to group '{}' ({} hosts)", group, members.len());
+        
+        let targets = members.iter().map(|host| (host.name.clone(), host.connect_arguments(arguments))).collect();
+        let results = self.connect_many(targets, max_parallel);
+        
+        let connected = results.iter().filter(|(_, _, result)| result.is_ok()).count();
+        eprintln!("[MCP] Connected {} of {} hosts in group '{}'", connected, results.len(), group);
+        
+        let mut response = connect_report(format!("Group '{}'", group), &results);
+        response["metadata"]["group"] = json!(group);
+        Ok(response)
+    }
+    
+    /// Handle `ssh_restore_sessions`, and restoring on startup: reconnect saved sessions
+    fn restore_sessions(&self, arguments: &Value) -> Result<Value> {
+        let store = self.store.as_ref()
+            .context("Sessions are not saved (set save_sessions to true in the config)")?;
+        let selected: Option<Vec<&str>> = match &arguments["session_ids"] {
+            Value::Null => None,
+            Value::Array(ids) => Some(ids.iter().filter_map(Value::as_str).collect()),
+            _ => bail!("Invalid session_ids parameter - must be an array of strings"),
+        };
+        let max_parallel = files::optional_u64(arguments, "max_parallel")?.unwrap_or(10).max(1) as usize;
+        
+        let targets: Vec<(String, Value)> = store.sessions().into_iter()
+            .filter(|(id, _)| selected.as_ref().is_none_or(|selected| selected.contains(&id.as_str())))
+            .collect();
+        eprintln!("[MCP] Restoring {} saved sessions", targets.len());
+        
+        let results = self.connect_many(targets, max_parallel);
+        for (session_id, arguments, result) in &results {
+            let mut fields = serde_json::Map::new();
+            fields.insert("session_id".to_string(), json!(session_id));
+            fields.insert("host".to_string(), arguments["host"].clone());
+            fields.insert("outcome".to_string(), json!(if result.is_ok() { "ok" } else { "error" }));
+            fields.insert("error".to_string(), json!(result.as_ref().err().map(|e| format!("{:#}", e))));
+            self.audit_record("session_restore", fields);
+        }
+        
+        let connected = results.iter().filter(|(_, _, result)| result.is_ok()).count();
+        eprintln!("[MCP] Restored {} of {} saved sessions", connected, results.len());
+        
+        if results.is_empty() {
+            return Ok(json!({
+                "content": [{
+                    "type": "text",
+                    "text": "No saved sessions to restore"
+                }]
+            }));
+        }
+        Ok(connect_report("Saved sessions".to_string(), &results))
+    }
+    
+    /// Drop a disconnected session from the state file; one whose remote side is
+    /// kept running stays saved so it can be restored
+    fn forget_session(&self, session_id: &str, keep_remote: bool) {
+        if let Some(store) = &self.store {
+            if !keep_remote {
+                store.remove(session_id);
+            }
+        }
+    }
+    
+    /// Check sessions every `interval` until the server shuts down
+    fn heartbeat_loop(&self, interval: Duration) {
+        let mut next = Instant::now() + interval;
+        while !self.shutdown.load(Ordering::SeqCst) {
+            if Instant::now() < next {
+                thread::sleep(Duration::from_millis(200));
+                continue;
+            }
+            self.heartbeat();
+            next = Instant::now() + interval;
+        }
+    }
+    
+    /// Disconnect sessions past their idle timeout, and check the others' relay and
+    /// master connection without opening a channel, so a busy session is not disturbed
+    fn heartbeat(&self) {
+        let mut idle = Vec::new();
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            // Port forwards may be in use without any tool calls
+            let expired: Vec<String> = sessions.iter()
+                .filter(|(_, session)| session.health.is_idle() && session.forwards.len() == 0)
+                .map(|(id, _)| id.clone())
+                .collect();
+            for id in expired {
+                if let Some(session) = sessions.remove(&id) {
+                    idle.push((id, session));
+                }
+            }
+            
+            for (id, session) in sessions.iter_mut() {
+                // The connection may be fine while the relay itself is gone
+                let result = match session.child.try_wait() {
+                    Ok(Some(status)) => Err(format!("relay exited ({})", status)),
+                    Ok(None) if session.control.check() => Ok(()),
+                    Ok(None) => Err("control master connection is gone".to_string()),
+                    Err(e) => Err(format!("cannot check relay: {}", e)),
+                };
+                match (&session.health.failure, &result) {
+                    (None, Err(reason)) => eprintln!("[MCP] Session '{}' dead: {}", id, reason),
+                    (Some(_), Ok(())) => eprintln!("[MCP] Session '{}' alive again", id),
+                    _ => {}
+                }
+                session.health.record(result);
+            }
+        }
+        
+        for (id, session) in idle {
+            let idle_seconds = session.health.last_used.elapsed().as_secs();
+            eprintln!("[MCP] Session '{}' idle for {}s, disconnecting", id, idle_seconds);
+            let mut fields = serde_json::Map::new();
+            fields.insert("session_id".to_string(), json!(id));
+            fields.insert("host".to_string(), json!(session.host));
+            fields.insert("idle_seconds".to_string(), json!(idle_seconds));
+            self.audit_record("session_idle_timeout", fields);
+            // Stays saved, and a persistent session's remote side keeps running, so it can be restored
+            if let Err(e) = session.close(true) {
+                eprintln!("[MCP] Failed to cleanly disconnect idle session '{}': {:#}", id, e);
+            }
+        }
+    }
+    
+    /// Connect sessions in parallel from `ssh_connect` arguments; already connected ones are skipped
+    fn connect_many(&self, targets: Vec<(String, Value)>, max_parallel: usize) -> Vec<(String, Value, Result<String>)> {
+        let count = targets.len();
+        let queue = Mutex::new(targets.into_iter().enumerate().collect::<VecDeque<_>>());
+        let results = Mutex::new(Vec::with_capacity(count));
+        thread::scope(|scope| {
+            for _ in 0..max_parallel.min(count) {
+                scope.spawn(|| loop {
+                    let Some((index, (session_id, arguments))) = queue.lock().unwrap().pop_front() else { break };
+                    let result = if self.sessions.lock().unwrap().contains_key(&session_id) {
+                        Ok("already connected".to_string())
+                    } else {
+                        self.connect(&arguments)
+                    };
+                    results.lock().unwrap().push((index, session_id, arguments, result));
+                });
+            }
+        });
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(index, _, _, _)| *index);
+        results.into_iter().map(|(_, session_id, arguments, result)| (session_id, arguments, result)).collect()
+    }
+    
+    /// Connected session IDs of `name` if it is a group rather than a session
+    fn group_sessions(&self, sessions: &HashMap<String, SshSession>, name: &str) -> Option<Vec<String>> {
+        if sessions.contains_key(name) {
+            return None;
+        }
+        self.config.hosts.session_ids(name, sessions)
+    }
+    
+    /// Replace group names in the `sessions` argument with their connected session IDs
+    fn expand_groups(&self, sessions: &HashMap<String, SshSession>, arguments: &Value) -> Result<Value> {
+        let mut arguments = arguments.clone();
+        if let Value::Array(names) = &arguments["sessions"] {
+            let mut expanded = Vec::new();
+            for name in names {
+                match name.as_str().and_then(|name| Some((name, self.group_sessions(sessions, name)?))) {
+                    Some((name, ids)) if ids.is_empty() => bail!("Group '{}' has no connected sessions. Use ssh_connect_group first.", name),
+                    Some((_, ids)) => expanded.extend(ids.into_iter().map(Value::String)),
+                    None => expanded.push(name.clone()),
                 }
             }
+            arguments["sessions"] = Value::Array(expanded);
+        }
+        Ok(arguments)
+    }
+    
+    /// Resolve a session ID, or a group with exactly one connected session, to a session ID
+    fn resolve_session_id(&self, sessions: &HashMap<String, SshSession>, name: &str) -> Result<String> {
+        match self.group_sessions(sessions, name) {
+            None if sessions.contains_key(name) => Ok(name.to_string()),
+            None => bail!("No active session found with ID '{}'. Use ssh_list_sessions to see available sessions.", name),
+            Some(ids) if ids.len() == 1 => Ok(ids.into_iter().next().unwrap_or_default()),
+            Some(ids) if ids.is_empty() => bail!("Group '{}' has no connected sessions. Use ssh_connect_group first.", name),
+            Some(ids) => bail!("Group '{}' has {} connected sessions ({}); this tool needs a single session. Use ssh_execute_many for groups.",
+                name, ids.len(), ids.join(", ")),
+        }
+    }
+    
+    /// Run `f` on the session named by the `session_id` argument
+    fn with_session<T>(&self, arguments: &Value, f: impl FnOnce(&str, &mut SshSession) -> Result<T>) -> Result<T> {
+        let session_id = arguments["session_id"].as_str()
+            .context("Missing or invalid session_id parameter - must be a string")?;
+        
+        let mut sessions = self.sessions.lock().unwrap();
+        let session_id = self.resolve_session_id(&sessions, session_id)?;
+        let session = sessions.get_mut(&session_id)
+            .context(format!("No active session found with ID '{}'. Use ssh_list_sessions to see available sessions.", session_id))?;
+        
+        let result = f(&session_id, session);
+        session.health.touch();
+        result
+    }
+    
+    /// Check an operation against the policy of the session named `name` and ask for approval if
+    /// `check` returns a reason. The sessions lock is released before asking, so callers look the
+    /// session up again afterwards
+    fn approve_on_session(&self, name: &str, tool_name: &str, target: &str, use_sudo: bool,
+                          check: impl FnOnce(&SshSession) -> Result<Option<String>>) -> Result<()> {
+        let (session_id, host, reason) = {
+            let sessions = self.sessions.lock().unwrap();
+            let session_id = self.resolve_session_id(&sessions, name)?;
+            let session = sessions.get(&session_id)
+                .context(format!("No active session found with ID '{}'. Use ssh_list_sessions to see available sessions.", session_id))?;
+            let reason = check(session)?;
+            (session_id, session.host.clone(), reason)
+        };
+        match reason {
+            Some(reason) => self.require_approval(&ApprovalRequest {
+                session_id: &session_id,
+                host: &host,
+                tool_name,
+                target,
+                use_sudo,
+                reason: &reason,
+            }),
+            None => Ok(()),
+        }
+    }
+    
+    /// Ask the user to confirm an operation held by policy; fails unless they approve
+    fn require_approval(&self, approval: &ApprovalRequest) -> Result<()> {
+        let supported = self.client.lock().unwrap()["capabilities"]["elicitation"].is_object();
+        if !supported {
+            eprintln!("[MCP] Approval unavailable, denied: {}", approval.describe());
+            self.audit_approval(approval, "unavailable");
+            bail!("Policy denied: {} needs approval ({}), but the client does not support elicitation",
+                approval.tool_name, approval.reason);
+        }
+        
+        eprintln!("[MCP] Requesting approval: {}", approval.describe());
+        let result = self.send_request("elicitation/create", approval.elicitation_params())
+            .inspect_err(|_| self.audit_approval(approval, "failed"))
+            .context("Failed to request approval")?;
+        
+        if !approval::is_approved(&result) {
+            let action = result["action"].as_str().unwrap_or("invalid response");
+            eprintln!("[MCP] Approval declined ({}): {}", action, approval.describe());
+            self.audit_approval(approval, action);
+            bail!("Approval declined: {} was not run ({})", approval.tool_name, approval.reason);
+        }
+        eprintln!("[MCP] Approval granted: {}", approval.describe());
+        self.audit_approval(approval, "approved");
+        Ok(())
+    }
+    
+    fn audit_approval(&self, approval: &ApprovalRequest, decision: &str) {
+        let mut fields = serde_json::Map::new();
+        fields.insert("client".to_string(), self.client.lock().unwrap()["clientInfo"].clone());
+        fields.insert("session_id".to_string(), json!(approval.session_id));
+        fields.insert("host".to_string(), json!(approval.host));
+        fields.insert("tool".to_string(), json!(approval.tool_name));
+        fields.insert("target".to_string(), json!(approval.target));
+        fields.insert("sudo".to_string(), json!(approval.use_sudo));
+        fields.insert("reason".to_string(), json!(approval.reason));
+        fields.insert("decision".to_string(), json!(decision));
+        self.audit_record("approval", fields);
+    }
+    
+    /// Send a JSON-RPC request to the client and wait for its response.
+    /// Other messages arriving meanwhile are handled after the current request.
+    fn send_request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = format!("mcp-ssh-{}", self.next_request_id.fetch_add(1, Ordering::Relaxed));
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params
         });
         
-        writeln!(stdout, "{}", server_info)?;
+        let mut stdout = io::stdout();
+        writeln!(stdout, "{}", request)?;
         stdout.flush()?;
-        eprintln!("[MCP] Server initialized and capabilities sent");
         
-        for line in stdin.lock().lines() {
+        loop {
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line)? == 0 {
+                bail!("Client closed the connection while waiting for the {} response", method);
+            }
+            
+            match serde_json::from_str::<Value>(&line) {
+                Ok(message) if message["id"] == id && message.get("method").is_none() => {
+                    if let Some(error) = message.get("error") {
+                        bail!("Client returned an error for {}: {}", method,
+                            error["message"].as_str().unwrap_or("unknown error"));
+                    }
+                    return Ok(message["result"].clone());
+                }
+                _ => self.deferred.lock().unwrap().push_back(line),
+            }
+        }
+    }
+    
+    /// Next message from the client, deferred ones first; `None` at end of input
+    fn next_message(&self) -> Option<io::Result<String>> {
+        if let Some(line) = self.deferred.lock().unwrap().pop_front() {
+            return Some(Ok(line));
+        }
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(line)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+    
+    /// Send a JSON-RPC notification to the client while a request is being handled
+    fn send_notification(&self, method: &str, params: Value) {
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params
+        });
+        
+        // Requests are handled on the thread that holds the stdout lock in run(),
+        // and that lock is reentrant
+        let mut stdout = io::stdout();
+        if let Err(e) = writeln!(stdout, "{}", notification).and_then(|()| stdout.flush()) {
+            eprintln!("[MCP] Failed to send {} notification: {}", method, e);
+        }
+    }
+    
+    /// Answer requests until stdin closes
+    fn serve(&self, stdout: &mut io::StdoutLock) -> Result<()> {
+        // stdin is not held locked here, so requests to the client can read responses mid-call
+        while let Some(line) = self.next_message() {
             let line = match line {
                 Ok(line) => line,
                 Err(e) => {
@@ -482,13 +1973,14 @@ impl McpServer {
                 }
             };
             
+            let line = line.trim_end();
             if line.trim().is_empty() {
                 continue;
             }
             
-            eprintln!("[MCP] Received: {}", line);
+            eprintln!("[MCP] Received: {}", redact::text(line));
             
-            let request: Value = match serde_json::from_str(&line) {
+            let request: Value = match serde_json::from_str(line) {
                 Ok(req) => req,
                 Err(e) => {
                     eprintln!("[MCP] JSON parse error: {}", e);
@@ -516,7 +2008,7 @@ impl McpServer {
                     })
                 }
                 Err(e) => {
-                    eprintln!("[MCP] Request error: {}", e);
+                    eprintln!("[MCP] Request error: {}", redact::text(&e.to_string()));
                     json!({
                         "jsonrpc": "2.0",
                         "id": request["id"],
@@ -528,11 +2020,62 @@ impl McpServer {
                 }
             };
             
+            // Tool output and error messages can carry secrets printed on the host
+            let mut response = response;
+            if redact::value(&mut response) && response["result"]["content"].is_array() {
+                response["result"]["metadata"]["redacted"] = json!(true);
+            }
+            
             eprintln!("[MCP] Sending response: {}", serde_json::to_string(&response)?);
             writeln!(stdout, "{}", response)?;
             stdout.flush()?;
         }
         
+        Ok(())
+    }
+    
+    fn run(&self) -> Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        
+        // Send initial server info notification
+        let server_info = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/initialized",
+            "params": {
+                "protocolVersion": "2024-11-05",
+                "capabilities": {
+                    "tools": {}
+                },
+                "serverInfo": {
+                    "name": "mcp-ssh-sessions",
+                    "version": "0.1.0"
+                }
+            }
+        });
+        
+        writeln!(stdout, "{}", server_info)?;
+        stdout.flush()?;
+        eprintln!("[MCP] Server initialized and capabilities sent");
+        
+        thread::scope(|scope| -> Result<()> {
+            if let Some(interval) = self.config.keepalive.heartbeat_interval() {
+                scope.spawn(move || self.heartbeat_loop(interval));
+            }
+            // Saved sessions reconnect in the background while requests are served
+            if self.config.restore_sessions && self.store.is_some() {
+                scope.spawn(|| {
+                    if let Err(e) = self.restore_sessions(&json!({})) {
+                        eprintln!("[MCP] Failed to restore saved sessions: {:#}", e);
+                    }
+                });
+            }
+            
+            let served = self.serve(&mut stdout);
+            self.shutdown.store(true, Ordering::SeqCst);
+            served
+        })?;
+        
         // Cleanup all sessions on exit
         eprintln!("[MCP] Server shutting down, cleaning up sessions");
         let mut sessions = self.sessions.lock().unwrap();
@@ -558,7 +2101,60 @@ fn main() -> Result<()> {
         eprintln!("[MAIN] Protocol: JSON-RPC over stdin/stdout");
         eprintln!("[MAIN] Features: SSH connections with bash relay, sudo support via X11 forwarding");
         
-        let server = McpServer::new();
+        let config_path = match std::env::args().skip(1).collect::<Vec<_>>().as_slice() {
+            [] => None,
+            [flag, path] if flag == "--config" => Some(std::path::PathBuf::from(path)),
+            [command, path] if command == "verify-audit" => {
+                return match audit::verify(std::path::Path::new(path)) {
+                    Ok((count, last_hash)) => {
+                        println!("Audit log OK: {} entries, hash chain intact, last hash {}", count, last_hash);
+                        0
+                    }
+                    Err(e) => {
+                        println!("Audit log verification FAILED: {:#}", e);
+                        1
+                    }
+                };
+            }
+            _ => {
+                eprintln!("Usage: mcp-ssh-sessions [--config <path>]");
+                eprintln!("       mcp-ssh-sessions verify-audit <audit-log>");
+                return 2;
+            }
+        };
+        let config = match Config::load(config_path.as_deref()) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("[MAIN] {:#}", e);
+                return 1;
+            }
+        };
+        
+        match redact::Redactor::new(&config.redaction) {
+            Ok(redactor) => redact::install(redactor),
+            Err(e) => {
+                eprintln!("[MAIN] {:#}", e);
+                return 1;
+            }
+        }
+        
+        let audit = match config.audit_log.as_deref().map(AuditLog::open).transpose() {
+            Ok(audit) => audit,
+            Err(e) => {
+                eprintln!("[MAIN] {:#}", e);
+                return 1;
+            }
+        };
+        
+        let store = match config.state_path().as_deref().map(SessionStore::open).transpose() {
+            Ok(store) => store,
+            Err(e) => {
+                eprintln!("[MAIN] {:#}", e);
+                return 1;
+            }
+        };
+        
+        let server = McpServer::new(config, audit, store);
         match server.run() {
             Ok(()) => {
                 eprintln!("[MAIN] Server shutdown gracefully");
@@ -0,0 +1,314 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// Connection parameters shared by the relay bootstrap (scp) and the ssh session
+
+/// Extra `-o` options callers may pass through. Anything that can run local
+/// commands or redirect the control socket (ProxyCommand, LocalCommand,
+/// ControlPath, ...) is deliberately left out.
+const ALLOWED_SSH_OPTIONS: &[&str] = &[
+    "AddressFamily",
+    "BatchMode",
+    "CheckHostIP",
+    "Ciphers",
+    "Compression",
+    "ConnectionAttempts",
+    "HostKeyAlgorithms",
+    "HostKeyAlias",
+    "HostbasedAuthentication",
+    "IdentitiesOnly",
+    "IPQoS",
+    "KbdInteractiveAuthentication",
+    "KexAlgorithms",
+    "LogLevel",
+    "MACs",
+    "PasswordAuthentication",
+    "PreferredAuthentications",
+    "ProxyJump",
+    "PubkeyAcceptedAlgorithms",
+    "PubkeyAuthentication",
+    "ServerAliveCountMax",
+    "ServerAliveInterval",
+    "StrictHostKeyChecking",
+    "TCPKeepAlive",
+    "UpdateHostKeys",
+    "UserKnownHostsFile",
+    "VerifyHostKeyDNS",
+];
+
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConnectOptions {
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_file: Option<String>,
+    pub connect_timeout: Option<u64>,
+    /// Validated extra options, keyed by their canonical spelling
+    #[serde(default)]
+    pub ssh_options: BTreeMap<String, String>,
+}
+
+impl ConnectOptions {
+    /// Parse the optional connection fields of an `ssh_connect` call
+    pub fn from_arguments(arguments: &Value) -> Result<Self> {
+        let user = optional_str(arguments, "user")?;
+        if let Some(user) = &user {
+            if user.is_empty() || user.starts_with('-') || user.contains(['@', ' ', '\n', '\0']) {
+                bail!("Invalid user parameter: '{}'", user);
+            }
+        }
+
+        let port = match &arguments["port"] {
+            Value::Null => None,
+            value => {
+                let port = value.as_u64()
+                    .context("Invalid port parameter - must be an integer")?;
+                if port == 0 || port > u16::MAX as u64 {
+                    bail!("Invalid port parameter: {} (must be 1-65535)", port);
+                }
+                Some(port as u16)
+            }
+        };
+
+        let identity_file = optional_str(arguments, "identity_file")?;
+        if let Some(path) = &identity_file {
+            if path.is_empty() || path.contains(['\n', '\0']) {
+                bail!("Invalid identity_file parameter");
+            }
+        }
+
+        let connect_timeout = match &arguments["connect_timeout"] {
+            Value::Null => None,
+            value => {
+                let timeout = value.as_u64()
+                    .context("Invalid connect_timeout parameter - must be a positive integer (seconds)")?;
+                if timeout == 0 {
+                    bail!("connect_timeout must be at least 1 second");
+                }
+                Some(timeout)
+            }
+        };
+
+        let mut ssh_options = BTreeMap::new();
+        match &arguments["ssh_options"] {
+            Value::Null => {}
+            Value::Object(map) => {
+                for (key, value) in map {
+                    let value = match value {
+                        Value::String(s) => s.clone(),
+                        Value::Number(n) => n.to_string(),
+                        Value::Bool(b) => if *b { "yes".to_string() } else { "no".to_string() },
+                        _ => bail!("Invalid value for ssh option '{}' - must be a string, number or boolean", key),
+                    };
+                    let (key, value) = validate_ssh_option(key, &value)?;
+                    ssh_options.insert(key, value);
+                }
+            }
+            _ => bail!("Invalid ssh_options parameter - must be an object of option names to values"),
+        }
+
+        Ok(ConnectOptions {
+            user,
+            port,
+            identity_file,
+            connect_timeout,
+            ssh_options,
+        })
+    }
+
+    pub fn connect_timeout(&self) -> u64 {
+        self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT)
+    }
+
+    /// Common `-o`/`-i` arguments understood by both ssh and scp
+    pub fn common_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "-o".to_string(),
+            format!("ConnectTimeout={}", self.connect_timeout()),
+        ];
+        if !self.ssh_options.contains_key("StrictHostKeyChecking") {
+            args.push("-o".to_string());
+            args.push("StrictHostKeyChecking=no".to_string());
+        }
+        if let Some(user) = &self.user {
+            args.push("-o".to_string());
+            args.push(format!("User={}", user));
+        }
+        if let Some(port) = self.port {
+            args.push("-o".to_string());
+            args.push(format!("Port={}", port));
+        }
+        if let Some(identity_file) = &self.identity_file {
+            args.push("-i".to_string());
+            args.push(identity_file.clone());
+        }
+        for (key, value) in &self.ssh_options {
+            args.push("-o".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+        args
+    }
+
+    /// Human-readable target, e.g. `deploy@db1:2222`
+    pub fn display_target(&self, host: &str) -> String {
+        let mut target = match &self.user {
+            Some(user) => format!("{}@{}", user, host),
+            None => host.to_string(),
+        };
+        if let Some(port) = self.port {
+            target.push_str(&format!(":{}", port));
+        }
+        target
+    }
+}
+
+/// Reject hosts that ssh would interpret as options or that break the command line
+pub fn validate_host(host: &str) -> Result<()> {
+    if host.is_empty() {
+        bail!("Host parameter cannot be empty");
+    }
+    if host.starts_with('-') || host.contains(|c: char| c.is_whitespace() || c.is_control()) {
+        bail!("Invalid host parameter: '{}'", host);
+    }
+    Ok(())
+}
+
+fn validate_ssh_option(key: &str, value: &str) -> Result<(String, String)> {
+    let canonical = ALLOWED_SSH_OPTIONS.iter()
+        .find(|allowed| allowed.eq_ignore_ascii_case(key))
+        .with_context(|| format!(
+            "SSH option '{}' is not allowed. Allowed options: {}", key, ALLOWED_SSH_OPTIONS.join(", ")))?;
+
+    if value.is_empty() || value.contains(|c: char| c.is_control()) {
+        bail!("Invalid value for SSH option '{}'", canonical);
+    }
+    // Each `-o` is one config line, where whitespace separates further arguments;
+    // only UserKnownHostsFile takes several
+    if value.contains(char::is_whitespace) && *canonical != "UserKnownHostsFile" {
+        bail!("Invalid value for SSH option '{}': must not contain spaces", canonical);
+    }
+    // ssh runs jump hosts through a shell command
+    if *canonical == "ProxyJump" && (value.split(',').any(|hop| hop.starts_with('-'))
+        || !value.chars().all(|c| c.is_ascii_alphanumeric() || "@:.,-_[]/".contains(c))) {
+        bail!("Invalid value for SSH option 'ProxyJump': '{}'", value);
+    }
+
+    Ok((canonical.to_string(), value.to_string()))
+}
+
+fn optional_str(arguments: &Value, key: &str) -> Result<Option<String>> {
+    match &arguments[key] {
+        Value::Null => Ok(None),
+        Value::String(s) => Ok(Some(s.clone())),
+        _ => bail!("Invalid {} parameter - must be a string", key),
+    }
+}
+
+pub fn tool_schema_properties() -> Value {
+    serde_json::json!({
+        "user": {
+            "type": "string",
+            "description": "Remote user name (defaults to your ssh config)"
+        },
+        "port": {
+            "type": "integer",
+            "description": "Remote SSH port (defaults to your ssh config)"
+        },
+        "identity_file": {
+            "type": "string",
+            "description": "Path to a private key file to use"
+        },
+        "connect_timeout": {
+            "type": "integer",
+            "description": "Connection timeout in seconds",
+            "default": DEFAULT_CONNECT_TIMEOUT
+        },
+        "ssh_options": {
+            "type": "object",
+            "description": format!("Extra ssh -o options as name/value pairs. Allowed: {}", ALLOWED_SSH_OPTIONS.join(", ")),
+            "additionalProperties": { "type": ["string", "integer", "boolean"] }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn options(ssh_options: Value) -> Result<BTreeMap<String, String>> {
+        ConnectOptions::from_arguments(&json!({ "ssh_options": ssh_options })).map(|options| options.ssh_options)
+    }
+
+    #[test]
+    fn accepts_allowed_options_in_any_case() {
+        let parsed = options(json!({
+            "serveraliveinterval": 30,
+            "PROXYJUMP": "admin@bastion:2222,ssh://jump.example.com",
+            "Compression": true,
+            "batchmode": false,
+            "Ciphers": "aes256-gcm@openssh.com,aes128-ctr"
+        })).unwrap();
+        assert_eq!(parsed["ServerAliveInterval"], "30");
+        assert_eq!(parsed["ProxyJump"], "admin@bastion:2222,ssh://jump.example.com");
+        assert_eq!(parsed["Compression"], "yes");
+        assert_eq!(parsed["BatchMode"], "no");
+        assert_eq!(parsed["Ciphers"], "aes256-gcm@openssh.com,aes128-ctr");
+    }
+
+    #[test]
+    fn rejects_options_that_run_commands_or_move_the_socket() {
+        for key in ["ProxyCommand", "proxycommand", "LocalCommand", "PermitLocalCommand", "ControlPath",
+                    "ControlMaster", "KnownHostsCommand", "Match", "Include", "LogLevel=ERROR", ""] {
+            let error = options(json!({ key: "x" })).unwrap_err().to_string();
+            assert!(error.contains("is not allowed"), "{}: {}", key, error);
+        }
+    }
+
+    #[test]
+    fn rejects_values_that_add_arguments() {
+        assert!(options(json!({ "LogLevel": "ERROR ProxyCommand=x" })).is_err());
+        assert!(options(json!({ "ProxyJump": "bastion -oProxyCommand=x" })).is_err());
+        assert!(options(json!({ "ProxyJump": "-oProxyCommand=x" })).is_err());
+        assert!(options(json!({ "ProxyJump": "a,-b" })).is_err());
+        assert!(options(json!({ "ProxyJump": "bastion;id" })).is_err());
+        assert!(options(json!({ "LogLevel": "ERROR\nProxyCommand x" })).is_err());
+        assert!(options(json!({ "LogLevel": "" })).is_err());
+        assert!(options(json!({ "LogLevel": ["ERROR"] })).is_err());
+        assert!(options(json!("LogLevel=ERROR")).is_err());
+    }
+
+    #[test]
+    fn keeps_equals_signs_and_known_hosts_lists() {
+        // ssh splits an option at its first '=', so later ones stay part of the value
+        let parsed = options(json!({ "UserKnownHostsFile": "/tmp/a=b /tmp/known_hosts" })).unwrap();
+        assert_eq!(parsed["UserKnownHostsFile"], "/tmp/a=b /tmp/known_hosts");
+        let args = ConnectOptions { ssh_options: parsed, ..ConnectOptions::default() }.common_args();
+        assert!(args.contains(&"UserKnownHostsFile=/tmp/a=b /tmp/known_hosts".to_string()));
+    }
+
+    #[test]
+    fn strict_host_key_checking_default_yields_to_the_caller() {
+        let args = ConnectOptions::default().common_args();
+        assert!(args.contains(&"StrictHostKeyChecking=no".to_string()));
+        let parsed = options(json!({ "stricthostkeychecking": "yes" })).unwrap();
+        let args = ConnectOptions { ssh_options: parsed, ..ConnectOptions::default() }.common_args();
+        assert!(args.contains(&"StrictHostKeyChecking=yes".to_string()));
+        assert!(!args.contains(&"StrictHostKeyChecking=no".to_string()));
+    }
+
+    #[test]
+    fn validates_connection_fields() {
+        assert!(ConnectOptions::from_arguments(&json!({ "user": "-oProxyCommand=x" })).is_err());
+        assert!(ConnectOptions::from_arguments(&json!({ "user": "a@b" })).is_err());
+        assert!(ConnectOptions::from_arguments(&json!({ "port": 0 })).is_err());
+        assert!(ConnectOptions::from_arguments(&json!({ "port": 65536 })).is_err());
+        assert!(ConnectOptions::from_arguments(&json!({ "connect_timeout": 0 })).is_err());
+        assert!(validate_host("-oProxyCommand=x").is_err());
+        assert!(validate_host("db 1").is_err());
+        assert!(validate_host("deploy@db1.example.com").is_ok());
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use crate::files::sha256_hex;
+
+// Persistent sessions: the relay runs inside a remote tmux or screen session and
+// outlives the SSH connection, so a restarted server can attach to it again
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Multiplexer {
+    Tmux,
+    Screen,
+}
+
+impl Multiplexer {
+    pub fn name(self) -> &'static str {
+        match self {
+            Multiplexer::Tmux => "tmux",
+            Multiplexer::Screen => "screen",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Persistence {
+    pub multiplexer: Multiplexer,
+    /// Name of the remote tmux/screen session, derived from the session ID
+    pub name: String,
+    /// Whether connecting found the remote session still running
+    pub reattached: bool,
+}
+
+pub fn schema_property() -> Value {
+    json!({
+        "type": "string",
+        "enum": ["tmux", "screen"],
+        "description": "Run the remote shell inside a named tmux or screen session that survives server restarts; connecting again with the same session_id re-attaches to it"
+    })
+}
+
+impl Persistence {
+    /// Read the `persist` argument of `ssh_connect`
+    pub fn from_arguments(arguments: &Value, session_id: &str) -> Result<Option<Self>> {
+        let multiplexer = match &arguments["persist"] {
+            Value::Null => return Ok(None),
+            Value::String(name) if name == "tmux" => Multiplexer::Tmux,
+            Value::String(name) if name == "screen" => Multiplexer::Screen,
+            _ => bail!("Invalid persist parameter - must be \"tmux\" or \"screen\""),
+        };
+        Ok(Some(Persistence {
+            multiplexer,
+            name: remote_name(session_id),
+            reattached: false,
+        }))
+    }
+
+    /// Arguments making the relay attach to (or start) the remote session
+    pub fn relay_args(&self) -> [&str; 3] {
+        ["attach", &self.name, self.multiplexer.name()]
+    }
+
+    /// Remote command ending the tmux/screen session and removing its directory
+    pub fn terminate_command(&self) -> String {
+        let quit = match self.multiplexer {
+            Multiplexer::Tmux => format!("tmux kill-session -t ={}", self.name),
+            Multiplexer::Screen => format!("screen -S {} -X quit", self.name),
+        };
+        format!("{} 2>/dev/null; rm -rf -- \"${{XDG_STATE_HOME:-$HOME/.local/state}}/mcp-ssh/sessions/{}\"", quit, self.name)
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{} session {}{}", self.multiplexer.name(), self.name, if self.reattached { ", re-attached" } else { "" })
+    }
+}
+
+/// tmux and screen session names cannot contain `.` or `:`, and the name ends up
+/// in remote paths, so keep only safe characters. A short hash of the session ID
+/// keeps IDs that differ only in replaced characters (`web.1`, `web_1`) apart.
+fn remote_name(session_id: &str) -> String {
+    let safe: String = session_id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .collect();
+    format!("mcp-ssh-{}-{}", safe, &sha256_hex(session_id.as_bytes())[..8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_names_are_safe_and_distinct() {
+        let dotted = remote_name("web.1");
+        assert!(dotted.starts_with("mcp-ssh-web_1-"), "{}", dotted);
+        assert_ne!(dotted, remote_name("web_1"));
+        assert_ne!(remote_name("a:b"), remote_name("a/b"));
+        assert_eq!(remote_name("web.1"), dotted);
+        assert!(remote_name("x; rm -rf ~").chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'));
+    }
+}
@@ -0,0 +1,303 @@
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::glob;
+use crate::readonly;
+
+// Per-profile command policy, consulted before anything runs on a host
+
+/// Environment variables that change what an otherwise permitted command runs
+/// (startup files, preloaded libraries, helper programs git and pagers invoke)
+const PROTECTED_ENV: &[&str] = &[
+    "BASH_ENV", "ENV", "BASH_FUNC_*", "SHELLOPTS", "BASHOPTS", "IFS", "PS4", "PATH",
+    "LD_*", "GIT_*", "PAGER", "*_PAGER", "*_COMMAND", "EDITOR", "VISUAL", "LESSOPEN", "LESSCLOSE",
+    "PYTHON*", "PERL5*", "RUBY*", "NODE_OPTIONS",
+];
+
+/// A command rule as written in the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleConfig {
+    /// Regular expression searched anywhere in the command
+    Regex(String),
+    /// Shell-style wildcard (`*`, `?`) matched against the whole command
+    Glob(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyConfig {
+    #[serde(default)]
+    pub allow: Vec<RuleConfig>,
+    #[serde(default)]
+    pub deny: Vec<RuleConfig>,
+    #[serde(default = "default_true")]
+    pub allow_sudo: bool,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub require_approval: ApprovalConfig,
+}
+
+/// Operations that are allowed, but only after the user confirms them
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApprovalConfig {
+    /// Ask before anything runs with sudo
+    #[serde(default)]
+    pub sudo: bool,
+    #[serde(default)]
+    pub commands: Vec<RuleConfig>,
+    /// Path globs; file tools writing to a matching path need approval
+    #[serde(default)]
+    pub write_paths: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    source: String,
+    regex: Regex,
+}
+
+impl Rule {
+    fn compile(config: &RuleConfig) -> Result<Self> {
+        let (source, pattern) = match config {
+            RuleConfig::Regex(pattern) => (format!("regex {}", pattern), pattern.clone()),
+            RuleConfig::Glob(pattern) => (format!("glob {}", pattern), glob_to_regex(pattern)),
+        };
+        let regex = Regex::new(&pattern)
+            .context(format!("Invalid policy rule: {}", source))?;
+        Ok(Rule { source, regex })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "PolicyConfig")]
+pub struct Policy {
+    allow: Vec<Rule>,
+    deny: Vec<Rule>,
+    pub allow_sudo: bool,
+    pub read_only: bool,
+    approve_sudo: bool,
+    approve_commands: Vec<Rule>,
+    approve_write_paths: Vec<String>,
+}
+
+impl Default for Policy {
+    /// No restrictions - the behaviour without a config file
+    fn default() -> Self {
+        Policy {
+            allow: Vec::new(),
+            deny: Vec::new(),
+            allow_sudo: true,
+            read_only: false,
+            approve_sudo: false,
+            approve_commands: Vec::new(),
+            approve_write_paths: Vec::new(),
+        }
+    }
+}
+
+impl TryFrom<PolicyConfig> for Policy {
+    type Error = anyhow::Error;
+
+    fn try_from(config: PolicyConfig) -> Result<Self> {
+        Ok(Policy {
+            allow: config.allow.iter().map(Rule::compile).collect::<Result<_>>()?,
+            deny: config.deny.iter().map(Rule::compile).collect::<Result<_>>()?,
+            allow_sudo: config.allow_sudo,
+            read_only: config.read_only,
+            approve_sudo: config.require_approval.sudo,
+            approve_commands: config.require_approval.commands.iter().map(Rule::compile).collect::<Result<_>>()?,
+            approve_write_paths: config.require_approval.write_paths,
+        })
+    }
+}
+
+impl Policy {
+    /// Check a command for `ssh_execute`
+    pub fn check_command(&self, command: &str, use_sudo: bool) -> Result<()> {
+        self.check_sudo(use_sudo)?;
+
+        if let Some(rule) = self.deny.iter().find(|rule| rule.regex.is_match(command)) {
+            bail!("Policy denied: command matches deny rule ({})", rule.source);
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|rule| rule.regex.is_match(command)) {
+            bail!("Policy denied: command does not match any allow rule");
+        }
+        if self.read_only {
+            readonly::check_command(command)?;
+        }
+        Ok(())
+    }
+
+    /// Check a script for `ssh_execute`; only shell scripts can be analysed for read-only sessions
+    pub fn check_script(&self, interpreter: &str, script: &str, use_sudo: bool) -> Result<()> {
+        if self.read_only && !matches!(interpreter, "bash" | "sh") {
+            bail!("Policy denied: read-only session, {} scripts cannot be checked", interpreter);
+        }
+        self.check_command(script, use_sudo)
+    }
+
+    /// Check a file or transfer tool; `writes` marks tools that modify the host
+    pub fn check_operation(&self, tool_name: &str, use_sudo: bool, writes: bool) -> Result<()> {
+        self.check_sudo(use_sudo)?;
+
+        if writes && self.read_only {
+            bail!("Policy denied: {} modifies files, but the session is read-only", tool_name);
+        }
+        Ok(())
+    }
+
+    /// Check setting or unsetting an environment variable of the session. Read-only
+    /// sessions keep their environment; with command rules, variables that could
+    /// make a permitted command run something else are refused.
+    pub fn check_env(&self, name: &str) -> Result<()> {
+        if self.read_only {
+            bail!("Policy denied: read-only session, environment variables cannot be changed");
+        }
+        let restricted = !self.allow.is_empty() || !self.deny.is_empty() || !self.approve_commands.is_empty();
+        if let Some(pattern) = protected_env(name).filter(|_| restricted) {
+            bail!("Policy denied: {} cannot be changed in a session with command rules ({})", name, pattern);
+        }
+        Ok(())
+    }
+
+    /// Why a permitted command still needs the user's approval, if it does
+    pub fn command_approval(&self, command: &str, use_sudo: bool) -> Option<String> {
+        if use_sudo && self.approve_sudo {
+            return Some("runs with sudo".to_string());
+        }
+        self.approve_commands.iter()
+            .find(|rule| rule.regex.is_match(command))
+            .map(|rule| format!("matches approval rule ({})", rule.source))
+    }
+
+    /// Why a permitted file operation still needs the user's approval, if it does;
+    /// `write_path` is resolved against the session's `cwd`, and a `directory`
+    /// target also matches the patterns of the paths below it
+    pub fn operation_approval(&self, use_sudo: bool, write_path: Option<&str>, cwd: Option<&str>, directory: bool) -> Option<String> {
+        if use_sudo && self.approve_sudo {
+            return Some("runs with sudo".to_string());
+        }
+        let path = normalize_path(write_path?, cwd);
+        self.approve_write_paths.iter()
+            .find(|pattern| glob::glob_match(pattern, &path) || (directory && glob::glob_match_below(pattern, &path)))
+            .map(|pattern| format!("writes to a protected path ({})", pattern))
+    }
+
+    fn check_sudo(&self, use_sudo: bool) -> Result<()> {
+        if use_sudo && (!self.allow_sudo || self.read_only) {
+            bail!("Policy denied: sudo is not allowed for this session");
+        }
+        Ok(())
+    }
+}
+
+/// Resolve `.` and `..` without touching the host; relative paths start at
+/// `cwd`, or at `~` when the session has none
+fn normalize_path(path: &str, cwd: Option<&str>) -> String {
+    let joined = match cwd {
+        _ if path.starts_with('/') || path == "~" || path.starts_with("~/") => path.to_string(),
+        Some(cwd) => format!("{}/{}", cwd, path),
+        None => format!("~/{}", path),
+    };
+    let (root, rest) = match joined.strip_prefix('~') {
+        Some(rest) => ("~", rest),
+        None => ("", joined.as_str()),
+    };
+
+    let mut parts: Vec<&str> = Vec::new();
+    for part in rest.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    format!("{}/{}", root, parts.join("/"))
+}
+
+/// The `PROTECTED_ENV` pattern `name` matches, if any
+pub fn protected_env(name: &str) -> Option<&'static str> {
+    PROTECTED_ENV.iter().copied().find(|pattern| glob::glob_match(pattern, name))
+}
+
+fn glob_to_regex(pattern: &str) -> String {
+    let mut regex = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    // Let `.` match newlines in multi-line commands as well
+    format!("(?s){}", regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(write_paths: &[&str]) -> Policy {
+        Policy {
+            approve_write_paths: write_paths.iter().map(|path| path.to_string()).collect(),
+            ..Policy::default()
+        }
+    }
+
+    #[test]
+    fn environment_changes_follow_the_policy() {
+        assert!(Policy::default().check_env("GIT_EXTERNAL_DIFF").is_ok());
+
+        let read_only = Policy { read_only: true, ..Policy::default() };
+        for name in ["GIT_EXTERNAL_DIFF", "LANG"] {
+            assert!(read_only.check_env(name).unwrap_err().to_string().contains("read-only"));
+        }
+
+        let restricted = Policy { deny: vec![Rule::compile(&RuleConfig::Glob("rm *".to_string())).unwrap()], ..Policy::default() };
+        for name in ["BASH_ENV", "ENV", "LD_PRELOAD", "GIT_EXTERNAL_DIFF", "PAGER", "GIT_PAGER", "PROMPT_COMMAND", "PATH"] {
+            assert!(restricted.check_env(name).is_err(), "{}", name);
+        }
+        for name in ["LANG", "TERM", "ENVIRONMENT", "MY_PATH"] {
+            assert!(restricted.check_env(name).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn normalizes_paths_lexically() {
+        assert_eq!(normalize_path("/etc/../etc/./passwd", None), "/etc/passwd");
+        assert_eq!(normalize_path("/../..//etc", None), "/etc");
+        assert_eq!(normalize_path("nginx.conf", Some("/etc/nginx")), "/etc/nginx/nginx.conf");
+        assert_eq!(normalize_path("../passwd", Some("/etc/nginx")), "/etc/passwd");
+        assert_eq!(normalize_path(".ssh/authorized_keys", None), "~/.ssh/authorized_keys");
+        assert_eq!(normalize_path("~/a/../b", Some("/tmp")), "~/b");
+    }
+
+    #[test]
+    fn protected_paths_need_approval_however_they_are_written() {
+        let policy = policy(&["/etc/**"]);
+        assert!(policy.operation_approval(false, Some("/etc/passwd"), None, false).is_some());
+        assert!(policy.operation_approval(false, Some("/tmp/../etc/passwd"), None, false).is_some());
+        assert!(policy.operation_approval(false, Some("passwd"), Some("/etc"), false).is_some());
+        assert!(policy.operation_approval(false, Some("/tmp/passwd"), None, false).is_none());
+        assert!(policy.operation_approval(false, None, None, false).is_none());
+    }
+
+    #[test]
+    fn directory_targets_match_the_paths_below_them() {
+        let policy = policy(&["/etc/nginx/*.conf"]);
+        assert!(policy.operation_approval(false, Some("/etc"), None, false).is_none());
+        assert!(policy.operation_approval(false, Some("/etc"), None, true).is_some());
+        assert!(policy.operation_approval(false, Some("/srv"), None, true).is_none());
+    }
+}
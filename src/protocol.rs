@@ -0,0 +1,138 @@
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+// Line protocol between the server and the relay script
+//
+// A request is one line, `<id> <mode> <interpreter> <byte length> <base64 code or ->
+// <base64 stdin or ->`. Empty code and stdin are sent as `-` (not a base64 character),
+// because the relay's `read` collapses runs of spaces. The relay answers with the command's output followed by
+// `<<<MCP-EXIT:<id>:<status>>>>` on a line of its own.
+
+/// The request line for `code`, newline included
+pub fn encode_request(id: &str, mode: &str, interpreter: &str, code: &str, input: Option<&[u8]>) -> Result<String> {
+    // NUL cannot be passed as an argument
+    if code.contains('\0') {
+        bail!("Command contains a NUL byte");
+    }
+    // Code and stdin are base64-encoded, so any string survives the line protocol
+    let input = match input {
+        Some(data) if !data.is_empty() => BASE64.encode(data),
+        _ => "-".to_string(),
+    };
+    let payload = match code {
+        "" => "-".to_string(),
+        code => BASE64.encode(code),
+    };
+    Ok(format!("{} {} {} {} {} {}\n", id, mode, interpreter, code.len(), payload, input))
+}
+
+/// The exit status if `line` is the end marker of request `id`
+pub fn parse_end_marker(line: &[u8], id: &str) -> Option<Result<i32>> {
+    let line = std::str::from_utf8(line).ok()?.trim_end();
+    let status = line.strip_prefix("<<<MCP-EXIT:")?.strip_prefix(id)?.strip_prefix(':')?;
+    Some(status.strip_suffix(">>>")
+        .and_then(|status| status.parse().ok())
+        .context(format!("Invalid exit status in end marker: {}", line)))
+}
+
+/// Output as returned to clients: the newline before the marker removed and trailing whitespace trimmed from every line
+pub fn finish_output(mut output: Vec<u8>) -> String {
+    if output.last() == Some(&b'\n') {
+        output.pop();
+    }
+    let output = String::from_utf8_lossy(&output);
+    output.lines().map(str::trim_end).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Split a request line the way the relay's `IFS=' ' read -r` does, which treats a run of spaces as one separator
+    fn decode_request(line: &str) -> (Vec<String>, String, Option<Vec<u8>>) {
+        let line = line.strip_suffix('\n').expect("request ends with a newline");
+        assert!(!line.contains('\n'), "request spans several lines");
+        let fields: Vec<&str> = line.split(' ').filter(|field| !field.is_empty()).collect();
+        assert_eq!(fields.len(), 6, "{}", line);
+        let code = match fields[4] {
+            "-" => String::new(),
+            payload => String::from_utf8(BASE64.decode(payload).unwrap()).unwrap(),
+        };
+        assert_eq!(fields[3].parse::<usize>().unwrap(), code.len());
+        let input = (fields[5] != "-").then(|| BASE64.decode(fields[5]).unwrap());
+        (fields[..3].iter().map(|field| field.to_string()).collect(), code, input)
+    }
+
+    /// Deterministic pseudo-random strings full of characters that break naive quoting
+    fn samples() -> Vec<String> {
+        const ALPHABET: &[char] = &['a', ' ', '\n', '\r', '\t', '\'', '"', '`', '$', '\\', '|', ';', '&',
+            '<', '>', '(', ')', '*', '%', '=', '-', '#', '!', 'é', '€', '😀', '\u{7f}', '\u{1b}'];
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut samples = vec![String::new(), "<<<MCP-EXIT:1-1:0>>>".to_string(), "\n".repeat(3)];
+        for _ in 0..500 {
+            let len = (next() % 64) as usize;
+            samples.push((0..len).map(|_| ALPHABET[(next() % ALPHABET.len() as u64) as usize]).collect());
+        }
+        samples
+    }
+
+    #[test]
+    fn requests_round_trip_any_code_and_input() {
+        for (n, code) in samples().iter().enumerate() {
+            let id = format!("token-{}", n);
+            let input = (n % 3 != 0).then_some(code.as_bytes());
+            let line = encode_request(&id, "run", "bash", code, input).unwrap();
+            let (head, decoded, decoded_input) = decode_request(&line);
+            assert_eq!(head, [id.as_str(), "run", "bash"]);
+            assert_eq!(&decoded, code);
+            assert_eq!(decoded_input.as_deref(), input.filter(|input| !input.is_empty()));
+        }
+    }
+
+    #[test]
+    fn empty_fields_keep_their_place() {
+        let line = encode_request("1-1", "export", "-", "", None).unwrap();
+        assert_eq!(line, "1-1 export - 0 - -\n");
+        let (head, code, input) = decode_request(&line);
+        assert_eq!(head, ["1-1", "export", "-"]);
+        assert_eq!(code, "");
+        assert_eq!(input, None);
+    }
+
+    #[test]
+    fn rejects_nul_bytes() {
+        assert!(encode_request("1-1", "run", "bash", "echo \0", None).is_err());
+    }
+
+    #[test]
+    fn parses_only_the_marker_of_its_request() {
+        assert_eq!(parse_end_marker(b"<<<MCP-EXIT:abc-3:0>>>\n", "abc-3").unwrap().unwrap(), 0);
+        assert_eq!(parse_end_marker(b"<<<MCP-EXIT:abc-3:127>>>\r\n", "abc-3").unwrap().unwrap(), 127);
+        assert_eq!(parse_end_marker(b"<<<MCP-EXIT:abc-3:-1>>>", "abc-3").unwrap().unwrap(), -1);
+        assert!(parse_end_marker(b"<<<MCP-EXIT:abc-2:0>>>\n", "abc-3").is_none());
+        assert!(parse_end_marker(b"<<<MCP-EXIT:abc-33:0>>>\n", "abc-3").is_none());
+        assert!(parse_end_marker(b"echo <<<MCP-EXIT:abc-3:0>>>\n", "abc-3").is_none());
+        assert!(parse_end_marker(b"\xff\xfe\n", "abc-3").is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_statuses() {
+        assert!(parse_end_marker(b"<<<MCP-EXIT:abc-3:x>>>\n", "abc-3").unwrap().is_err());
+        assert!(parse_end_marker(b"<<<MCP-EXIT:abc-3:0\n", "abc-3").unwrap().is_err());
+    }
+
+    #[test]
+    fn finishes_output() {
+        assert_eq!(finish_output(b"a  \nb\t\n".to_vec()), "a\nb");
+        assert_eq!(finish_output(b"no newline".to_vec()), "no newline");
+        assert_eq!(finish_output(b"\n".to_vec()), "");
+        assert_eq!(finish_output(b"\xffx\n".to_vec()), "\u{fffd}x");
+    }
+}
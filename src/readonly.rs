@@ -0,0 +1,796 @@
+use anyhow::{bail, Result};
+
+use crate::policy;
+
+// Command analysis for read-only sessions
+//
+// A lightweight shell parser splits a command line into simple commands
+// (including those in pipelines, subshells, `$(...)` and backticks) and output
+// redirections. Commands that are known to modify state, and redirections to
+// anything but /dev/null or another descriptor, are rejected. This guards
+// against accidents; it is not a sandbox - programs can still write in ways
+// not listed here. Interpreter code (python, perl, awk programs running
+// commands, ...) is refused, as it cannot be analysed.
+
+/// Commands that modify the host no matter how they are invoked
+const MUTATING_COMMANDS: &[&str] = &[
+    "rm", "rmdir", "unlink", "mv", "cp", "install", "mkdir", "mkfifo", "mknod", "touch", "ln",
+    "chmod", "chown", "chgrp", "chattr", "setfacl", "dd", "truncate", "fallocate", "shred", "tee",
+    "patch", "rsync", "scp", "kill", "pkill", "killall", "reboot", "shutdown", "halt", "poweroff",
+    "useradd", "userdel", "usermod", "groupadd", "groupdel", "groupmod", "passwd", "chpasswd",
+    "chsh", "chfn", "mount", "umount", "fdisk", "sfdisk", "parted", "wipefs", "mkswap", "swapon",
+    "swapoff", "insmod", "rmmod", "modprobe", "ldconfig", "at", "batch", "systemd-run", "eval",
+    "source", ".", "visudo", "vipw",
+];
+
+/// Commands whose subcommand decides, with the subcommands that only read
+const SUBCOMMAND_ALLOWLIST: &[(&str, &[&str])] = &[
+    ("systemctl", &["status", "show", "cat", "help", "list-units", "list-unit-files", "list-sockets",
+        "list-timers", "list-jobs", "list-dependencies", "list-machines", "is-active", "is-enabled",
+        "is-failed", "is-system-running", "get-default", "show-environment"]),
+    ("apt", &["list", "search", "show", "showsrc", "policy", "depends", "rdepends"]),
+    ("apt-get", &["check", "changelog"]),
+    ("apt-cache", &["search", "show", "showpkg", "policy", "depends", "rdepends", "madison", "pkgnames", "stats"]),
+    ("yum", &["list", "info", "search", "provides", "repolist", "deplist", "history", "check-update"]),
+    ("dnf", &["list", "info", "search", "provides", "repolist", "repoquery", "deplist", "history", "check-update"]),
+    ("pip", &["list", "show", "freeze", "check", "search", "help"]),
+    ("pip3", &["list", "show", "freeze", "check", "search", "help"]),
+    ("npm", &["ls", "list", "view", "info", "outdated", "search", "help"]),
+    ("snap", &["list", "info", "find", "services", "changes", "version"]),
+    ("git", &["status", "log", "diff", "show", "blame", "grep", "ls-files", "ls-tree", "ls-remote",
+        "rev-parse", "rev-list", "describe", "shortlog", "cat-file", "remote", "branch", "tag", "reflog",
+        "for-each-ref", "name-rev", "whatchanged", "help", "version"]),
+    ("docker", &["ps", "images", "inspect", "logs", "version", "info", "stats", "top", "port", "history",
+        "diff", "events", "search", "network", "volume"]),
+    ("podman", &["ps", "images", "inspect", "logs", "version", "info", "stats", "top", "port", "history", "diff"]),
+    ("kubectl", &["get", "describe", "logs", "top", "explain", "version", "api-resources", "api-versions",
+        "cluster-info", "auth", "config", "diff"]),
+    ("ip", &["addr", "address", "a", "link", "l", "route", "r", "neigh", "n", "rule", "maddr", "netconf"]),
+    ("timedatectl", &["status", "show", "list-timezones"]),
+    ("hostnamectl", &["status"]),
+];
+
+/// Commands whose subcommand is an option, with the options that only read
+const OPTION_ALLOWLIST: &[(&str, &[&str])] = &[
+    ("dpkg", &["-l", "--list", "-L", "--listfiles", "-s", "--status", "-S", "--search", "-p", "--print-avail",
+        "--get-selections", "--print-architecture", "--version"]),
+    ("rpm", &["-q", "-qa", "-qi", "-ql", "-qf", "-qc", "-qd", "-qR", "--query", "-V", "-Va", "--verify", "--version"]),
+    ("crontab", &["-l"]),
+];
+
+/// Arguments under an allowed subcommand that still modify state
+const MUTATING_NESTED: &[&str] = &[
+    "add", "del", "delete", "set", "flush", "change", "replace", "append", "rm", "remove", "prune",
+    "create", "apply", "edit", "patch", "connect", "disconnect", "set-context", "use-context",
+    "set-url", "rename", "-d", "-D", "-m", "--delete", "--set-upstream-to", "--unset", "--add",
+];
+
+/// Subcommands whose own subcommand decides, with those that only read
+const NESTED_ALLOWLIST: &[(&str, &str, &[&str])] = &[
+    ("kubectl", "config", &["view", "current-context", "get-contexts", "get-clusters", "get-users"]),
+    ("kubectl", "auth", &["can-i", "whoami"]),
+];
+
+/// Options that take a separate value, so the value is not mistaken for a subcommand or command
+const OPTIONS_WITH_VALUE: &[&str] = &[
+    "-C", "-c", "-n", "-u", "-I", "-L", "-P", "-d", "-E", "-s", "-a", "-k", "-H", "-M",
+    "--namespace", "--context", "--kubeconfig", "--host", "--machine", "--user",
+];
+
+/// `git branch`/`git tag` options that take a separate value, so it is not taken for a ref name
+const GIT_REF_OPTIONS_WITH_VALUE: &[&str] = &["--contains", "--no-contains", "--merged", "--no-merged", "--points-at", "--format", "--sort"];
+
+/// Commands that run another command given as their arguments
+const WRAPPERS: &[&str] = &["env", "nice", "nohup", "time", "timeout", "command", "builtin", "exec",
+    "stdbuf", "ionice", "xargs", "watch", "chroot", "setsid", "strace", "ltrace"];
+
+/// Shell keywords that may precede a command
+const KEYWORDS: &[&str] = &["{", "}", "!", "if", "then", "else", "elif", "fi", "do", "done", "while", "until"];
+
+const SHELLS: &[&str] = &["bash", "sh", "dash", "zsh", "ksh"];
+
+/// Interpreters whose code (inline, from a file or stdin) cannot be analysed;
+/// names starting with `python`, `perl` or `lua` count as well
+const INTERPRETERS: &[&str] = &["ruby", "irb", "node", "nodejs", "deno", "php", "Rscript", "tclsh", "expect", "groovy", "jshell"];
+
+/// Interpreter options that only print information
+const INTERPRETER_INFO_OPTIONS: &[&str] = &["-V", "-v", "--version", "-h", "--help"];
+
+const AWKS: &[&str] = &["awk", "gawk", "mawk", "nawk"];
+
+/// Reject commands that could modify the host
+pub fn check_command(command: &str) -> Result<()> {
+    let parsed = parse(command);
+    if let Some(target) = parsed.redirections.iter().find(|target| !harmless_redirection(target)) {
+        bail!("Policy denied: read-only session, output redirection to '{}' is not allowed", target);
+    }
+    for words in &parsed.commands {
+        check_simple_command(words)?;
+    }
+    Ok(())
+}
+
+fn harmless_redirection(target: &str) -> bool {
+    matches!(target, "/dev/null" | "/dev/stdout" | "/dev/stderr")
+        || target.starts_with('&') && target[1..].chars().all(|c| c.is_ascii_digit() || c == '-')
+}
+
+fn check_simple_command(words: &[String]) -> Result<()> {
+    // `for x in ...` and `case ... in` headers are not commands
+    if words.first().is_some_and(|word| matches!(word.as_str(), "for" | "select" | "case")) {
+        return Ok(());
+    }
+
+    // Skip keywords, variable assignments and wrappers to find the command that runs
+    let mut index = 0;
+    while let Some(word) = words.get(index) {
+        if KEYWORDS.contains(&word.as_str()) {
+            index += 1;
+            continue;
+        }
+        if is_assignment(word) {
+            check_assignment(word)?;
+            index += 1;
+            continue;
+        }
+        let name = basename(word);
+        if !WRAPPERS.contains(&name) {
+            break;
+        }
+        index += 1;
+        // Wrapper options, and the duration/priority/count argument some wrappers take
+        while let Some(arg) = words.get(index) {
+            if OPTIONS_WITH_VALUE.contains(&arg.as_str()) {
+                index += 2;
+            } else if is_assignment(arg) {
+                check_assignment(arg)?;
+                index += 1;
+            } else if arg.starts_with('-')
+                || (matches!(name, "timeout" | "nice" | "watch") && arg.starts_with(|c: char| c.is_ascii_digit())) {
+                index += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    let Some(first) = words.get(index) else {
+        return Ok(());
+    };
+    // Substitutions are parsed as `$`; a command name computed at run time cannot be checked
+    if first.contains(['$', '`']) {
+        bail!("Policy denied: read-only session, command name '{}' is only known at run time", first);
+    }
+    let name = basename(first);
+    let args = &words[index + 1..];
+
+    if matches!(name, "sudo" | "su" | "doas" | "pkexec" | "runuser") {
+        bail!("Policy denied: read-only session, '{}' is not allowed", name);
+    }
+    if MUTATING_COMMANDS.contains(&name) || name.starts_with("mkfs") {
+        bail!("Policy denied: read-only session, '{}' modifies the host", name);
+    }
+    if matches!(name, "export" | "declare" | "typeset" | "local" | "readonly") {
+        for arg in args.iter().filter(|arg| !arg.starts_with('-')) {
+            check_assignment(arg)?;
+        }
+    }
+
+    if SHELLS.contains(&name) {
+        // Analyse inline scripts; running script files cannot be checked
+        let inline = args.iter().position(|arg| arg.starts_with('-') && !arg.starts_with("--") && arg.contains('c'));
+        return match inline {
+            Some(position) => match args.get(position + 1) {
+                Some(script) => check_command(script),
+                None => Ok(()),
+            },
+            None if args.iter().any(|arg| !arg.starts_with('-')) =>
+                bail!("Policy denied: read-only session, running scripts with '{}' cannot be checked", name),
+            None => Ok(()),
+        };
+    }
+
+    if is_interpreter(name) {
+        // Without arguments the program is read from stdin
+        if args.is_empty() || args.iter().any(|arg| !INTERPRETER_INFO_OPTIONS.contains(&arg.as_str())) {
+            bail!("Policy denied: read-only session, {} code cannot be checked", name);
+        }
+        return Ok(());
+    }
+    if AWKS.contains(&name) {
+        return check_awk(name, args);
+    }
+
+    match name {
+        "sed" if has_switch(args, 'i', "nrEsuz") || args.iter().any(|arg| arg.starts_with("--in-place")) =>
+            bail!("Policy denied: read-only session, 'sed -i' modifies files"),
+        "sort" if args.iter().any(|arg| arg.starts_with("-o") || arg.starts_with("--output")) =>
+            bail!("Policy denied: read-only session, 'sort -o' writes files"),
+        "curl" if args.iter().any(|arg| matches!(arg.as_str(), "-o" | "-O" | "--output" | "--remote-name" | "--remote-name-all" | "-T" | "--upload-file")
+            || arg.starts_with("--output=")) =>
+            bail!("Policy denied: read-only session, 'curl' writing files is not allowed"),
+        "wget" if !args.iter().any(|arg| matches!(arg.as_str(), "-O-" | "--spider" | "--output-document=-"))
+            && !args.windows(2).any(|pair| pair[0] == "-O" && pair[1] == "-") =>
+            bail!("Policy denied: read-only session, 'wget' saves files; use 'wget -O -' or curl"),
+        "tar" if !is_tar_listing(args) =>
+            bail!("Policy denied: read-only session, only 'tar -t' listings are allowed"),
+        "unzip" if !args.iter().any(|arg| matches!(arg.as_str(), "-l" | "-t" | "-p" | "-v" | "-Z")) =>
+            bail!("Policy denied: read-only session, only 'unzip -l/-t/-p' are allowed"),
+        "gzip" | "gunzip" | "bzip2" | "bunzip2" | "xz" | "unxz" | "zstd" | "unzstd"
+            if args.iter().any(|arg| !arg.starts_with('-'))
+                && !args.iter().any(|arg| matches!(arg.as_str(), "-c" | "--stdout" | "-l" | "--list" | "-t" | "--test")
+                    || (arg.starts_with('-') && !arg.starts_with("--") && arg.contains('c'))) =>
+            bail!("Policy denied: read-only session, '{}' replaces files unless run with -c", name),
+        "find" => {
+            if let Some(action) = args.iter().find(|arg| matches!(arg.as_str(), "-delete" | "-fprint" | "-fprint0" | "-fprintf" | "-fls")) {
+                bail!("Policy denied: read-only session, 'find {}' modifies files", action);
+            }
+            // Check commands run by -exec/-execdir/-ok/-okdir
+            let mut rest = args;
+            while let Some(start) = rest.iter().position(|arg| matches!(arg.as_str(), "-exec" | "-execdir" | "-ok" | "-okdir")) {
+                let rest_after = &rest[start + 1..];
+                let end = rest_after.iter().position(|arg| arg == ";" || arg == "+").unwrap_or(rest_after.len());
+                check_simple_command(&rest_after[..end])?;
+                rest = &rest_after[(end + 1).min(rest_after.len())..];
+            }
+            Ok(())
+        }
+        "journalctl" if args.iter().any(|arg| arg.starts_with("--vacuum") || matches!(arg.as_str(), "--rotate" | "--flush" | "--sync")) =>
+            bail!("Policy denied: read-only session, 'journalctl' maintenance is not allowed"),
+        "service" if !(args.len() == 1 && args[0] == "--status-all" || args.len() == 2 && args[1] == "status") =>
+            bail!("Policy denied: read-only session, only 'service <name> status' is allowed"),
+        "sysctl" if args.iter().any(|arg| matches!(arg.as_str(), "-w" | "--write" | "-p" | "--load" | "--system") || arg.contains('=')) =>
+            bail!("Policy denied: read-only session, 'sysctl' writes are not allowed"),
+        "iptables" | "ip6tables" if !args.iter().any(|arg| matches!(arg.as_str(), "-L" | "--list" | "-S" | "--list-rules")) =>
+            bail!("Policy denied: read-only session, '{}' changes are not allowed", name),
+        "nft" if args.iter().find(|arg| !arg.starts_with('-')).is_some_and(|arg| arg != "list") =>
+            bail!("Policy denied: read-only session, 'nft' changes are not allowed"),
+        "date" if args.iter().any(|arg| arg == "-s" || arg.starts_with("--set")) =>
+            bail!("Policy denied: read-only session, 'date --set' is not allowed"),
+        "hostname" if args.iter().any(|arg| !arg.starts_with('-')) =>
+            bail!("Policy denied: read-only session, setting the hostname is not allowed"),
+        _ => check_subcommand(name, args),
+    }
+}
+
+fn is_interpreter(name: &str) -> bool {
+    INTERPRETERS.contains(&name) || ["python", "perl", "lua"].iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// awk programs may run commands (`system()`, pipes to and from commands) or
+/// edit files in place (`gawk -i inplace`); program files cannot be checked
+fn check_awk(name: &str, args: &[String]) -> Result<()> {
+    if let Some(option) = args.iter().find(|arg| matches!(arg.as_str(), "-f" | "--file" | "-E" | "--exec" | "-i" | "--include")
+        || arg.starts_with("--file=") || arg.starts_with("--include=")) {
+        bail!("Policy denied: read-only session, '{} {}' cannot be checked", name, option);
+    }
+    for arg in args {
+        // `||` is a logical or; a single `|` (or gawk's `|&`) pipes to or from a command
+        let pipe = arg.char_indices().any(|(position, c)| c == '|'
+            && !arg[..position].ends_with('|') && !arg[position + 1..].starts_with('|'));
+        if pipe || arg.contains("system") {
+            bail!("Policy denied: read-only session, {} programs running commands are not allowed", name);
+        }
+    }
+    Ok(())
+}
+
+/// Whether a short-option bundle enables `switch`, e.g. `-ni` for sed; only
+/// bundles made of `flags` before the switch count, so `-Mstrict` is not `-i`
+fn has_switch(args: &[String], switch: char, flags: &str) -> bool {
+    args.iter()
+        .filter_map(|arg| arg.strip_prefix('-').filter(|_| !arg.starts_with("--")))
+        .any(|bundle| match bundle.find(switch) {
+            Some(position) => bundle[..position].chars().all(|c| flags.contains(c)),
+            None => false,
+        })
+}
+
+fn is_tar_listing(args: &[String]) -> bool {
+    let Some(first) = args.first() else { return false };
+    // The first argument may be a bundle without a dash (`tar tzf x.tgz`)
+    let bundle = first.strip_prefix('-').filter(|_| !first.starts_with("--")).unwrap_or(first);
+    let listing = args.iter().any(|arg| arg == "--list") || (!first.starts_with("--") && bundle.contains('t'));
+    let writing = args.iter().any(|arg| matches!(arg.as_str(), "--create" | "--extract" | "--get" | "--append" | "--update" | "--delete" | "--to-command"))
+        || (!first.starts_with("--") && bundle.contains(['c', 'x', 'r', 'u', 'A']));
+    listing && !writing
+}
+
+fn check_subcommand(name: &str, args: &[String]) -> Result<()> {
+    if let Some((_, allowed)) = OPTION_ALLOWLIST.iter().find(|(command, _)| *command == name) {
+        return match args.iter().find(|arg| allowed.contains(&arg.as_str())) {
+            Some(_) if args.iter().all(|arg| !arg.starts_with('-') || allowed.contains(&arg.as_str())) => Ok(()),
+            _ => bail!("Policy denied: read-only session, '{} {}' is not known to be read-only", name, args.join(" ")),
+        };
+    }
+
+    let Some((_, allowed)) = SUBCOMMAND_ALLOWLIST.iter().find(|(command, _)| *command == name) else {
+        return Ok(());
+    };
+
+    // Options before the subcommand (`git -C dir status`) are skipped
+    let mut position = 0;
+    while let Some(arg) = args.get(position) {
+        // Config given to git on the command line can name programs to run (`core.pager`, `core.sshCommand`, ...)
+        if name == "git" && (matches!(arg.as_str(), "-c" | "--config-env" | "--exec-path")
+            || arg.starts_with("--config-env=") || arg.starts_with("--exec-path=")) {
+            bail!("Policy denied: read-only session, 'git {}' can make git run other programs", arg);
+        }
+        if OPTIONS_WITH_VALUE.contains(&arg.as_str()) {
+            position += 2;
+        } else if arg.starts_with('-') {
+            position += 1;
+        } else {
+            break;
+        }
+    }
+    let Some(subcommand) = args.get(position) else {
+        // Just options, e.g. `git --version` or `systemctl --failed`
+        return Ok(());
+    };
+    if !allowed.contains(&subcommand.as_str()) {
+        bail!("Policy denied: read-only session, '{} {}' is not known to be read-only", name, subcommand);
+    }
+    if let Some((_, _, allowed)) = NESTED_ALLOWLIST.iter().find(|(command, parent, _)| *command == name && parent == subcommand) {
+        match args[position + 1..].iter().find(|arg| !arg.starts_with('-')) {
+            Some(nested) if allowed.contains(&nested.as_str()) => {}
+            Some(nested) => bail!("Policy denied: read-only session, '{} {} {}' is not known to be read-only", name, subcommand, nested),
+            None => {}
+        }
+    }
+    if let Some(nested) = args[position + 1..].iter().find(|arg| MUTATING_NESTED.contains(&arg.as_str())) {
+        bail!("Policy denied: read-only session, '{} {} {}' modifies state", name, subcommand, nested);
+    }
+    if name == "git" && matches!(subcommand.as_str(), "branch" | "tag") {
+        return check_git_ref(subcommand, &args[position + 1..]);
+    }
+    Ok(())
+}
+
+/// `git branch` and `git tag` only list refs without a name argument (or with `--list`)
+fn check_git_ref(subcommand: &str, args: &[String]) -> Result<()> {
+    let writing: &[&str] = match subcommand {
+        "tag" => &["-a", "--annotate", "-s", "--sign", "-u", "--local-user", "-f", "--force", "-F", "--file", "-e", "--edit"],
+        _ => &["-c", "-C", "--copy", "-M", "--move", "-f", "--force", "-t", "--track", "--no-track",
+            "--unset-upstream", "--edit-description"],
+    };
+    if let Some(option) = args.iter().find(|arg| writing.contains(&arg.as_str())) {
+        bail!("Policy denied: read-only session, 'git {} {}' modifies refs", subcommand, option);
+    }
+    if args.iter().any(|arg| arg == "-l" || arg == "--list") {
+        return Ok(());
+    }
+
+    let mut position = 0;
+    while let Some(arg) = args.get(position) {
+        if GIT_REF_OPTIONS_WITH_VALUE.contains(&arg.as_str()) {
+            position += 2;
+        } else if arg.starts_with('-') {
+            position += 1;
+        } else {
+            bail!("Policy denied: read-only session, 'git {} {}' creates a {}", subcommand, arg,
+                if subcommand == "tag" { "tag" } else { "branch" });
+        }
+    }
+    Ok(())
+}
+
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !name.starts_with(|c: char| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// Variables like `GIT_EXTERNAL_DIFF` or `LD_PRELOAD` make a read-only command run
+/// something else, so `NAME=value` (or `export NAME`) must not set them
+fn check_assignment(word: &str) -> Result<()> {
+    let name = word.split_once('=').map_or(word, |(name, _)| name);
+    if let Some(pattern) = policy::protected_env(name) {
+        bail!("Policy denied: read-only session, setting {} is not allowed ({})", name, pattern);
+    }
+    Ok(())
+}
+
+fn basename(word: &str) -> &str {
+    word.rsplit('/').next().unwrap_or(word)
+}
+
+#[derive(Debug, Default)]
+struct Parsed {
+    /// Simple commands as word lists, quotes removed
+    commands: Vec<Vec<String>>,
+    /// Targets of output redirections (`&2` for descriptor duplication)
+    redirections: Vec<String>,
+}
+
+fn parse(command: &str) -> Parsed {
+    let mut parser = Parser {
+        chars: command.chars().collect(),
+        pos: 0,
+        parsed: Parsed::default(),
+        heredocs: Vec::new(),
+    };
+    parser.sequence(None);
+    parser.parsed
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    parsed: Parsed,
+    /// Here-document delimiters whose bodies start at the next newline
+    heredocs: Vec<(String, bool)>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    /// Parse commands until `end` (or end of input), collecting simple commands
+    fn sequence(&mut self, end: Option<char>) {
+        let mut words: Vec<String> = Vec::new();
+        loop {
+            while matches!(self.peek(), Some(' ' | '\t')) {
+                self.pos += 1;
+            }
+            let Some(c) = self.peek() else { break };
+            if Some(c) == end {
+                self.pos += 1;
+                break;
+            }
+            match c {
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                '\n' => {
+                    self.finish(&mut words);
+                    self.pos += 1;
+                    self.skip_heredoc_bodies();
+                }
+                ';' | '|' => {
+                    self.finish(&mut words);
+                    self.pos += 1;
+                }
+                '&' if self.peek_at(1) != Some('>') => {
+                    self.finish(&mut words);
+                    self.pos += 1;
+                }
+                '(' => {
+                    self.finish(&mut words);
+                    self.pos += 1;
+                    self.sequence(Some(')'));
+                }
+                ')' => self.pos += 1,
+                _ if self.redirection() => {}
+                _ => {
+                    let word = self.word();
+                    words.push(word);
+                }
+            }
+        }
+        self.finish(&mut words);
+    }
+
+    fn finish(&mut self, words: &mut Vec<String>) {
+        if !words.is_empty() {
+            self.parsed.commands.push(std::mem::take(words));
+        }
+    }
+
+    /// Parse a redirection at the current position, if there is one
+    fn redirection(&mut self) -> bool {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let mut operator = String::new();
+        if self.pos == start && self.peek() == Some('&') && self.peek_at(1) == Some('>') {
+            operator.push('&');
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(c @ ('>' | '<')) => {
+                operator.push(c);
+                self.pos += 1;
+            }
+            _ => {
+                self.pos = start;
+                return false;
+            }
+        }
+        while let Some(c @ ('>' | '<' | '&' | '|')) = self.peek() {
+            // `<(...)`/`>(...)` are process substitutions, not redirections
+            if c == '&' && !matches!(self.peek_at(1), Some(c) if c.is_ascii_digit() || c == '-') {
+                break;
+            }
+            operator.push(c);
+            self.pos += 1;
+            if c == '&' {
+                break;
+            }
+        }
+        if self.peek() == Some('(') {
+            self.pos += 1;
+            self.sequence(Some(')'));
+            return true;
+        }
+        if operator == "<<" && self.peek() == Some('-') {
+            operator.push('-');
+            self.pos += 1;
+        }
+
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+        let target = self.word();
+
+        match operator.trim_start_matches('&') {
+            "<<" | "<<-" => self.heredocs.push((target, operator.ends_with('-'))),
+            "<" | "<<<" | "<&" => {}
+            "<>" => self.parsed.redirections.push(target),
+            _ if operator.ends_with('&') => self.parsed.redirections.push(format!("&{}", target)),
+            _ => self.parsed.redirections.push(target),
+        }
+        true
+    }
+
+    fn skip_heredoc_bodies(&mut self) {
+        for (delimiter, strip_tabs) in std::mem::take(&mut self.heredocs) {
+            loop {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+                let line: String = self.chars[start..self.pos].iter().collect();
+                if self.peek().is_some() {
+                    self.pos += 1;
+                }
+                let line = if strip_tabs { line.trim_start_matches('\t') } else { &line };
+                if line == delimiter || self.peek().is_none() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Parse one word, removing quotes; substitutions inside it are parsed as commands
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\n' | ';' | '&' | '|' | '(' | ')' | '<' | '>' => break,
+                '\\' => {
+                    self.pos += 1;
+                    if let Some(c) = self.peek() {
+                        if c != '\n' {
+                            word.push(c);
+                        }
+                        self.pos += 1;
+                    }
+                }
+                '\'' => {
+                    self.pos += 1;
+                    while let Some(c) = self.peek() {
+                        self.pos += 1;
+                        if c == '\'' {
+                            break;
+                        }
+                        word.push(c);
+                    }
+                }
+                '"' => {
+                    self.pos += 1;
+                    while let Some(c) = self.peek() {
+                        match c {
+                            '"' => {
+                                self.pos += 1;
+                                break;
+                            }
+                            '\\' => {
+                                self.pos += 1;
+                                if let Some(c) = self.peek() {
+                                    word.push(c);
+                                    self.pos += 1;
+                                }
+                            }
+                            '$' | '`' if self.substitution() => word.push('$'),
+                            c => {
+                                word.push(c);
+                                self.pos += 1;
+                            }
+                        }
+                    }
+                }
+                '$' | '`' if self.substitution() => word.push('$'),
+                c => {
+                    word.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        word
+    }
+
+    /// Parse `$(...)`, `` `...` ``, `$((...))` or `${...}` at the current position
+    fn substitution(&mut self) -> bool {
+        match (self.peek(), self.peek_at(1), self.peek_at(2)) {
+            (Some('$'), Some('('), Some('(')) => {
+                self.pos += 3;
+                self.skip_balanced('(', ')', 2);
+            }
+            (Some('$'), Some('('), _) => {
+                self.pos += 2;
+                self.sequence(Some(')'));
+            }
+            (Some('$'), Some('{'), _) => {
+                self.pos += 2;
+                self.skip_balanced('{', '}', 1);
+            }
+            (Some('`'), _, _) => {
+                self.pos += 1;
+                let start = self.pos;
+                while let Some(c) = self.peek() {
+                    self.pos += 1;
+                    match c {
+                        '\\' => self.pos += 1,
+                        '`' => break,
+                        _ => {}
+                    }
+                }
+                let end = self.pos.saturating_sub(1).max(start);
+                let inner: String = self.chars[start..end].iter().collect();
+                let nested = parse(&inner);
+                self.parsed.commands.extend(nested.commands);
+                self.parsed.redirections.extend(nested.redirections);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn skip_balanced(&mut self, open: char, close: char, mut depth: usize) {
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(command: &str) -> bool {
+        check_command(command).is_ok()
+    }
+
+    #[test]
+    fn splits_pipelines_lists_and_substitutions() {
+        let parsed = parse("cat a | grep b && echo \"$(rm -rf x)\"; (ls `whoami`) > /dev/null 2>&1");
+        let names: Vec<&str> = parsed.commands.iter().map(|words| words[0].as_str()).collect();
+        assert_eq!(names, ["cat", "grep", "rm", "echo", "whoami", "ls"]);
+        assert_eq!(parsed.redirections, ["/dev/null", "&1"]);
+    }
+
+    #[test]
+    fn removes_quotes_and_skips_heredoc_bodies() {
+        let parsed = parse("echo 'a b' \"c\\\"d\" e\\ f\ncat <<EOF\nrm -rf /\nEOF\nls");
+        assert_eq!(parsed.commands[0], ["echo", "a b", "c\"d", "e f"]);
+        let names: Vec<&str> = parsed.commands.iter().map(|words| words[0].as_str()).collect();
+        assert_eq!(names, ["echo", "cat", "ls"]);
+    }
+
+    #[test]
+    fn allows_reading_commands() {
+        for command in ["ls -la /etc", "cat /etc/passwd | grep root > /dev/null", "systemctl status nginx",
+            "git -C /srv/app log --oneline", "sudo_helper=1 ps aux", "timeout 5 env LANG=C df -h",
+            "find / -name '*.conf' -exec grep -l x {} +", "sed -n 1,5p f", "tar tzf a.tgz", "bash -c 'uptime'",
+            "git branch", "git branch -a -v", "git branch --contains HEAD", "git tag", "git tag -l 'v1.*'",
+            "git branch --list 'feature/*'", "echo $HOME", "for f in *; do wc -l \"$f\"; done"] {
+            assert!(allowed(command), "{}", command);
+        }
+    }
+
+    #[test]
+    fn rejects_mutating_commands() {
+        for command in ["rm -f x", "echo x > file", "cat a >> b", "ls; rm x", "echo $(touch x)", "ls `rm x`",
+            "sudo ls", "env nice rm x", "/bin/rm x", "sed -i s/a/b/ f", "sed -ni p f", "find . -delete",
+            "find . -exec rm {} ;", "bash -c 'rm x'", "bash script.sh", "systemctl restart nginx",
+            "git push", "git remote add origin x", "tar xzf a.tgz", "cp a b", "wget http://x"] {
+            assert!(!allowed(command), "{}", command);
+        }
+    }
+
+    #[test]
+    fn rejects_command_names_known_only_at_run_time() {
+        for command in ["$CMD x", "${CMD} x", "$(echo rm) x", "`echo rm` x", "\"$SHELL\" -c ls", "env $CMD", "a=1 $CMD"] {
+            assert!(!allowed(command), "{}", command);
+        }
+    }
+
+    #[test]
+    fn rejects_variables_that_change_what_runs() {
+        for command in ["GIT_EXTERNAL_DIFF=/tmp/x git diff", "GIT_SSH_COMMAND='sh -c id' git ls-remote origin",
+            "LD_PRELOAD=/tmp/x.so ls", "env PAGER=/tmp/x git log", "timeout 5 env BASH_ENV=/tmp/x bash -c ls",
+            "export GIT_EXTERNAL_DIFF=/tmp/x; git diff", "GIT_EXTERNAL_DIFF=/tmp/x; export GIT_EXTERNAL_DIFF",
+            "declare -x LD_PRELOAD=/tmp/x.so", "PATH=/tmp:$PATH ls"] {
+            assert!(!allowed(command), "{}", command);
+        }
+        for command in ["LANG=C ls", "env TZ=UTC date", "export LC_ALL=C; sort f", "export -p"] {
+            assert!(allowed(command), "{}", command);
+        }
+    }
+
+    #[test]
+    fn rejects_git_config_overrides() {
+        for command in ["git -c core.sshCommand='sh -c id' ls-remote origin", "git -c core.fsmonitor=/tmp/x status",
+            "git -c core.pager=/tmp/x log", "git --config-env=core.pager=PAGER log", "git --config-env core.pager=X log",
+            "git --exec-path=/tmp status", "git -C /srv/app -c alias.x=!id status"] {
+            assert!(!allowed(command), "{}", command);
+        }
+        assert!(allowed("git -C /srv/app status"));
+        assert!(allowed("git log -c"));
+    }
+
+    #[test]
+    fn git_branch_and_tag_with_a_name_write_refs() {
+        for command in ["git branch feature", "git branch -f main HEAD~1", "git branch -c a b", "git branch -M new",
+            "git branch --unset-upstream", "git tag v1.0", "git tag -a v1.0 -m release", "git tag -f v1",
+            "git branch -d old", "git tag --delete v1"] {
+            assert!(!allowed(command), "{}", command);
+        }
+    }
+
+    #[test]
+    fn kubectl_config_and_auth_only_read() {
+        for command in ["kubectl config set-cluster x", "kubectl config set-credentials u", "kubectl config delete-cluster x",
+            "kubectl config delete-user u", "kubectl config unset users.u", "kubectl auth reconcile -f rbac.yaml",
+            "kubectl --context prod config rename-context a b"] {
+            assert!(!allowed(command), "{}", command);
+        }
+        for command in ["kubectl config view --minify", "kubectl config get-contexts", "kubectl auth can-i get pods",
+            "kubectl get pods -n kube-system"] {
+            assert!(allowed(command), "{}", command);
+        }
+    }
+
+    #[test]
+    fn rejects_interpreter_code() {
+        for command in ["python3 -c 'import os; os.remove(\"x\")'", "perl -e 'unlink \"x\"'", "perl -pi -e s/a/b/ f",
+            "python3 script.py", "/usr/bin/python3.11 -m http.server", "echo 'print(1)' | python3", "ruby -e 'File.delete(1)'",
+            "node -e 'x'", "awk 'BEGIN{system(\"rm x\")}'", "awk '{print | \"sh\"}' f", "gawk '{print |& \"sh\"}' f",
+            "awk '\"id\" | getline x'", "awk -f prog.awk f", "gawk -i inplace '{print}' f"] {
+            assert!(!allowed(command), "{}", command);
+        }
+        for command in ["python3 --version", "perl -v", "awk -F: '{print $1}' /etc/passwd",
+            "awk '$1 == \"a\" || $2 == \"b\"' f", "ps aux | awk '{print $2}'"] {
+            assert!(allowed(command), "{}", command);
+        }
+    }
+
+    #[test]
+    fn option_allowlists() {
+        assert!(allowed("dpkg -l"));
+        assert!(allowed("rpm -qa"));
+        assert!(!allowed("dpkg -i x.deb"));
+        assert!(!allowed("crontab -r"));
+        assert!(allowed("crontab -l"));
+    }
+}
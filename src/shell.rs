@@ -0,0 +1,6 @@
+// Helpers for building remote shell command lines
+
+/// Quote a string as a single POSIX shell word
+pub fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r#"'\''"#))
+}
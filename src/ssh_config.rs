@@ -0,0 +1,387 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+use crate::glob;
+
+// Known hosts from the OpenSSH client config (`~/.ssh/config`)
+//
+// Follows ssh's rules closely enough for discovery: `Include` is expanded in
+// place (globs in the file name, relative paths under ~/.ssh), and for each
+// option the first value from a matching `Host` block wins. `Match` blocks
+// cannot be evaluated without connecting and are skipped.
+
+/// URI of the resource listing the known hosts
+pub const HOSTS_RESOURCE_URI: &str = "mcp-ssh://hosts";
+/// Nesting limit for `Include`, as in ssh
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A concrete `Host` alias with the options ssh would use for it
+#[derive(Debug, Clone)]
+pub struct KnownHost {
+    pub alias: String,
+    pub hostname: String,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub proxy_jump: Option<String>,
+    /// File the alias is declared in
+    pub source: PathBuf,
+}
+
+impl KnownHost {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "alias": self.alias,
+            "hostname": self.hostname,
+            "user": self.user,
+            "port": self.port,
+            "proxy_jump": self.proxy_jump,
+            "source": self.source
+        })
+    }
+}
+
+/// One `keyword value` line, with the `Host` patterns in effect for it
+#[derive(Debug)]
+struct Directive {
+    /// `None` inside a `Match` block, which never applies
+    patterns: Option<Vec<String>>,
+    keyword: String,
+    value: String,
+}
+
+pub fn tool_definitions() -> Vec<Value> {
+    vec![
+        json!({
+            "name": "ssh_list_hosts",
+            "description": "List host aliases from ~/.ssh/config (including Include files) with their resolved HostName, User, Port and ProxyJump, as valid targets for ssh_connect",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "pattern": { "type": "string", "description": "Glob filtering hosts by alias or HostName" }
+                }
+            }
+        }),
+    ]
+}
+
+/// Handle `ssh_list_hosts`
+pub fn list_hosts(arguments: &Value) -> Result<Value> {
+    let pattern = arguments["pattern"].as_str();
+    let hosts: Vec<KnownHost> = known_hosts()?.into_iter()
+        .filter(|host| pattern.is_none_or(|pattern| glob::glob_match(pattern, &host.alias) || glob::glob_match(pattern, &host.hostname)))
+        .collect();
+
+    eprintln!("[MCP] Listing {} known hosts", hosts.len());
+
+    let text = if hosts.is_empty() {
+        "No hosts found in ssh config".to_string()
+    } else {
+        format!("Known SSH hosts ({}):\n{}", hosts.len(), hosts.iter().map(describe).collect::<Vec<_>>().join("\n"))
+    };
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": text
+        }],
+        "metadata": {
+            "hosts": hosts.iter().map(KnownHost::to_json).collect::<Vec<_>>()
+        }
+    }))
+}
+
+pub fn resource_definitions() -> Vec<Value> {
+    vec![json!({
+        "uri": HOSTS_RESOURCE_URI,
+        "name": "Known SSH hosts",
+        "description": "Host aliases from ~/.ssh/config with resolved HostName, User, Port and ProxyJump",
+        "mimeType": "application/json"
+    })]
+}
+
+/// Contents of the hosts resource
+pub fn read_hosts_resource() -> Result<Value> {
+    let hosts: Vec<Value> = known_hosts()?.iter().map(KnownHost::to_json).collect();
+    Ok(json!({
+        "contents": [{
+            "uri": HOSTS_RESOURCE_URI,
+            "mimeType": "application/json",
+            "text": serde_json::to_string_pretty(&hosts)?
+        }]
+    }))
+}
+
+/// Hosts from the user's ssh config; none if it does not exist
+fn known_hosts() -> Result<Vec<KnownHost>> {
+    match default_path() {
+        Some(path) if path.exists() => load(&path),
+        _ => Ok(Vec::new()),
+    }
+}
+
+fn describe(host: &KnownHost) -> String {
+    let mut line = format!("  - {}", host.alias);
+    let target = format!("{}{}{}",
+        host.user.as_deref().map(|user| format!("{}@", user)).unwrap_or_default(),
+        host.hostname,
+        host.port.map(|port| format!(":{}", port)).unwrap_or_default());
+    if target != host.alias {
+        line.push_str(&format!(" ({})", target));
+    }
+    if let Some(jump) = &host.proxy_jump {
+        line.push_str(&format!(" via {}", jump));
+    }
+    line
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    Some(ssh_dir()?.join("config"))
+}
+
+/// Parse an ssh client config and resolve every concrete `Host` alias in it, sorted by alias
+pub fn load(path: &Path) -> Result<Vec<KnownHost>> {
+    let mut directives = Vec::new();
+    let mut aliases = Vec::new();
+    let mut patterns = Some(vec!["*".to_string()]);
+    read_file(path, 0, &mut patterns, &mut directives, &mut aliases)?;
+
+    let mut hosts: Vec<KnownHost> = Vec::new();
+    for (alias, source) in aliases {
+        if hosts.iter().any(|host| host.alias == alias) {
+            continue;
+        }
+        let lookup = |keyword: &str| directives.iter()
+            .filter(|directive| directive.keyword == keyword)
+            .find(|directive| directive.patterns.as_ref().is_some_and(|patterns| host_matches(patterns, &alias)))
+            .map(|directive| directive.value.clone());
+
+        hosts.push(KnownHost {
+            hostname: lookup("hostname").map_or_else(|| alias.clone(), |hostname| hostname.replace("%h", &alias)),
+            user: lookup("user"),
+            port: lookup("port").and_then(|port| port.parse().ok()),
+            proxy_jump: lookup("proxyjump").filter(|jump| !jump.eq_ignore_ascii_case("none")),
+            alias,
+            source,
+        });
+    }
+    hosts.sort_by(|a, b| a.alias.cmp(&b.alias));
+    Ok(hosts)
+}
+
+fn read_file(path: &Path, depth: usize, patterns: &mut Option<Vec<String>>,
+             directives: &mut Vec<Directive>, aliases: &mut Vec<(String, PathBuf)>) -> Result<()> {
+    let text = std::fs::read_to_string(path)
+        .context(format!("Failed to read ssh config {}", path.display()))?;
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (keyword, value) = split_line(line);
+        let keyword = keyword.to_ascii_lowercase();
+
+        match keyword.as_str() {
+            "host" => {
+                let block: Vec<String> = value.split_whitespace().map(|pattern| pattern.trim_matches('"').to_string()).collect();
+                aliases.extend(block.iter()
+                    .filter(|pattern| !pattern.contains(['*', '?', '!']))
+                    .map(|alias| (alias.clone(), path.to_path_buf())));
+                *patterns = Some(block);
+            }
+            "match" => *patterns = None,
+            "include" => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    eprintln!("[MAIN] ssh config Include nested too deeply in {}", path.display());
+                    continue;
+                }
+                // Host blocks opened in an included file end with it
+                let outer = patterns.clone();
+                for pattern in value.split_whitespace() {
+                    for included in expand_include(pattern.trim_matches('"')) {
+                        // Missing or unreadable includes are ignored, as ssh does
+                        if let Err(e) = read_file(&included, depth + 1, patterns, directives, aliases) {
+                            eprintln!("[MAIN] Skipping ssh config include: {:#}", e);
+                        }
+                    }
+                }
+                *patterns = outer;
+            }
+            _ => directives.push(Directive {
+                patterns: patterns.clone(),
+                keyword,
+                value: value.trim_matches('"').to_string(),
+            }),
+        }
+    }
+    Ok(())
+}
+
+/// Split `Keyword value`, `Keyword=value` or `Keyword = value`
+fn split_line(line: &str) -> (&str, &str) {
+    match line.find(|c: char| c.is_whitespace() || c == '=') {
+        Some(end) => (&line[..end], line[end..].trim_start_matches(|c: char| c.is_whitespace() || c == '=').trim_end()),
+        None => (line, ""),
+    }
+}
+
+/// ssh pattern lists: any positive pattern must match and no negated one may
+fn host_matches(patterns: &[String], host: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        match pattern.strip_prefix('!') {
+            Some(negated) if glob::glob_match(negated, host) => return false,
+            Some(_) => {}
+            None => matched |= glob::glob_match(pattern, host),
+        }
+    }
+    matched
+}
+
+/// Resolve an `Include` argument to files, sorted; wildcards are supported in the file name
+fn expand_include(pattern: &str) -> Vec<PathBuf> {
+    let path = match (pattern.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ if Path::new(pattern).is_absolute() => PathBuf::from(pattern),
+        _ => match ssh_dir() {
+            Some(dir) => dir.join(pattern),
+            None => PathBuf::from(pattern),
+        },
+    };
+
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    if !name.contains(['*', '?']) {
+        return vec![path];
+    }
+    let Some(dir) = path.parent() else { return Vec::new() };
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir).into_iter().flatten().flatten()
+        .filter(|entry| entry.file_name().to_str().is_some_and(|file| glob::glob_match(name, file)))
+        .map(|entry| entry.path())
+        .filter(|file| file.is_file())
+        .collect();
+    files.sort();
+    files
+}
+
+fn ssh_dir() -> Option<PathBuf> {
+    Some(PathBuf::from(std::env::var_os("HOME")?).join(".ssh"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mcp-ssh-config-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn host<'a>(hosts: &'a [KnownHost], alias: &str) -> &'a KnownHost {
+        hosts.iter().find(|host| host.alias == alias).unwrap()
+    }
+
+    #[test]
+    fn splits_keyword_lines() {
+        assert_eq!(split_line("HostName example.com"), ("HostName", "example.com"));
+        assert_eq!(split_line("Port=2222"), ("Port", "2222"));
+        assert_eq!(split_line("User = admin  "), ("User", "admin"));
+        assert_eq!(split_line("Host"), ("Host", ""));
+    }
+
+    #[test]
+    fn matches_negated_patterns() {
+        let patterns = ["*.example.com".to_string(), "!db.example.com".to_string()];
+        assert!(host_matches(&patterns, "web.example.com"));
+        assert!(!host_matches(&patterns, "db.example.com"));
+        assert!(!host_matches(&patterns, "example.org"));
+        assert!(!host_matches(&["!db".to_string()], "web"));
+    }
+
+    #[test]
+    fn first_matching_value_wins() {
+        let dir = temp_dir("first");
+        let path = dir.join("config");
+        fs::write(&path, "
+# comment
+Host web web-alt
+    HostName %h.example.com
+    User deploy
+
+Host db !legacy
+    HostName=10.0.0.7
+    Port 2222
+    ProxyJump bastion
+
+Host bastion
+    ProxyJump none
+
+Host *.example.com legacy
+    User ignored
+
+Match user root
+    User matched
+
+Host *
+    User fallback
+    Port 22
+").unwrap();
+
+        let hosts = load(&path).unwrap();
+        let aliases: Vec<&str> = hosts.iter().map(|host| host.alias.as_str()).collect();
+        assert_eq!(aliases, ["bastion", "db", "legacy", "web", "web-alt"]);
+
+        let web = host(&hosts, "web");
+        assert_eq!(web.hostname, "web.example.com");
+        assert_eq!(web.user.as_deref(), Some("deploy"));
+        assert_eq!(web.port, Some(22));
+        assert_eq!(host(&hosts, "web-alt").hostname, "web-alt.example.com");
+
+        let db = host(&hosts, "db");
+        assert_eq!(db.hostname, "10.0.0.7");
+        assert_eq!(db.user.as_deref(), Some("fallback"));
+        assert_eq!(db.port, Some(2222));
+        assert_eq!(db.proxy_jump.as_deref(), Some("bastion"));
+
+        assert_eq!(host(&hosts, "bastion").proxy_jump, None);
+        assert_eq!(host(&hosts, "legacy").user.as_deref(), Some("ignored"));
+        assert_eq!(host(&hosts, "legacy").hostname, "legacy");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expands_includes_in_place() {
+        let dir = temp_dir("include");
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        fs::write(dir.join("conf.d/10-app"), "Host app\n    HostName app.internal\nHost *\n    User from-include\n").unwrap();
+        fs::write(dir.join("conf.d/20-cache"), "Host cache\n    Port 6000\n").unwrap();
+        fs::write(dir.join("loop"), format!("Include {}\n", dir.join("loop").display())).unwrap();
+        let path = dir.join("config");
+        fs::write(&path, format!("
+Host web
+    Include {}/conf.d/*
+    Port 2200
+Include {}
+Include {}/missing
+Host *
+    User default
+", dir.display(), dir.join("loop").display(), dir.display())).unwrap();
+
+        let hosts = load(&path).unwrap();
+        let app = host(&hosts, "app");
+        assert_eq!(app.hostname, "app.internal");
+        assert_eq!(app.source, dir.join("conf.d/10-app"));
+        assert_eq!(app.user.as_deref(), Some("from-include"));
+        assert_eq!(host(&hosts, "cache").port, Some(6000));
+
+        // The `Host web` block resumes after the include
+        let web = host(&hosts, "web");
+        assert_eq!(web.port, Some(2200));
+        assert_eq!(web.user.as_deref(), Some("from-include"));
+        assert_eq!(web.source, path);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Session registry on disk, so sessions can be reconnected after a restart
+//
+// Each session is saved as the `ssh_connect` arguments that recreate it, with
+// its current `cwd` and `env` folded in. Sessions are removed by
+// `ssh_disconnect`, but not when the server shuts down.
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StateFile {
+    #[serde(default)]
+    sessions: BTreeMap<String, Value>,
+}
+
+#[derive(Debug)]
+pub struct SessionStore {
+    path: PathBuf,
+    sessions: Mutex<BTreeMap<String, Value>>,
+}
+
+impl SessionStore {
+    /// Load the registry; a missing file is an empty registry
+    pub fn open(path: &Path) -> Result<Self> {
+        let state: StateFile = match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data)
+                .context(format!("Invalid session state file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StateFile::default(),
+            Err(e) => return Err(anyhow::Error::from(e)
+                .context(format!("Failed to read session state file {}", path.display()))),
+        };
+        eprintln!("[MAIN] Session state in {} ({} saved sessions)", path.display(), state.sessions.len());
+
+        Ok(SessionStore {
+            path: path.to_path_buf(),
+            sessions: Mutex::new(state.sessions),
+        })
+    }
+
+    /// Saved sessions with the arguments that reconnect them
+    pub fn sessions(&self) -> BTreeMap<String, Value> {
+        self.sessions.lock().unwrap().clone()
+    }
+
+    pub fn contains(&self, session_id: &str) -> bool {
+        self.sessions.lock().unwrap().contains_key(session_id)
+    }
+
+    pub fn save(&self, session_id: &str, arguments: &Value) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.insert(session_id.to_string(), arguments.clone());
+        self.write(&sessions);
+    }
+
+    /// Change a saved session's arguments in place, if it is saved
+    pub fn update(&self, session_id: &str, f: impl FnOnce(&mut Value)) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(arguments) = sessions.get_mut(session_id) {
+            f(arguments);
+            self.write(&sessions);
+        }
+    }
+
+    pub fn remove(&self, session_id: &str) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.remove(session_id).is_some() {
+            self.write(&sessions);
+        }
+    }
+
+    /// Replace the file atomically; failures are logged, the sessions themselves still work
+    fn write(&self, sessions: &BTreeMap<String, Value>) {
+        if let Err(e) = self.try_write(sessions) {
+            eprintln!("[MAIN] Failed to save session state to {}: {:#}", self.path.display(), e);
+        }
+    }
+
+    fn try_write(&self, sessions: &BTreeMap<String, Value>) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        }
+        let data = serde_json::to_string_pretty(&StateFile { sessions: sessions.clone() })?;
+
+        let temp = self.path.with_extension("tmp");
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&temp)?;
+        file.write_all(data.as_bytes())?;
+        file.sync_data()?;
+        fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".local").join("state"),
+    };
+    Some(base.join("mcp-ssh-sessions").join("sessions.json"))
+}
@@ -0,0 +1,129 @@
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::time;
+
+// Per-session usage counters shown by `ssh_list_sessions`
+//
+// Counters are atomic so side-channel work that only borrows the session
+// (`ssh_copy_between`) can update them too.
+
+/// sudo's default `timestamp_timeout`; credentials are assumed cached this long after a sudo command
+const SUDO_CACHE_TIME: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug)]
+pub struct SessionStats {
+    /// Unix milliseconds
+    pub connected_at: i64,
+    commands: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    /// Last successful sudo command through the relay
+    pub last_sudo: Option<Instant>,
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        SessionStats {
+            connected_at: time::now_millis(),
+            commands: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            last_sudo: None,
+        }
+    }
+
+    /// Count a command the client asked to run
+    pub fn command(&self) {
+        self.commands.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn commands(&self) -> u64 {
+        self.commands.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    /// Whether a sudo command can run now: `cached` shortly after one succeeded,
+    /// `askpass` if the password can be asked for over X11, else `unknown`
+    /// (sudo may still work without a password, e.g. with `NOPASSWD`)
+    pub fn sudo_readiness(&self, x11: bool) -> &'static str {
+        if self.last_sudo.is_some_and(|at| at.elapsed() < SUDO_CACHE_TIME) {
+            "cached"
+        } else if x11 {
+            "askpass"
+        } else {
+            "unknown"
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "connected_at": time::format_rfc3339(self.connected_at.div_euclid(1000)),
+            "commands": self.commands(),
+            "bytes_sent": self.bytes_sent(),
+            "bytes_received": self.bytes_received()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_commands_and_traffic() {
+        let stats = SessionStats::new();
+        stats.command();
+        stats.command();
+        stats.sent(100);
+        stats.sent(20);
+        stats.received(7);
+        assert_eq!((stats.commands(), stats.bytes_sent(), stats.bytes_received()), (2, 120, 7));
+
+        let json = stats.to_json();
+        assert_eq!((&json["commands"], &json["bytes_sent"], &json["bytes_received"]), (&json!(2), &json!(120), &json!(7)));
+        assert_eq!(json["connected_at"], time::format_rfc3339(stats.connected_at / 1000));
+    }
+
+    #[test]
+    fn counters_are_shared_across_threads() {
+        let stats = SessionStats::new();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| (0..1000).for_each(|_| stats.received(1)));
+            }
+        });
+        assert_eq!(stats.bytes_received(), 4000);
+    }
+
+    #[test]
+    fn sudo_readiness_follows_the_cache_window() {
+        let mut stats = SessionStats::new();
+        assert_eq!(stats.sudo_readiness(false), "unknown");
+        assert_eq!(stats.sudo_readiness(true), "askpass");
+
+        stats.last_sudo = Some(Instant::now());
+        assert_eq!(stats.sudo_readiness(false), "cached");
+        assert_eq!(stats.sudo_readiness(true), "cached");
+
+        stats.last_sudo = Some(Instant::now() - SUDO_CACHE_TIME);
+        assert_eq!(stats.sudo_readiness(false), "unknown");
+        assert_eq!(stats.sudo_readiness(true), "askpass");
+    }
+}
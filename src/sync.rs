@@ -0,0 +1,305 @@
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::files;
+use crate::glob;
+use crate::shell::quote;
+use crate::transfer::{self, Filters};
+use crate::SshSession;
+
+// Incremental local -> remote sync based on sha256 checksums
+
+/// Number of paths per category listed in the text summary
+const SUMMARY_PATHS: usize = 20;
+
+pub fn tool_definitions() -> Vec<Value> {
+    vec![
+        json!({
+            "name": "ssh_sync",
+            "description": "Sync a local directory to a remote directory, transferring only added or changed files (compared by sha256)",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": { "type": "string", "description": "Session ID or hostname" },
+                    "local_path": { "type": "string", "description": "Local source directory" },
+                    "remote_path": { "type": "string", "description": "Remote destination directory (created if missing)" },
+                    "include": { "type": "array", "items": { "type": "string" }, "description": "Only sync files matching one of these globs" },
+                    "exclude": { "type": "array", "items": { "type": "string" }, "description": "Skip (and never delete) files matching any of these globs" },
+                    "delete": { "type": "boolean", "description": "Delete remote files that do not exist locally", "default": false },
+                    "dry_run": { "type": "boolean", "description": "Only report what would change", "default": false },
+                    "max_bytes": { "type": "integer", "description": "Abort if the files to transfer are larger than this in total" },
+                    "sudo": { "type": "boolean", "description": "Read and write remote files with sudo", "default": false }
+                },
+                "required": ["session_id", "local_path", "remote_path"]
+            }
+        }),
+    ]
+}
+
+/// Handle `ssh_sync`
+pub fn sync(session_id: &str, session: &mut SshSession, arguments: &Value) -> Result<Value> {
+    let local_path = arguments["local_path"].as_str()
+        .context("Missing or invalid local_path parameter - must be a string")?;
+    let remote_path = arguments["remote_path"].as_str()
+        .context("Missing or invalid remote_path parameter - must be a string")?;
+    if remote_path.is_empty() || remote_path.contains(['\n', '\0']) {
+        bail!("Invalid remote_path parameter");
+    }
+    let delete = arguments["delete"].as_bool().unwrap_or(false);
+    let dry_run = arguments["dry_run"].as_bool().unwrap_or(false);
+    let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
+    let filters = Filters::from_arguments(arguments)?;
+
+    let local_dir = Path::new(local_path);
+    if !local_dir.is_dir() {
+        bail!("Local path is not a directory: {}", local_path);
+    }
+
+    let keep = |path: &String| glob::filter_path(path, &filters.include, &filters.exclude);
+    let local = local_checksums(local_dir, keep)?;
+    let remote = remote_checksums(session, remote_path, use_sudo)?;
+
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    let mut unchanged = 0;
+    let mut transfer_bytes = 0;
+    for (path, (checksum, size)) in &local {
+        match remote.get(path) {
+            None => added.push(path.clone()),
+            Some(remote_checksum) if remote_checksum != checksum => changed.push(path.clone()),
+            Some(_) => {
+                unchanged += 1;
+                continue;
+            }
+        }
+        transfer_bytes += size;
+    }
+    let deleted: Vec<String> = if delete {
+        remote.keys()
+            .filter(|path| keep(path) && !local.contains_key(*path))
+            .cloned()
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    if transfer_bytes > filters.max_bytes {
+        bail!("Files to transfer total {} bytes, more than max_bytes ({})", transfer_bytes, filters.max_bytes);
+    }
+
+    eprintln!("[MCP] Sync {} -> {} on session '{}': {} added, {} changed, {} deleted (dry run: {})",
+        local_path, remote_path, session_id, added.len(), changed.len(), deleted.len(), dry_run);
+
+    let mut archive_bytes = 0;
+    if !dry_run {
+        let transfer: Vec<String> = added.iter().chain(changed.iter()).cloned().collect();
+        archive_bytes = transfer::upload_paths(session, local_dir, &transfer, remote_path, use_sudo)?;
+
+        if !deleted.is_empty() {
+            delete_remote(session, remote_path, &deleted, use_sudo)?;
+        }
+    }
+
+    let mut text = format!("{}{} added, {} changed, {} deleted, {} unchanged ({} bytes to transfer)",
+        if dry_run { "Dry run: " } else { "" },
+        added.len(), changed.len(), deleted.len(), unchanged, transfer_bytes);
+    for (marker, paths) in [("+", &added), ("~", &changed), ("-", &deleted)] {
+        for path in paths.iter().take(SUMMARY_PATHS) {
+            text.push_str(&format!("\n  {} {}", marker, path));
+        }
+        if paths.len() > SUMMARY_PATHS {
+            text.push_str(&format!("\n  {} ... and {} more", marker, paths.len() - SUMMARY_PATHS));
+        }
+    }
+
+    Ok(json!({
+        "content": [{
+            "type": "text",
+            "text": text
+        }],
+        "metadata": {
+            "session_id": session_id,
+            "local_path": local_path,
+            "remote_path": remote_path,
+            "dry_run": dry_run,
+            "added": added,
+            "changed": changed,
+            "deleted": deleted,
+            "unchanged": unchanged,
+            "bytes": transfer_bytes,
+            "archive_bytes": archive_bytes
+        }
+    }))
+}
+
+/// Checksums of the local regular files and symlinks passing `keep` (symlinks by target)
+fn local_checksums(root: &Path, keep: impl Fn(&String) -> bool) -> Result<BTreeMap<String, (String, u64)>> {
+    let mut checksums = BTreeMap::new();
+    for (relative, size) in transfer::walk_local(root)?.into_iter().filter(|(relative, _)| keep(relative)) {
+        let path = root.join(&relative);
+        let checksum = if path.symlink_metadata()?.file_type().is_symlink() {
+            format!("symlink:{}", fs::read_link(&path)?.to_string_lossy())
+        } else {
+            files::sha256_hex(&fs::read(&path).context(format!("Failed to read {}", path.display()))?)
+        };
+        checksums.insert(relative, (checksum, size));
+    }
+    Ok(checksums)
+}
+
+/// Checksums of remote regular files and symlinks, computed on the remote host
+fn remote_checksums(session: &mut SshSession, remote_dir: &str, use_sudo: bool) -> Result<BTreeMap<String, String>> {
+    let files_output = session.run_checked(&format!("{} | base64 -w0 && echo", checksums_command(remote_dir)), use_sudo)
+        .context(format!("Failed to compute checksums in {}", remote_dir))?;
+    let links_output = session.run_checked(&format!("{} | base64 -w0 && echo", symlinks_command(remote_dir)), use_sudo)
+        .context(format!("Failed to list symlinks in {}", remote_dir))?;
+
+    let mut checksums = BTreeMap::new();
+
+    let files_listing = BASE64.decode(files_output.trim()).context("Invalid checksum listing encoding")?;
+    checksums.extend(parse_checksums(&files_listing));
+
+    let links_listing = BASE64.decode(links_output.trim()).context("Invalid symlink listing encoding")?;
+    checksums.extend(parse_symlinks(&links_listing));
+
+    Ok(checksums)
+}
+
+// Errors (e.g. unreadable files) would corrupt the encoded listings, so both drop them
+
+/// `sha256sum -z` output for the regular files below `dir`, nothing if it does not exist
+fn checksums_command(dir: &str) -> String {
+    format!("if [ -d {d} ]; then cd -- {d} && find . -mindepth 1 -type f -printf '%P\\0' 2>/dev/null | xargs -0 -r sha256sum -z -- 2>/dev/null; fi",
+        d = quote(dir))
+}
+
+/// NUL-separated path and target pairs of the symlinks below `dir`
+fn symlinks_command(dir: &str) -> String {
+    format!("if [ -d {d} ]; then cd -- {d} && find . -mindepth 1 -type l -printf '%P\\0%l\\0' 2>/dev/null; fi",
+        d = quote(dir))
+}
+
+/// Parse `symlinks_command` output into path -> "symlink:<target>", as symlinks are compared
+fn parse_symlinks(listing: &[u8]) -> BTreeMap<String, String> {
+    let mut symlinks = BTreeMap::new();
+    let mut fields = listing.split(|&b| b == 0);
+    while let (Some(path), Some(target)) = (fields.next(), fields.next()) {
+        if !path.is_empty() {
+            symlinks.insert(String::from_utf8_lossy(path).into_owned(),
+                format!("symlink:{}", String::from_utf8_lossy(target)));
+        }
+    }
+    symlinks
+}
+
+/// Parse `sha256sum -z` output into path -> checksum
+pub fn parse_checksums(listing: &[u8]) -> BTreeMap<String, String> {
+    listing.split(|&b| b == 0)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (checksum, path) = entry.split_once("  ")?;
+            Some((path.to_string(), checksum.to_string()))
+        })
+        .collect()
+}
+
+fn delete_remote(session: &mut SshSession, remote_dir: &str, paths: &[String], use_sudo: bool) -> Result<()> {
+    let mut list = Vec::new();
+    for path in paths {
+        list.extend_from_slice(path.as_bytes());
+        list.push(0);
+    }
+
+    let remote_list = transfer::remote_temp_file(session, use_sudo)?;
+    let result = files::upload_bytes(session, &list, &remote_list, use_sudo).and_then(|()| {
+        session.run_checked(&format!("cd -- {} && xargs -0 -r rm -f -- < {}", quote(remote_dir), quote(&remote_list)), use_sudo)
+            .map(|_| ())
+            .context(format!("Failed to delete extraneous files in {}", remote_dir))
+    });
+    let _ = session.execute(&format!("rm -f -- {}", quote(&remote_list)), use_sudo);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    /// A tree with awkward names, a symlink, an empty directory and a file in a subdirectory
+    fn tree(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mcp-ssh-sync-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub dir")).unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        fs::write(dir.join("plain.txt"), "plain").unwrap();
+        fs::write(dir.join("two  spaces.txt"), "spaces").unwrap();
+        fs::write(dir.join("new\nline"), "newline").unwrap();
+        fs::write(dir.join("back\\slash"), "backslash").unwrap();
+        fs::write(dir.join("sub dir/inner.txt"), "inner").unwrap();
+        symlink("../plain.txt", dir.join("sub dir/link")).unwrap();
+        dir
+    }
+
+    fn run(command: &str) -> Vec<u8> {
+        let output = Command::new("bash").arg("-c").arg(command).output().unwrap();
+        assert!(output.status.success());
+        output.stdout
+    }
+
+    #[test]
+    fn parses_remote_checksums_of_awkward_names() {
+        let dir = tree("remote");
+        let checksums = parse_checksums(&run(&checksums_command(dir.to_str().unwrap())));
+        assert_eq!(checksums.keys().collect::<Vec<_>>(),
+            ["back\\slash", "new\nline", "plain.txt", "sub dir/inner.txt", "two  spaces.txt"]);
+        assert_eq!(checksums["two  spaces.txt"], files::sha256_hex(b"spaces"));
+        assert_eq!(checksums["new\nline"], files::sha256_hex(b"newline"));
+        assert_eq!(checksums["back\\slash"], files::sha256_hex(b"backslash"));
+
+        let symlinks = parse_symlinks(&run(&symlinks_command(dir.to_str().unwrap())));
+        assert_eq!(symlinks.len(), 1);
+        assert_eq!(symlinks["sub dir/link"], "symlink:../plain.txt");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn local_and_remote_checksums_agree() {
+        let dir = tree("agree");
+        let local: BTreeMap<String, String> = local_checksums(&dir, |_| true).unwrap().into_iter()
+            .map(|(path, (checksum, _))| (path, checksum))
+            .collect();
+        let mut remote = parse_checksums(&run(&checksums_command(dir.to_str().unwrap())));
+        remote.extend(parse_symlinks(&run(&symlinks_command(dir.to_str().unwrap()))));
+        assert_eq!(local, remote);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_and_empty_directories_list_nothing() {
+        let dir = tree("empty");
+        for path in [dir.join("empty"), dir.join("missing")] {
+            assert!(run(&checksums_command(path.to_str().unwrap())).is_empty());
+            assert!(run(&symlinks_command(path.to_str().unwrap())).is_empty());
+        }
+        assert!(parse_checksums(b"").is_empty());
+        assert!(parse_symlinks(b"").is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skips_malformed_checksum_entries() {
+        let parsed = parse_checksums(b"abc  a b\0garbage\0\0def  c\0");
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed["a b"], "abc");
+        // A dangling path without a target is ignored
+        assert!(parse_symlinks(b"link").is_empty());
+    }
+}
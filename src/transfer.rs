@@ -0,0 +1,523 @@
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use crate::copy;
+use crate::files;
+use crate::glob;
+use crate::shell::quote;
+use crate::SshSession;
+
+// Directory transfers as tar archives streamed over a side channel of the
+// session's master connection
+//
+// Not through the session's relay shell: the relay carries one text request and
+// its output at a time, so a binary archive would have to be base64-encoded and
+// buffered through it, and would hold the relay for the whole transfer.
+//
+// Downloaded archives come from the remote host and are not trusted: only the
+// listed members are extracted, their names are checked first, and local tar
+// keeps its defaults of stripping leading `/` and refusing `..` members.
+
+const DEFAULT_MAX_TRANSFER_BYTES: u64 = 100 * 1024 * 1024;
+
+/// Number of transferred file names listed in the text summary
+const SUMMARY_FILES: usize = 20;
+
+/// Most files a remote directory walk returns
+const MAX_WALK_ENTRIES: usize = 100_000;
+
+/// Numbers the local member list files of concurrent downloads
+static NEXT_MEMBER_LIST: AtomicU64 = AtomicU64::new(1);
+
+pub fn tool_definitions() -> Vec<Value> {
+    let filter_properties = json!({
+        "include": { "type": "array", "items": { "type": "string" }, "description": "Only transfer files matching one of these globs (relative paths; patterns without / match file names)" },
+        "exclude": { "type": "array", "items": { "type": "string" }, "description": "Skip files matching any of these globs" },
+        "max_bytes": { "type": "integer", "description": "Abort if the selected files are larger than this in total", "default": DEFAULT_MAX_TRANSFER_BYTES },
+        "sudo": { "type": "boolean", "description": "Read or write remote files with sudo", "default": false }
+    });
+
+    let mut upload = json!({
+        "name": "ssh_upload_dir",
+        "description": "Upload a local directory tree to a remote directory as a tar stream through the session",
+        "inputSchema": {
+            "type": "object",
+            "properties": {
+                "session_id": { "type": "string", "description": "Session ID or hostname" },
+                "local_path": { "type": "string", "description": "Local directory to upload" },
+                "remote_path": { "type": "string", "description": "Remote destination directory (created if missing)" }
+            },
+            "required": ["session_id", "local_path", "remote_path"]
+        }
+    });
+    let mut download = json!({
+        "name": "ssh_download_dir",
+        "description": "Download a remote directory tree to a local directory as a tar stream through the session",
+        "inputSchema": {
+            "type": "object",
+            "properties": {
+                "session_id": { "type": "string", "description": "Session ID or hostname" },
+                "remote_path": { "type": "string", "description": "Remote directory to download" },
+                "local_path": { "type": "string", "description": "Local destination directory (created if missing)" }
+            },
+            "required": ["session_id", "remote_path", "local_path"]
+        }
+    });
+    for tool in [&mut upload, &mut download] {
+        if let (Some(properties), Some(extra)) = (tool["inputSchema"]["properties"].as_object_mut(), filter_properties.as_object()) {
+            properties.extend(extra.clone());
+        }
+    }
+
+    vec![upload, download]
+}
+
+pub struct Filters {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub max_bytes: u64,
+}
+
+impl Filters {
+    pub fn from_arguments(arguments: &Value) -> Result<Self> {
+        Ok(Filters {
+            include: string_list(arguments, "include")?,
+            exclude: string_list(arguments, "exclude")?,
+            max_bytes: files::optional_u64(arguments, "max_bytes")?.unwrap_or(DEFAULT_MAX_TRANSFER_BYTES),
+        })
+    }
+
+    /// Keep the entries passing the globs and enforce the size limit
+    pub fn select(&self, entries: Vec<(String, u64)>) -> Result<(Vec<String>, u64)> {
+        let selected: Vec<(String, u64)> = entries.into_iter()
+            .filter(|(path, _)| glob::filter_path(path, &self.include, &self.exclude))
+            .collect();
+        let total: u64 = selected.iter().map(|(_, size)| size).sum();
+        if total > self.max_bytes {
+            bail!("Selected files total {} bytes, more than max_bytes ({})", total, self.max_bytes);
+        }
+        Ok((selected.into_iter().map(|(path, _)| path).collect(), total))
+    }
+}
+
+/// Handle `ssh_upload_dir`
+pub fn upload_dir(session_id: &str, session: &mut SshSession, arguments: &Value) -> Result<Value> {
+    let local_path = arguments["local_path"].as_str()
+        .context("Missing or invalid local_path parameter - must be a string")?;
+    let remote_path = remote_path_arg(arguments)?;
+    let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
+    let filters = Filters::from_arguments(arguments)?;
+
+    let local_dir = Path::new(local_path);
+    if !local_dir.is_dir() {
+        bail!("Local path is not a directory: {}", local_path);
+    }
+
+    let (selected, total) = filters.select(walk_local(local_dir)?)?;
+    eprintln!("[MCP] Uploading {} files ({} bytes) from {} to {} on session '{}'",
+        selected.len(), total, local_path, remote_path, session_id);
+
+    let archive_size = upload_paths(session, local_dir, &selected, remote_path, use_sudo)?;
+
+    Ok(summary("Uploaded", &selected, total, archive_size, json!({
+        "session_id": session_id,
+        "local_path": local_path,
+        "remote_path": remote_path,
+    })))
+}
+
+/// Handle `ssh_download_dir`
+pub fn download_dir(session_id: &str, session: &mut SshSession, arguments: &Value) -> Result<Value> {
+    let remote_path = remote_path_arg(arguments)?;
+    let local_path = arguments["local_path"].as_str()
+        .context("Missing or invalid local_path parameter - must be a string")?;
+    let use_sudo = arguments["sudo"].as_bool().unwrap_or(false);
+    let filters = Filters::from_arguments(arguments)?;
+
+    let (selected, total) = filters.select(walk_remote(session, remote_path, use_sudo)?)?;
+    eprintln!("[MCP] Downloading {} files ({} bytes) from {} on session '{}' to {}",
+        selected.len(), total, remote_path, session_id, local_path);
+
+    let archive_size = download_paths(session, remote_path, &selected, Path::new(local_path), filters.max_bytes, use_sudo)?;
+
+    Ok(summary("Downloaded", &selected, total, archive_size, json!({
+        "session_id": session_id,
+        "remote_path": remote_path,
+        "local_path": local_path,
+    })))
+}
+
+/// Tar the given relative paths of `local_dir` and unpack them under `remote_dir`
+pub fn upload_paths(session: &SshSession, local_dir: &Path, paths: &[String], remote_dir: &str, use_sudo: bool) -> Result<u64> {
+    if paths.is_empty() {
+        return Ok(0);
+    }
+
+    let mut pack = Command::new("tar")
+        .arg("-C").arg(local_dir)
+        .args(["-czf", "-", "--null", "-T", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to execute local tar")?;
+    // Files keep the uploading user's ownership, also when root unpacks them
+    let unpack = session.side_command(&format!("mkdir -p -- {d} && tar -xzf - --no-same-owner -C {d}", d = quote(remote_dir)), use_sudo)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut unpack = match unpack {
+        Ok(unpack) => unpack,
+        Err(e) => {
+            let _ = pack.kill();
+            let _ = pack.wait();
+            return Err(anyhow::Error::from(e).context("Failed to start remote tar"));
+        }
+    };
+
+    pipe(&mut pack, &mut unpack, nul_list(paths), u64::MAX, |n| session.stats.sent(n),
+        "Failed to create local tar archive".to_string(),
+        format!("Failed to unpack archive into {}", remote_dir))
+}
+
+/// Tar the given relative paths of `remote_dir` and unpack them under `local_dir`
+pub fn download_paths(session: &SshSession, remote_dir: &str, paths: &[String], local_dir: &Path, max_bytes: u64, use_sudo: bool) -> Result<u64> {
+    if paths.is_empty() {
+        return Ok(0);
+    }
+    for path in paths {
+        check_member(path)?;
+    }
+
+    fs::create_dir_all(local_dir)
+        .context(format!("Failed to create local directory {}", local_dir.display()))?;
+    let members = MemberList::write(paths)?;
+
+    let mut pack = session.side_command(&format!("tar -czf - -C {} --null -T -", quote(remote_dir)), use_sudo)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to start remote tar")?;
+    let unpack = unpack_command(local_dir, &members.path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut unpack = match unpack {
+        Ok(unpack) => unpack,
+        Err(e) => {
+            let _ = pack.kill();
+            let _ = pack.wait();
+            return Err(anyhow::Error::from(e).context("Failed to execute local tar"));
+        }
+    };
+
+    // Compressed archives are smaller than their content, so the size limit still applies
+    pipe(&mut pack, &mut unpack, nul_list(paths), max_bytes, |n| session.stats.received(n),
+        format!("Failed to create archive of {}", remote_dir),
+        "Failed to unpack archive locally".to_string())
+}
+
+/// Local tar extracting exactly the members named in `member_list`, nothing else the archive holds
+fn unpack_command(local_dir: &Path, member_list: &Path) -> Command {
+    let mut command = Command::new("tar");
+    command.arg("-C").arg(local_dir)
+        .args(["-xzf", "-", "--no-same-owner", "--no-recursion", "--no-wildcards", "--null", "-T"])
+        .arg(member_list);
+    command
+}
+
+/// A NUL-separated list of archive members in a local temporary file, removed on drop
+struct MemberList {
+    path: PathBuf,
+}
+
+impl MemberList {
+    fn write(paths: &[String]) -> Result<Self> {
+        let path = std::env::temp_dir().join(format!("mcp-ssh-members-{}-{}",
+            std::process::id(), NEXT_MEMBER_LIST.fetch_add(1, Ordering::Relaxed)));
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&path)
+            .context(format!("Failed to create {}", path.display()))?;
+        let list = MemberList { path };
+        file.write_all(&nul_list(paths))
+            .context(format!("Failed to write {}", list.path.display()))?;
+        Ok(list)
+    }
+}
+
+impl Drop for MemberList {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Refuse member names that could land outside the destination directory
+fn check_member(path: &str) -> Result<()> {
+    if path.is_empty() || path.starts_with('/') || path.contains('\0')
+        || path.split('/').any(|component| component == "..") {
+        bail!("Refusing to transfer unsafe path from the remote listing: {:?}", path);
+    }
+    Ok(())
+}
+
+/// Feed `list` to `pack`, stream its output into `unpack` and wait for both,
+/// returning the bytes streamed; more than `limit` bytes abort the transfer
+fn pipe(pack: &mut Child, unpack: &mut Child, list: Vec<u8>, limit: u64, count: impl Fn(usize),
+        pack_context: String, unpack_context: String) -> Result<u64> {
+    // Feed stdin from a thread so a full stdout pipe cannot deadlock us
+    let writer = pack.stdin.take().map(|mut stdin| thread::spawn(move || stdin.write_all(&list)));
+    let pack_stderr = copy::collect(pack.stderr.take());
+    let unpack_stderr = copy::collect(unpack.stderr.take());
+
+    let mut input = pack.stdout.take().context("Failed to get stdout of tar")?;
+    let mut output = unpack.stdin.take().context("Failed to get stdin of tar")?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut streamed = 0u64;
+    let copied: Result<()> = loop {
+        let n = match input.read(&mut buffer) {
+            Ok(0) => break Ok(()),
+            Ok(n) => n,
+            Err(e) => break Err(anyhow::Error::from(e).context("Failed to read archive")),
+        };
+        streamed += n as u64;
+        if streamed > limit {
+            break Err(anyhow::anyhow!("Archive is larger than max_bytes ({}), transfer aborted", limit));
+        }
+        if let Err(e) = output.write_all(&buffer[..n]) {
+            break Err(anyhow::Error::from(e).context("Failed to write archive"));
+        }
+        count(n);
+    };
+    // Closing both ends stops the other side too; killing tar would leave its
+    // gzip child holding the stderr pipe
+    drop(input);
+    drop(output);
+
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    let pack_status = copy::finish(pack, pack_stderr);
+    let unpack_status = copy::finish(unpack, unpack_stderr);
+    if streamed > limit {
+        return copied.map(|()| streamed);
+    }
+    // When unpacking fails the packing side only sees a closed pipe
+    unpack_status.context(unpack_context)?;
+    pack_status.context(pack_context)?;
+    copied?;
+    Ok(streamed)
+}
+
+/// Regular files and symlinks below `root` as (relative path, size)
+pub fn walk_local(root: &Path) -> Result<Vec<(String, u64)>> {
+    let mut entries = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let listing = fs::read_dir(&dir)
+            .context(format!("Failed to read local directory {}", dir.display()))?;
+        for entry in listing {
+            let entry = entry?;
+            let metadata = entry.path().symlink_metadata()?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else if metadata.is_file() || metadata.file_type().is_symlink() {
+                let path = entry.path();
+                let relative = path.strip_prefix(root)?;
+                let relative = relative.to_str()
+                    .context(format!("File name is not valid UTF-8: {}", String::from_utf8_lossy(relative.as_os_str().as_bytes())))?;
+                entries.push((relative.to_string(), metadata.len()));
+            }
+        }
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+/// Regular files and symlinks below a remote directory as (relative path, size)
+pub fn walk_remote(session: &mut SshSession, remote_dir: &str, use_sudo: bool) -> Result<Vec<(String, u64)>> {
+    // NUL-separated and base64-encoded so any file name survives the relay;
+    // find's errors would corrupt the encoded listing
+    let output = session.run_checked(&format!(
+        "cd -- {} && find . -mindepth 1 \\( -type f -o -type l \\) -printf '%s %P\\0' 2>/dev/null | head -z -n {} | base64 -w0 && echo",
+        quote(remote_dir), MAX_WALK_ENTRIES + 1), use_sudo)
+        .context(format!("Failed to list remote directory {}", remote_dir))?;
+    let listing = BASE64.decode(output.trim())
+        .context("Invalid directory listing encoding")?;
+
+    let mut entries = Vec::new();
+    for entry in listing.split(|&b| b == 0).filter(|entry| !entry.is_empty()) {
+        let entry = std::str::from_utf8(entry)
+            .context(format!("File name is not valid UTF-8: {}", String::from_utf8_lossy(entry)))?;
+        if let Some((size, path)) = entry.split_once(' ') {
+            entries.push((path.to_string(), size.parse().context("Invalid size in directory listing")?));
+        }
+    }
+    if entries.len() > MAX_WALK_ENTRIES {
+        bail!("{} has more than {} files; transfer a subdirectory instead", remote_dir, MAX_WALK_ENTRIES);
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+pub fn remote_temp_file(session: &mut SshSession, use_sudo: bool) -> Result<String> {
+    let output = session.run_checked("mktemp /tmp/mcp-transfer.XXXXXXXX", use_sudo)
+        .context("Failed to create remote temp file")?;
+    Ok(output.trim().to_string())
+}
+
+fn nul_list(paths: &[String]) -> Vec<u8> {
+    let mut list = Vec::new();
+    for path in paths {
+        list.extend_from_slice(path.as_bytes());
+        list.push(0);
+    }
+    list
+}
+
+fn summary(verb: &str, files: &[String], total: u64, archive_size: u64, mut metadata: Value) -> Value {
+    let mut text = format!("{} {} files ({} bytes, {} bytes compressed)", verb, files.len(), total, archive_size);
+    for file in files.iter().take(SUMMARY_FILES) {
+        text.push_str(&format!("\n  {}", file));
+    }
+    if files.len() > SUMMARY_FILES {
+        text.push_str(&format!("\n  ... and {} more", files.len() - SUMMARY_FILES));
+    }
+
+    metadata["files_transferred"] = json!(files.len());
+    metadata["bytes"] = json!(total);
+    metadata["archive_bytes"] = json!(archive_size);
+    metadata["files"] = json!(files);
+
+    json!({
+        "content": [{
+            "type": "text",
+            "text": text
+        }],
+        "metadata": metadata
+    })
+}
+
+fn remote_path_arg(arguments: &Value) -> Result<&str> {
+    let path = arguments["remote_path"].as_str()
+        .context("Missing or invalid remote_path parameter - must be a string")?;
+    if path.is_empty() || path.contains(['\n', '\0']) {
+        bail!("Invalid remote_path parameter");
+    }
+    Ok(path)
+}
+
+pub fn string_list(arguments: &Value, key: &str) -> Result<Vec<String>> {
+    match &arguments[key] {
+        Value::Null => Ok(Vec::new()),
+        Value::Array(items) => items.iter()
+            .map(|item| item.as_str().map(str::to_string)
+                .context(format!("Invalid {} parameter - must be an array of strings", key)))
+            .collect(),
+        _ => bail!("Invalid {} parameter - must be an array of strings", key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mcp-ssh-transfer-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn refuses_members_outside_the_destination() {
+        for path in ["a.txt", "dir/b c.txt", "..data", "x/..y", "-rf", "./a"] {
+            assert!(check_member(path).is_ok(), "{}", path);
+        }
+        for path in ["", "/etc/passwd", "..", "../x", "a/../../x", "a/..", "a\0b"] {
+            assert!(check_member(path).is_err(), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn extracts_only_listed_members() {
+        let dir = temp_dir("unpack");
+        let (source, dest) = (dir.join("source/inner"), dir.join("dest"));
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        for name in ["ok.txt", "extra.txt", "-dash.txt"] {
+            fs::write(source.join(name), name).unwrap();
+        }
+        fs::write(dir.join("source/escape.txt"), "escape").unwrap();
+
+        // An archive a hostile host could send: unlisted and escaping members included
+        let archive = Command::new("tar")
+            .arg("-C").arg(&source)
+            .args(["-P", "-czf", "-", "ok.txt", "extra.txt", "./-dash.txt", "../escape.txt"])
+            .output().unwrap();
+        assert!(archive.status.success());
+
+        let members = MemberList::write(&["ok.txt".to_string(), "./-dash.txt".to_string(), "../escape.txt".to_string()]).unwrap();
+        let mut unpack = unpack_command(&dest, &members.path).stdin(Stdio::piped()).stderr(Stdio::null()).spawn().unwrap();
+        unpack.stdin.take().unwrap().write_all(&archive.stdout).unwrap();
+        unpack.wait().unwrap();
+
+        assert_eq!(fs::read_to_string(dest.join("ok.txt")).unwrap(), "ok.txt");
+        assert_eq!(fs::read_to_string(dest.join("-dash.txt")).unwrap(), "-dash.txt");
+        assert!(!dest.join("extra.txt").exists());
+        assert!(!dir.join("escape.txt").exists() && !dest.join("escape.txt").exists());
+
+        let list = members.path.clone();
+        drop(members);
+        assert!(!list.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filters_select_and_limit_size() {
+        let entries = vec![("a.rs".to_string(), 10), ("b.log".to_string(), 20), ("src/c.rs".to_string(), 30)];
+        let filters = Filters { include: vec!["*.rs".to_string()], exclude: vec![], max_bytes: 100 };
+        assert_eq!(filters.select(entries.clone()).unwrap(), (vec!["a.rs".to_string(), "src/c.rs".to_string()], 40));
+
+        let filters = Filters { include: vec![], exclude: vec!["*.log".to_string()], max_bytes: 39 };
+        assert!(filters.select(entries).unwrap_err().to_string().contains("more than max_bytes (39)"));
+    }
+
+    #[test]
+    fn lists_paths_nul_separated() {
+        assert_eq!(nul_list(&["a b".to_string(), "c\nd".to_string()]), b"a b\0c\nd\0");
+        assert!(nul_list(&[]).is_empty());
+    }
+
+    #[test]
+    fn summarizes_at_most_some_files() {
+        let files: Vec<String> = (0..SUMMARY_FILES + 3).map(|n| format!("f{}", n)).collect();
+        let summary = summary("Uploaded", &files, 100, 40, json!({ "session_id": "s" }));
+        let text = summary["content"][0]["text"].as_str().unwrap();
+        assert!(text.starts_with(&format!("Uploaded {} files (100 bytes, 40 bytes compressed)", files.len())));
+        assert!(text.ends_with("\n  ... and 3 more"));
+        assert_eq!(summary["metadata"]["files_transferred"], json!(files.len()));
+        assert_eq!(summary["metadata"]["session_id"], json!("s"));
+    }
+
+    #[test]
+    fn validates_arguments() {
+        assert!(remote_path_arg(&json!({ "remote_path": "/srv/app" })).is_ok());
+        assert!(remote_path_arg(&json!({ "remote_path": "" })).is_err());
+        assert!(remote_path_arg(&json!({ "remote_path": "a\nb" })).is_err());
+        assert_eq!(string_list(&json!({ "include": ["*.rs"] }), "include").unwrap(), ["*.rs"]);
+        assert!(string_list(&json!({}), "include").unwrap().is_empty());
+        assert!(string_list(&json!({ "include": "*.rs" }), "include").is_err());
+    }
+}
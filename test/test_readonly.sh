@@ -0,0 +1,84 @@
+#!/bin/bash
+
+# Test read-only session command analysis
+# Usage: test/test_readonly.sh [host]   (defaults to localhost)
+
+HOST="${1:-localhost}"
+
+echo "Testing read-only session on $HOST..."
+
+# Commands that must be rejected before reaching the host
+DENIED=(
+    'echo hi > /tmp/mcp-readonly-test'
+    'ls &>/tmp/mcp-readonly-test'
+    'rm -rf /tmp/mcp-readonly-test'
+    'ls; touch /tmp/mcp-readonly-test'
+    'echo "$(touch /tmp/mcp-readonly-test)"'
+    'FOO=1 nice -n 5 rm /tmp/mcp-readonly-test'
+    'find /tmp -name mcp-readonly-test -delete'
+    'find /tmp -exec rm {} +'
+    'sed -i s/a/b/ /tmp/mcp-readonly-test'
+    'systemctl restart nginx'
+    'git push'
+    'apt-get install foo'
+    'tar xzf x.tgz'
+    'bash -c "rm -rf /tmp/mcp-readonly-test"'
+    'grep -r foo . | tee /tmp/mcp-readonly-test'
+    'sudo cat /etc/shadow'
+)
+
+# Commands that must run
+ALLOWED=(
+    'ls -la /'
+    'cat /etc/hostname | grep . > /dev/null 2>&1; echo ok'
+    'echo $(date) "$(uname -s)"'
+    'git --version 2>/dev/null || true'
+    'cat <<EOF
+rm -rf /tmp/mcp-readonly-test > /tmp/mcp-readonly-test
+EOF'
+)
+
+request() {
+    python3 -c 'import json,sys; print(json.dumps({"jsonrpc":"2.0","id":int(sys.argv[1]),"method":"tools/call","params":{"name":"ssh_execute","arguments":{"session_id":"ro","command":sys.argv[2]}}}))' "$1" "$2"
+}
+
+{
+    echo '{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}'
+    echo "{\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"tools/call\",\"params\":{\"name\":\"ssh_connect\",\"arguments\":{\"host\":\"$HOST\",\"session_id\":\"ro\",\"read_only\":true}}}"
+    id=100
+    for command in "${DENIED[@]}"; do request $id "$command"; id=$((id + 1)); done
+    id=200
+    for command in "${ALLOWED[@]}"; do request $id "$command"; id=$((id + 1)); done
+} > /tmp/mcp_readonly_test.txt
+
+OUTPUT=$(timeout 60 cargo run -q < /tmp/mcp_readonly_test.txt 2>/dev/null)
+
+FAILED=0
+check() {
+    local id=$1 expect=$2 command=$3
+    local line
+    line=$(echo "$OUTPUT" | grep "\"id\":$id[,}]")
+    if [ "$expect" = denied ] && ! echo "$line" | grep -q 'Policy denied'; then
+        echo "FAIL (not denied): $command"
+        FAILED=1
+    elif [ "$expect" = allowed ] && ! echo "$line" | grep -q '"result"'; then
+        echo "FAIL (not allowed): $command"
+        FAILED=1
+    fi
+}
+
+id=100
+for command in "${DENIED[@]}"; do check $id denied "$command"; id=$((id + 1)); done
+id=200
+for command in "${ALLOWED[@]}"; do check $id allowed "$command"; id=$((id + 1)); done
+
+if [ -e /tmp/mcp-readonly-test ] && [ "$HOST" = localhost ]; then
+    echo "FAIL: /tmp/mcp-readonly-test was created"
+    FAILED=1
+fi
+
+if [ $FAILED -eq 0 ]; then
+    echo "All read-only checks passed"
+else
+    exit 1
+fi
@@ -0,0 +1,57 @@
+#!/bin/bash
+
+# Property test for the relay command transport: random commands must reach
+# bash byte for byte. Each command prints the sha256 of its own text
+# ($BASH_EXECUTION_STRING) and exits before the random tail is parsed.
+# Usage: test/test_transport.sh [host] [count]   (defaults to localhost, 200)
+
+HOST="${1:-localhost}"
+COUNT="${2:-200}"
+
+echo "Testing command transport on $HOST with $COUNT random commands..."
+
+python3 - "$HOST" "$COUNT" > /tmp/mcp_transport_test.txt 3> /tmp/mcp_transport_expected.txt << 'EOF'
+import hashlib, json, os, random, sys
+
+host, count = sys.argv[1], int(sys.argv[2])
+expected = os.fdopen(3, "w")
+rng = random.Random(41)
+
+# Characters that broke the old escaping, plus newlines, control characters and non-ASCII
+special = list("\\\"'$`!&|;<>(){}[]*?~#%^=\n\r\t ") + ["\x01", "\x1b", "\x7f", "é", "€", "𝄞", "<<<EXIT:0>>>"]
+
+def random_tail():
+    length = rng.choice([0, 1, 5, 50, 500, 5000])
+    return "".join(rng.choice(special) if rng.random() < 0.5 else chr(rng.randint(1, 0x2FF)) for _ in range(length))
+
+print(json.dumps({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}))
+print(json.dumps({"jsonrpc": "2.0", "id": 2, "method": "tools/call", "params": {"name": "ssh_connect", "arguments": {"host": host, "session_id": "transport"}}}))
+for i in range(count):
+    command = "printf '%s' \"$BASH_EXECUTION_STRING\" | sha256sum | cut -d' ' -f1; exit\n" + random_tail()
+    print(json.dumps({"jsonrpc": "2.0", "id": 100 + i, "method": "tools/call", "params": {"name": "ssh_execute", "arguments": {"session_id": "transport", "command": command}}}))
+    expected.write("%d %s\n" % (100 + i, hashlib.sha256(command.encode()).hexdigest()))
+EOF
+
+timeout 120 cargo run -q < /tmp/mcp_transport_test.txt 2>/dev/null > /tmp/mcp_transport_output.txt
+
+python3 - << 'EOF'
+import json, sys
+
+expected = dict(line.split() for line in open("/tmp/mcp_transport_expected.txt"))
+results = {}
+for line in open("/tmp/mcp_transport_output.txt"):
+    try:
+        message = json.loads(line)
+    except ValueError:
+        continue
+    if "result" in message and str(message.get("id")) in expected:
+        results[str(message["id"])] = message["result"]["content"][0]["text"].strip()
+
+failed = [id for id, sha in expected.items() if results.get(id) != sha]
+for id in failed[:10]:
+    print("FAIL: request %s returned %r, expected %s" % (id, results.get(id), expected[id]))
+if failed:
+    print("%d of %d commands did not round-trip" % (len(failed), len(expected)))
+    sys.exit(1)
+print("All %d commands round-tripped unchanged" % len(expected))
+EOF